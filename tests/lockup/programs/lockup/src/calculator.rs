@@ -1,11 +1,47 @@
 //! Utility functions for calculating unlock schedules for a vesting account.
 
-use crate::Vesting;
+use crate::{
+    ExtraVault, Frequency, NextUnlock, PeriodRounding, Rounding, ScheduleKind, Vesting,
+    VestingSchedule,
+};
 
+// Returns the amount the beneficiary can withdraw at the given ts.
+//
+// `outstanding` counts both the funds in the vault and those held by
+// whitelisted programs (`whitelist_owned`). Only the former can be withdrawn,
+// so the vested amount is capped at `outstanding - whitelist_owned`; loaned
+// funds become withdrawable again once returned via a whitelist deposit.
 pub fn available_for_withdrawal(vesting: &Vesting, current_ts: i64) -> u64 {
     std::cmp::min(outstanding_vested(vesting, current_ts), balance(vesting))
 }
 
+// Returns the amount the beneficiary can withdraw from an extra vault at the
+// given ts. The vault vests on the account's schedule as if it were the only
+// one, i.e., in proportion to its own deposit. Extra vaults are never loaned
+// to whitelisted programs or partially revoked.
+pub fn extra_vault_available(vesting: &Vesting, extra: &ExtraVault, current_ts: i64) -> u64 {
+    let mut schedule = vesting.clone();
+    schedule.start_balance = extra.start_balance;
+    schedule.outstanding = extra.outstanding;
+    schedule.whitelist_owned = 0;
+    schedule.partially_revoked = 0;
+    available_for_withdrawal(&schedule, current_ts)
+}
+
+// Returns true if the last withdrawal was too recent to withdraw again at
+// the given ts. A zero `min_withdraw_interval` never throttles.
+pub fn is_withdraw_throttled(vesting: &Vesting, current_ts: i64) -> bool {
+    current_ts.saturating_sub(vesting.last_withdraw_ts) < vesting.min_withdraw_interval
+}
+
+// The amount of outstanding tokens that have not yet vested.
+pub fn unvested(vesting: &Vesting, current_ts: i64) -> u64 {
+    vesting
+        .outstanding
+        .checked_sub(outstanding_vested(vesting, current_ts))
+        .unwrap()
+}
+
 // The amount of funds currently in the vault.
 fn balance(vesting: &Vesting) -> u64 {
     vesting
@@ -33,18 +69,67 @@ fn withdrawn_amount(vesting: &Vesting) -> u64 {
 // Returns the total vested amount up to the given ts, assuming zero
 // withdrawals and zero funds sent to other programs.
 fn total_vested(vesting: &Vesting, current_ts: i64) -> u64 {
-    if current_ts < vesting.start_ts {
+    if current_ts < vesting.start_ts || current_ts < vesting.cliff_ts {
         0
     } else if current_ts >= vesting.end_ts {
         vesting.start_balance
+    } else if vesting.schedule_kind == ScheduleKind::TimeLock {
+        0
+    } else if vesting.milestone_count > 0 {
+        milestone_unlock(vesting, current_ts)
+    } else if vesting.schedule_kind == ScheduleKind::FrontLoaded {
+        std::cmp::min(
+            front_loaded_unlock(vesting, current_ts).unwrap(),
+            vesting.start_balance,
+        )
     } else {
-        linear_unlock(vesting, current_ts).unwrap()
+        // Partial revocations end the schedule early.
+        std::cmp::min(
+            linear_unlock(vesting, current_ts).unwrap(),
+            vesting.start_balance,
+        )
     }
 }
 
-fn linear_unlock(vesting: &Vesting, current_ts: i64) -> Option<u64> {
+// The balance the schedule unlocks at the rate of, i.e., including any
+// partially revoked amount, which is taken off the end of the schedule.
+fn scheduled_balance(vesting: &Vesting) -> u64 {
+    vesting
+        .start_balance
+        .checked_add(vesting.partially_revoked)
+        .unwrap()
+}
+
+// Returns the cumulative amount of the last milestone reached by the given
+// ts. Capped at the starting balance, which revocation can reduce.
+fn milestone_unlock(vesting: &Vesting, current_ts: i64) -> u64 {
+    let unlocked = vesting
+        .milestones()
+        .iter()
+        .take_while(|m| m.unlock_ts <= current_ts)
+        .last()
+        .map(|m| m.cumulative_amount)
+        .unwrap_or(0);
+    std::cmp::min(unlocked, vesting.start_balance)
+}
+
+// The amount held back until the end of the schedule.
+fn final_amount(vesting: &Vesting) -> u64 {
+    (scheduled_balance(vesting) as u128 * vesting.final_bps as u128 / 10_000) as u64
+}
+
+// Parameters of the linear unlock, derived from the vesting account.
+// Excludes the final amount, which only unlocks at `end_ts`.
+struct LinearSchedule {
+    shifted_start_ts: u64,
+    period_secs: u64,
+    linear_balance: u64,
+    reward_per_period: u64,
+    reward_overflow: u64,
+}
+
+fn linear_schedule(vesting: &Vesting) -> Option<LinearSchedule> {
     // Signed division not supported.
-    let current_ts = current_ts as u64;
     let start_ts = vesting.start_ts as u64;
     let end_ts = vesting.end_ts as u64;
 
@@ -57,28 +142,606 @@ fn linear_unlock(vesting: &Vesting, current_ts: i64) -> Option<u64> {
         start_ts.checked_sub(end_ts.checked_sub(start_ts)? % vesting.period_count)?;
 
     // Similarly, if we can't perfectly divide up the vesting rewards
-    // then make the first period act as a cliff, earning slightly more than
-    // subsequent periods.
-    let reward_overflow = vesting.start_balance % vesting.period_count;
-
-    // Reward per period ignoring the overflow.
-    let reward_per_period =
-        (vesting.start_balance.checked_sub(reward_overflow)?).checked_div(vesting.period_count)?;
-
-    // Number of vesting periods that have passed.
-    let current_period = {
-        let period_secs =
-            (end_ts.checked_sub(shifted_start_ts)?).checked_div(vesting.period_count)?;
-        let current_period_count =
-            (current_ts.checked_sub(shifted_start_ts)?).checked_div(period_secs)?;
-        std::cmp::min(current_period_count, vesting.period_count)
-    };
+    // then one period earns slightly more than the others. By default, the
+    // first period acts as a cliff, unlocking the overflow. Rounding the
+    // reward per period up instead leaves no overflow, and the final period
+    // unlocks whatever is left.
+    let linear_balance = scheduled_balance(vesting).checked_sub(final_amount(vesting))?;
+    let reward_per_period = vesting
+        .rounding
+        .div(linear_balance as u128, vesting.period_count as u128)
+        as u64;
+    // Rounding up can take the periods' total past the balance, and so past
+    // `u64::MAX`, hence the u128.
+    let reward_overflow = (linear_balance as u128)
+        .saturating_sub(reward_per_period as u128 * vesting.period_count as u128)
+        as u64;
+
+    let period_secs = (end_ts.checked_sub(shifted_start_ts)?).checked_div(vesting.period_count)?;
+
+    Some(LinearSchedule {
+        shifted_start_ts,
+        period_secs,
+        linear_balance,
+        reward_per_period,
+        reward_overflow,
+    })
+}
+
+// Number of vesting periods that have passed.
+fn elapsed_periods(vesting: &Vesting, schedule: &LinearSchedule, current_ts: i64) -> Option<u64> {
+    let current_period_count = ((current_ts as u64).checked_sub(schedule.shifted_start_ts)?)
+        .checked_div(schedule.period_secs)?;
+    Some(std::cmp::min(current_period_count, vesting.period_count))
+}
+
+fn linear_unlock(vesting: &Vesting, current_ts: i64) -> Option<u64> {
+    let schedule = linear_schedule(vesting)?;
+    let current_period = elapsed_periods(vesting, &schedule, current_ts)?;
 
     if current_period == 0 {
         return Some(0);
     }
 
-    current_period
-        .checked_mul(reward_per_period)?
-        .checked_add(reward_overflow)
+    // In u128 for the same reason as the overflow, capped below.
+    let unlocked = current_period as u128 * schedule.reward_per_period as u128;
+    let unlocked = match vesting.period_rounding {
+        PeriodRounding::FirstPeriod => unlocked + schedule.reward_overflow as u128,
+        PeriodRounding::FinalPeriod if current_period == vesting.period_count => {
+            unlocked + schedule.reward_overflow as u128
+        }
+        PeriodRounding::FinalPeriod => unlocked,
+    };
+    Some(std::cmp::min(unlocked, schedule.linear_balance as u128) as u64)
+}
+
+// Fixed point scale of the fraction a front-loaded schedule has left to vest.
+const CURVE_SCALE: u128 = 1_000_000_000_000;
+
+// After `p` of `n` periods, a front-loaded schedule has vested
+// `1 - (1 - p / n)^k` of the balance, for the curve exponent `k`. The
+// fraction left, `((n - p) / n)^k`, is computed in fixed point one factor
+// at a time, so no intermediate value exceeds `CURVE_SCALE * n`, and the
+// balance times `CURVE_SCALE` fits comfortably in a u128.
+fn front_loaded_unlock(vesting: &Vesting, current_ts: i64) -> Option<u64> {
+    let schedule = linear_schedule(vesting)?;
+    let current_period = elapsed_periods(vesting, &schedule, current_ts)?;
+    let remaining_periods = vesting.period_count.checked_sub(current_period)? as u128;
+
+    let mut unvested_fraction = CURVE_SCALE;
+    for _ in 0..vesting.curve_exponent {
+        unvested_fraction = unvested_fraction
+            .checked_mul(remaining_periods)?
+            .checked_div(vesting.period_count as u128)?;
+    }
+    let vested = vesting.rounding.div(
+        (schedule.linear_balance as u128).checked_mul(CURVE_SCALE - unvested_fraction)?,
+        CURVE_SCALE,
+    );
+    u64::try_from(vested).ok()
+}
+
+// Returns the time at which tokens next unlock, or the end of the schedule
+// if everything has already vested.
+pub fn next_unlock_ts(vesting: &Vesting, current_ts: i64) -> i64 {
+    if current_ts >= vesting.end_ts || vesting.schedule_kind == ScheduleKind::TimeLock {
+        return vesting.end_ts;
+    }
+    if vesting.milestone_count > 0 {
+        let next_milestone_ts = vesting
+            .milestones()
+            .iter()
+            .map(|m| m.unlock_ts)
+            .find(|ts| *ts > current_ts)
+            .unwrap_or(vesting.end_ts);
+        return std::cmp::max(next_milestone_ts, vesting.cliff_ts);
+    }
+    let next_period_ts = linear_schedule(vesting)
+        .and_then(|schedule| {
+            let elapsed = (current_ts as u64).saturating_sub(schedule.shifted_start_ts);
+            let next_period = elapsed.checked_div(schedule.period_secs)?.checked_add(1)?;
+            next_period
+                .checked_mul(schedule.period_secs)?
+                .checked_add(schedule.shifted_start_ts)
+        })
+        .map(|ts| std::cmp::min(ts as i64, vesting.end_ts))
+        .unwrap_or(vesting.end_ts);
+    // Anything unlocking during the cliff is released at the cliff.
+    std::cmp::max(next_period_ts, vesting.cliff_ts)
+}
+
+// Returns the total amount vested at the given ts, past or future, ignoring
+// withdrawals and funds sent to other programs.
+pub fn vested_at(vesting: &Vesting, ts: i64) -> u64 {
+    std::cmp::min(total_vested(vesting, ts), vesting.start_balance)
+}
+
+// Returns the fraction of the starting balance vested at the given ts, in
+// basis points.
+pub fn vested_bps(vesting: &Vesting, current_ts: i64) -> u64 {
+    if current_ts >= vesting.end_ts {
+        return 10_000;
+    }
+    if current_ts <= vesting.start_ts || vesting.start_balance == 0 {
+        return 0;
+    }
+    (total_vested(vesting, current_ts) as u128 * 10_000 / vesting.start_balance as u128) as u64
+}
+
+// Returns the amount vested after `since_ts`, up to `current_ts`.
+pub fn unlocked_since(vesting: &Vesting, since_ts: i64, current_ts: i64) -> u64 {
+    vested_at(vesting, current_ts).saturating_sub(vested_at(vesting, since_ts))
+}
+
+// Returns the seconds left until everything has vested, or zero if it
+// already has.
+pub fn time_to_full_vest(vesting: &Vesting, current_ts: i64) -> u64 {
+    vesting.end_ts.saturating_sub(current_ts).max(0) as u64
+}
+
+// Returns the amount that unlocks at `next_unlock_ts`, or zero if everything
+// has already vested.
+pub fn next_unlock_amount(vesting: &Vesting, current_ts: i64) -> u64 {
+    if current_ts >= vesting.end_ts {
+        return 0;
+    }
+    total_vested(vesting, next_unlock_ts(vesting, current_ts))
+        .saturating_sub(total_vested(vesting, current_ts))
+}
+
+// Returns the next unlock as of the given ts.
+pub fn next_unlock(vesting: &Vesting, current_ts: i64) -> NextUnlock {
+    NextUnlock {
+        ts: next_unlock_ts(vesting, current_ts),
+        amount: next_unlock_amount(vesting, current_ts),
+    }
+}
+
+// Returns a breakdown of the vesting schedule as of the given ts.
+pub fn schedule(vesting: &Vesting, current_ts: i64) -> VestingSchedule {
+    VestingSchedule {
+        start_ts: vesting.start_ts,
+        end_ts: vesting.end_ts,
+        period_count: vesting.period_count,
+        period_amount: linear_schedule(vesting)
+            .filter(|_| {
+                vesting.milestone_count == 0 && vesting.schedule_kind == ScheduleKind::Linear
+            })
+            .map(|schedule| schedule.reward_per_period)
+            .unwrap_or(0),
+        next_unlock_ts: next_unlock_ts(vesting, current_ts),
+    }
+}
+
+// Returns the number of periods of the given frequency between the two
+// timestamps, rounded to the nearest period so that, e.g., 4 years of 365
+// days is 48 months. Never less than one.
+pub fn periods_for(start_ts: i64, end_ts: i64, frequency: Frequency) -> u64 {
+    let duration = end_ts.saturating_sub(start_ts).max(0) as u64;
+    let period_secs = frequency.seconds();
+    let periods = (duration + period_secs / 2) / period_secs;
+    std::cmp::max(periods, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Milestone, Realizor, WhitelistLoan, MAX_CURVE_EXPONENT, MAX_DESTINATION_ALLOWLIST,
+        MAX_EXTRA_VAULTS, MAX_MILESTONES, MAX_PROGRAM_ALLOWLIST, MAX_RELAY_INITIATORS,
+        MAX_WHITELIST_LOANS,
+    };
+    use anchor_lang::prelude::Pubkey;
+
+    fn vesting(start_balance: u64, period_count: u64, period_rounding: PeriodRounding) -> Vesting {
+        Vesting {
+            beneficiary: Pubkey::default(),
+            mint: Pubkey::default(),
+            vault: Pubkey::default(),
+            grantor: Pubkey::default(),
+            outstanding: start_balance,
+            start_balance,
+            created_ts: 0,
+            start_ts: 1_000,
+            end_ts: 1_000 + 60 * period_count as i64,
+            cliff_ts: 1_000,
+            period_count,
+            whitelist_owned: 0,
+            nonce: 0,
+            realizor: None::<Realizor>,
+            revocable: false,
+            whitelist_loans: [WhitelistLoan::default(); MAX_WHITELIST_LOANS],
+            period_rounding,
+            frozen: false,
+            in_relay: false,
+            min_withdraw_interval: 0,
+            last_withdraw_ts: 0,
+            co_beneficiary: None,
+            accelerable: false,
+            withdraw_delegate: None,
+            memo: [0; 64],
+            milestone_count: 0,
+            milestones: [Milestone::default(); MAX_MILESTONES],
+            program_allowlist: [Pubkey::default(); MAX_PROGRAM_ALLOWLIST],
+            version: Vesting::VERSION,
+            final_bps: 0,
+            revoke_notice_seconds: 0,
+            revoke_effective_ts: 0,
+            requires_grantor_approval: false,
+            relay_initiators: [Pubkey::default(); MAX_RELAY_INITIATORS],
+            immutable: false,
+            partially_revoked: 0,
+            schedule_kind: ScheduleKind::Linear,
+            withdraw_callback_program: None,
+            recovery_authority: None,
+            relay_deadline: None,
+            loan_due_ts: 0,
+            delinquent: false,
+            extra_vaults: [ExtraVault::default(); MAX_EXTRA_VAULTS],
+            extra_vault_count: 0,
+            rounding: Rounding::Down,
+            destination_allowlist: [Pubkey::default(); MAX_DESTINATION_ALLOWLIST],
+            pending_destination_allowlist: [Pubkey::default(); MAX_DESTINATION_ALLOWLIST],
+            destination_allowlist_effective_ts: 0,
+            last_poke_ts: 0,
+            curve_exponent: 0,
+            withdraw_count: 0,
+        }
+    }
+
+    #[test]
+    fn whitelist_owned_is_not_withdrawable() {
+        let mut v = vesting(100, 2, PeriodRounding::FirstPeriod);
+        v.whitelist_owned = 70;
+        assert_eq!(available_for_withdrawal(&v, v.start_ts + 60), 30);
+        assert_eq!(available_for_withdrawal(&v, v.end_ts), 30);
+
+        // Once returned, loaned funds are withdrawable again.
+        v.whitelist_owned = 0;
+        assert_eq!(available_for_withdrawal(&v, v.end_ts), 100);
+    }
+
+    #[test]
+    fn time_lock_unlocks_everything_at_the_end() {
+        let mut v = vesting(100, 4, PeriodRounding::FirstPeriod);
+        v.schedule_kind = ScheduleKind::TimeLock;
+        assert_eq!(available_for_withdrawal(&v, v.start_ts + 60), 0);
+        assert_eq!(available_for_withdrawal(&v, v.end_ts - 1), 0);
+        assert_eq!(next_unlock(&v, v.start_ts).ts, v.end_ts);
+        assert_eq!(next_unlock(&v, v.start_ts).amount, 100);
+        assert_eq!(available_for_withdrawal(&v, v.end_ts), 100);
+    }
+
+    #[test]
+    fn extra_vaults_vest_on_their_own_balance() {
+        let mut v = vesting(100, 4, PeriodRounding::FirstPeriod);
+        v.whitelist_owned = 100;
+        let mut extra = ExtraVault {
+            start_balance: 1_000,
+            outstanding: 1_000,
+            ..ExtraVault::default()
+        };
+        assert_eq!(extra_vault_available(&v, &extra, v.start_ts + 60), 250);
+        assert_eq!(extra_vault_available(&v, &extra, v.start_ts + 120), 500);
+
+        // Past withdrawals come off what's vested.
+        extra.outstanding = 800;
+        assert_eq!(extra_vault_available(&v, &extra, v.start_ts + 120), 300);
+        assert_eq!(extra_vault_available(&v, &extra, v.end_ts), 800);
+    }
+
+    #[test]
+    fn rounding_decides_who_bears_the_dust() {
+        // 9 tokens over 4 periods is 2.25 per period.
+        let cumulative = |rounding, period_rounding| {
+            let mut v = vesting(9, 4, period_rounding);
+            v.rounding = rounding;
+            (1..=4)
+                .map(|period| total_vested(&v, v.start_ts + 60 * period))
+                .collect::<Vec<_>>()
+        };
+        use PeriodRounding::{FinalPeriod, FirstPeriod};
+        assert_eq!(cumulative(Rounding::Down, FirstPeriod), [3, 5, 7, 9]);
+        assert_eq!(cumulative(Rounding::Down, FinalPeriod), [2, 4, 6, 9]);
+        assert_eq!(cumulative(Rounding::Up, FirstPeriod), [3, 6, 9, 9]);
+        assert_eq!(cumulative(Rounding::Up, FinalPeriod), [3, 6, 9, 9]);
+        assert_eq!(cumulative(Rounding::Nearest, FirstPeriod), [3, 5, 7, 9]);
+
+        // 10 tokens is 2.5 per period, which rounds to the nearest up.
+        let mut v = vesting(10, 4, FinalPeriod);
+        v.rounding = Rounding::Nearest;
+        assert_eq!(total_vested(&v, v.start_ts + 60), 3);
+        assert_eq!(total_vested(&v, v.start_ts + 180), 9);
+        assert_eq!(total_vested(&v, v.end_ts), 10);
+    }
+
+    #[test]
+    fn rounding_handles_balances_near_u64_max() {
+        use PeriodRounding::{FinalPeriod, FirstPeriod};
+        for rounding in [Rounding::Down, Rounding::Up, Rounding::Nearest] {
+            for (balance, period_count) in [(u64::MAX - 1, 3), (u64::MAX, 2)] {
+                for period_rounding in [FirstPeriod, FinalPeriod] {
+                    let mut v = vesting(balance, period_count, period_rounding);
+                    v.rounding = rounding;
+                    let vested = (1..=period_count)
+                        .map(|period| total_vested(&v, v.start_ts + 60 * period as i64))
+                        .collect::<Vec<_>>();
+                    assert!(vested.windows(2).all(|w| w[0] <= w[1]));
+                    assert_eq!(vested.last(), Some(&balance));
+                    assert_eq!(total_vested(&v, v.end_ts), balance);
+                }
+            }
+        }
+
+        // Rounding up gives the first period the larger half.
+        let mut v = vesting(u64::MAX, 2, FinalPeriod);
+        v.rounding = Rounding::Up;
+        assert_eq!(total_vested(&v, v.start_ts + 60), 1 << 63);
+        v.rounding = Rounding::Down;
+        assert_eq!(total_vested(&v, v.start_ts + 60), (1 << 63) - 1);
+    }
+
+    #[test]
+    fn unlocked_since_counts_only_new_periods() {
+        let v = vesting(100, 4, PeriodRounding::FirstPeriod);
+        assert_eq!(unlocked_since(&v, 0, v.start_ts), 0);
+        assert_eq!(unlocked_since(&v, 0, v.start_ts + 60), 25);
+        // Within the same period nothing more has vested.
+        assert_eq!(unlocked_since(&v, v.start_ts + 60, v.start_ts + 119), 0);
+        assert_eq!(unlocked_since(&v, v.start_ts + 60, v.start_ts + 180), 50);
+        assert_eq!(unlocked_since(&v, v.end_ts, i64::MAX), 0);
+        // A clock behind the last poke unlocks nothing.
+        assert_eq!(unlocked_since(&v, v.end_ts, v.start_ts), 0);
+    }
+
+    #[test]
+    fn time_to_full_vest_clamps_at_zero() {
+        let v = vesting(100, 4, PeriodRounding::FirstPeriod);
+        assert_eq!(time_to_full_vest(&v, 0), 1_240);
+        assert_eq!(time_to_full_vest(&v, v.start_ts), 240);
+        assert_eq!(time_to_full_vest(&v, v.end_ts), 0);
+        assert_eq!(time_to_full_vest(&v, i64::MAX), 0);
+        assert_eq!(time_to_full_vest(&v, i64::MIN), i64::MAX as u64);
+    }
+
+    #[test]
+    fn remainder_is_never_stranded() {
+        for rounding in [PeriodRounding::FirstPeriod, PeriodRounding::FinalPeriod] {
+            for (start_balance, period_count) in [(100, 3), (7, 7), (1_000_003, 12), (5, 4)] {
+                let v = vesting(start_balance, period_count, rounding);
+                let mut previous = 0;
+                for period in 1..=period_count {
+                    let vested = total_vested(&v, v.start_ts + 60 * period as i64);
+                    assert!(vested >= previous);
+                    previous = vested;
+                }
+                assert_eq!(previous, start_balance);
+                assert_eq!(available_for_withdrawal(&v, v.end_ts), start_balance);
+            }
+        }
+    }
+
+    #[test]
+    fn remainder_unlocks_with_configured_period() {
+        let first = vesting(100, 3, PeriodRounding::FirstPeriod);
+        assert_eq!(total_vested(&first, first.start_ts + 60), 34);
+        assert_eq!(total_vested(&first, first.start_ts + 120), 67);
+
+        let last = vesting(100, 3, PeriodRounding::FinalPeriod);
+        assert_eq!(total_vested(&last, last.start_ts + 60), 33);
+        assert_eq!(total_vested(&last, last.start_ts + 120), 66);
+        assert_eq!(total_vested(&last, last.end_ts), 100);
+    }
+
+    #[test]
+    fn final_period_carries_the_whole_remainder() {
+        // 1_000_003 over 12 periods is 83_333 each, with 7 left over.
+        let v = vesting(1_000_003, 12, PeriodRounding::FinalPeriod);
+        let mut previous = 0;
+        for period in 1..=12 {
+            let vested = total_vested(&v, v.start_ts + 60 * period);
+            let unlocked = vested - previous;
+            if period < 12 {
+                assert_eq!(unlocked, 83_333);
+            } else {
+                assert_eq!(unlocked, 83_340);
+            }
+            previous = vested;
+        }
+        assert_eq!(previous, 1_000_003);
+    }
+
+    #[test]
+    fn front_loaded_schedule_follows_its_curve() {
+        let curve = |balance, period_count, exponent| {
+            let mut v = vesting(balance, period_count, PeriodRounding::FirstPeriod);
+            v.schedule_kind = ScheduleKind::FrontLoaded;
+            v.curve_exponent = exponent;
+            (1..=period_count)
+                .map(|period| total_vested(&v, v.start_ts + 60 * period as i64))
+                .collect::<Vec<_>>()
+        };
+        // 1 - (3/4)^2, 1 - (1/2)^2 and 1 - (1/4)^2, rounded down.
+        assert_eq!(curve(100, 4, 2), [43, 75, 93, 100]);
+        // 1 - (9/10)^3 = 0.271, 1 - (1/2)^3 = 0.875.
+        let cubic = curve(1_000, 10, 3);
+        assert_eq!(cubic[0], 271);
+        assert_eq!(cubic[4], 875);
+        assert_eq!(cubic[9], 1_000);
+        // Always ahead of the linear schedule until the end.
+        let linear = |period: u64| 1_000 * period / 10;
+        for (period, vested) in (1..10).zip(&cubic) {
+            assert!(*vested > linear(period));
+        }
+    }
+
+    #[test]
+    fn front_loaded_schedule_does_not_overflow() {
+        let mut v = vesting(u64::MAX, 1_000_000, PeriodRounding::FirstPeriod);
+        v.schedule_kind = ScheduleKind::FrontLoaded;
+        v.curve_exponent = MAX_CURVE_EXPONENT;
+        let mut previous = 0;
+        for period in [1, 10, 1_000, 500_000, 999_999] {
+            let vested = total_vested(&v, v.start_ts + 60 * period);
+            assert!(vested > previous);
+            previous = vested;
+        }
+        assert_eq!(total_vested(&v, v.end_ts), u64::MAX);
+
+        let rounded_down = total_vested(&v, v.start_ts + 60);
+        v.rounding = Rounding::Up;
+        assert_eq!(total_vested(&v, v.start_ts + 60), rounded_down + 1);
+        assert_eq!(total_vested(&v, v.start_ts), 0);
+    }
+
+    #[test]
+    fn withdrawals_are_throttled_until_interval_passes() {
+        let mut v = vesting(100, 2, PeriodRounding::FirstPeriod);
+        assert!(!is_withdraw_throttled(&v, v.start_ts));
+
+        v.min_withdraw_interval = 60;
+        v.last_withdraw_ts = v.start_ts;
+        assert!(is_withdraw_throttled(&v, v.start_ts));
+        assert!(is_withdraw_throttled(&v, v.start_ts + 59));
+        assert!(!is_withdraw_throttled(&v, v.start_ts + 60));
+    }
+
+    #[test]
+    fn nothing_vests_before_a_future_start() {
+        // Created a month before the schedule starts.
+        let mut v = vesting(1_200, 12, PeriodRounding::FirstPeriod);
+        v.created_ts = v.start_ts - 2_629_800;
+        assert_eq!(available_for_withdrawal(&v, v.created_ts), 0);
+        assert_eq!(available_for_withdrawal(&v, v.start_ts - 1), 0);
+        assert_eq!(unvested(&v, v.start_ts - 1), 1_200);
+        assert_eq!(available_for_withdrawal(&v, v.start_ts + 60), 100);
+    }
+
+    #[test]
+    fn vested_at_projects_the_schedule() {
+        let mut v = vesting(100, 4, PeriodRounding::FirstPeriod);
+        // Withdrawals don't change the projection.
+        v.outstanding = 40;
+        assert_eq!(vested_at(&v, i64::MIN), 0);
+        assert_eq!(vested_at(&v, v.start_ts), 0);
+        assert_eq!(vested_at(&v, v.start_ts + 120), 50);
+        assert_eq!(vested_at(&v, v.end_ts), 100);
+        assert_eq!(vested_at(&v, i64::MAX), 100);
+    }
+
+    #[test]
+    fn partial_revocation_ends_the_schedule_early() {
+        let mut v = vesting(100, 4, PeriodRounding::FirstPeriod);
+        // A quarter revoked after the first period.
+        v.start_balance = 75;
+        v.outstanding = 75;
+        v.partially_revoked = 25;
+        assert_eq!(total_vested(&v, v.start_ts + 60), 25);
+        assert_eq!(total_vested(&v, v.start_ts + 120), 50);
+        assert_eq!(total_vested(&v, v.start_ts + 180), 75);
+        assert_eq!(total_vested(&v, v.end_ts - 1), 75);
+        assert_eq!(available_for_withdrawal(&v, v.end_ts), 75);
+    }
+
+    #[test]
+    fn vested_bps_spans_the_schedule() {
+        let v = vesting(100, 4, PeriodRounding::FirstPeriod);
+        assert_eq!(vested_bps(&v, 0), 0);
+        assert_eq!(vested_bps(&v, v.start_ts), 0);
+        assert_eq!(vested_bps(&v, v.start_ts + 60), 2_500);
+        assert_eq!(vested_bps(&v, v.start_ts + 150), 5_000);
+        assert_eq!(vested_bps(&v, v.end_ts), 10_000);
+        assert_eq!(vested_bps(&v, v.end_ts + 1), 10_000);
+
+        // Rounds down.
+        let v = vesting(3, 3, PeriodRounding::FirstPeriod);
+        assert_eq!(vested_bps(&v, v.start_ts + 60), 3_333);
+    }
+
+    #[test]
+    fn next_unlock_follows_period_boundaries() {
+        let at = |v: &Vesting, ts| {
+            let next = next_unlock(v, ts);
+            (next.ts, next.amount)
+        };
+
+        let v = vesting(100, 4, PeriodRounding::FirstPeriod);
+        assert_eq!(at(&v, 0), (1_060, 25));
+        assert_eq!(at(&v, 1_000), (1_060, 25));
+        assert_eq!(at(&v, 1_060), (1_120, 25));
+        assert_eq!(at(&v, 1_239), (1_240, 25));
+        assert_eq!(at(&v, 1_240), (1_240, 0));
+        assert_eq!(at(&v, 2_000), (1_240, 0));
+
+        // The remainder unlocks with the configured period.
+        let first = vesting(100, 3, PeriodRounding::FirstPeriod);
+        assert_eq!(next_unlock(&first, 1_000).amount, 34);
+        assert_eq!(next_unlock(&first, 1_120).amount, 33);
+        let last = vesting(100, 3, PeriodRounding::FinalPeriod);
+        assert_eq!(next_unlock(&last, 1_000).amount, 33);
+        assert_eq!(next_unlock(&last, 1_120).amount, 34);
+
+        // Everything unlocking during the cliff is released at the cliff.
+        let mut cliff = vesting(100, 4, PeriodRounding::FirstPeriod);
+        cliff.cliff_ts = 1_130;
+        assert_eq!(at(&cliff, 1_000), (1_130, 50));
+        assert_eq!(at(&cliff, 1_130), (1_180, 25));
+    }
+
+    #[test]
+    fn milestones_unlock_in_steps() {
+        let mut v = vesting(100, 1, PeriodRounding::FirstPeriod);
+        v.end_ts = v.start_ts + 240;
+        let steps = [
+            (v.start_ts + 60, 10),
+            (v.start_ts + 120, 50),
+            (v.end_ts, 100),
+        ];
+        for (i, (unlock_ts, cumulative_amount)) in steps.into_iter().enumerate() {
+            v.milestones[i] = Milestone {
+                unlock_ts,
+                cumulative_amount,
+            };
+        }
+        v.milestone_count = steps.len() as u8;
+
+        assert_eq!(available_for_withdrawal(&v, v.start_ts + 59), 0);
+        assert_eq!(available_for_withdrawal(&v, v.start_ts + 60), 10);
+        assert_eq!(available_for_withdrawal(&v, v.start_ts + 119), 10);
+        assert_eq!(available_for_withdrawal(&v, v.start_ts + 120), 50);
+        assert_eq!(available_for_withdrawal(&v, v.end_ts), 100);
+        assert_eq!(next_unlock_ts(&v, v.start_ts + 60), v.start_ts + 120);
+        assert_eq!(next_unlock_amount(&v, v.start_ts + 60), 40);
+    }
+
+    #[test]
+    fn periods_for_rounds_to_the_nearest_period() {
+        const DAY: i64 = 24 * 60 * 60;
+        assert_eq!(periods_for(0, 4 * 365 * DAY, Frequency::Monthly), 48);
+        assert_eq!(periods_for(0, 4 * 365 * DAY + DAY, Frequency::Monthly), 48);
+        assert_eq!(periods_for(0, 52 * 7 * DAY, Frequency::Weekly), 52);
+        assert_eq!(periods_for(0, 30 * DAY, Frequency::Daily), 30);
+        assert_eq!(periods_for(0, 1, Frequency::Daily), 1);
+    }
+
+    #[test]
+    fn final_bps_unlocks_at_the_end() {
+        // 1000 tokens over 4 periods, a quarter of which unlocks at the end.
+        // The remaining 750 vest linearly, 187 per period plus 2 left over in
+        // the first.
+        let mut v = vesting(1_000, 4, PeriodRounding::FirstPeriod);
+        v.final_bps = 2_500;
+        let model = [
+            (0, 0),
+            (59, 0),
+            (60, 189),
+            (120, 376),
+            (180, 563),
+            (239, 563),
+            (240, 1_000),
+        ];
+        for (elapsed, expected) in model {
+            assert_eq!(total_vested(&v, v.start_ts + elapsed), expected);
+        }
+    }
 }
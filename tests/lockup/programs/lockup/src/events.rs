@@ -0,0 +1,167 @@
+//! Events emitted by the lockup program so that indexers can track vesting
+//! accounts without parsing logs.
+
+use crate::WhitelistEntry;
+use anchor_lang::prelude::*;
+
+#[event]
+pub struct VestingCreated {
+    pub vesting: Pubkey,
+    pub beneficiary: Pubkey,
+    pub deposit_amount: u64,
+    pub end_ts: i64,
+}
+
+#[event]
+pub struct VestingWithdrawn {
+    pub vesting: Pubkey,
+    pub beneficiary: Pubkey,
+    pub amount: u64,
+    pub remaining: u64,
+}
+
+#[event]
+pub struct VestingAccelerated {
+    pub vesting: Pubkey,
+    pub end_ts: i64,
+}
+
+#[event]
+pub struct RevocationScheduled {
+    pub vesting: Pubkey,
+    pub effective_ts: i64,
+}
+
+#[event]
+pub struct VestingRevoked {
+    pub vesting: Pubkey,
+    /// The unvested amount returned to the grantor.
+    pub amount: u64,
+}
+
+#[event]
+pub struct VestingPartiallyRevoked {
+    pub vesting: Pubkey,
+    /// The amount returned to the grantor.
+    pub amount: u64,
+    pub start_balance: u64,
+    pub outstanding: u64,
+}
+
+#[event]
+pub struct ReconcileEvent {
+    pub vesting: Pubkey,
+    pub outstanding: u64,
+    /// The funds actually backing the vesting account.
+    pub actual: u64,
+    /// `actual - outstanding`.
+    pub delta: i64,
+    pub corrected: bool,
+}
+
+#[event]
+pub struct WhitelistAdded {
+    pub authority: Pubkey,
+    pub entry: WhitelistEntry,
+}
+
+#[event]
+pub struct WhitelistDeleted {
+    pub authority: Pubkey,
+    pub entry: WhitelistEntry,
+}
+
+#[event]
+pub struct AuthorityChanged {
+    pub authority: Pubkey,
+    pub new_authority: Pubkey,
+}
+
+#[event]
+pub struct RelayExecuted {
+    pub vesting: Pubkey,
+    pub program: Pubkey,
+    /// The first 8 bytes of the relayed instruction data, zero padded.
+    pub discriminator: [u8; 8],
+    pub vault_before: u64,
+    pub vault_after: u64,
+    /// `vault_after - vault_before`.
+    pub delta: i64,
+}
+
+#[event]
+pub struct WhitelistBalanceChanged {
+    pub vesting: Pubkey,
+    pub program: Pubkey,
+    pub whitelist_owned: u64,
+}
+
+#[event]
+pub struct VestingSplit {
+    pub vesting: Pubkey,
+    pub new_vesting: Pubkey,
+    pub new_beneficiary: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct VestingRecovered {
+    pub vesting: Pubkey,
+    pub recovery_authority: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct RelayLoaned {
+    pub vesting: Pubkey,
+    pub program: Pubkey,
+    pub amount: u64,
+    /// When all loaned funds are due back.
+    pub due_ts: i64,
+}
+
+#[event]
+pub struct RelayDeadlineBreached {
+    pub vesting: Pubkey,
+    pub whitelist_owned: u64,
+    pub due_ts: i64,
+}
+
+#[event]
+pub struct VaultAdded {
+    pub vesting: Pubkey,
+    pub vault: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct VaultWithdrawn {
+    pub vesting: Pubkey,
+    pub vault: Pubkey,
+    pub amount: u64,
+    pub remaining: u64,
+}
+
+#[event]
+pub struct DestinationAllowlistProposed {
+    pub vesting: Pubkey,
+    pub effective_ts: i64,
+}
+
+#[event]
+pub struct VestingSignerRotated {
+    pub vesting: Pubkey,
+    pub vesting_signer: Pubkey,
+    pub nonce: u8,
+}
+
+#[event]
+pub struct PeriodUnlocked {
+    pub vesting: Pubkey,
+    /// The amount vested since the last poke.
+    pub amount: u64,
+    pub available: u64,
+    pub ts: i64,
+}
@@ -5,12 +5,58 @@ use anchor_lang::accounts::state::ProgramState;
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program;
 use anchor_lang::solana_program::instruction::Instruction;
-use anchor_spl::token::{self, TokenAccount, Transfer};
+use anchor_lang::system_program;
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use anchor_spl::token;
+use anchor_spl::token_interface::spl_token_2022::instruction::AuthorityType;
+use anchor_spl::token_interface::{
+    self, CloseAccount, Mint, SetAuthority, TokenAccount, TransferChecked,
+};
+use events::*;
 
 mod calculator;
+mod events;
 
 declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 
+/// Number of whitelisted programs a single vesting account can send funds to
+/// at the same time.
+pub const MAX_WHITELIST_LOANS: usize = 4;
+
+/// Maximum number of milestones in a step vesting schedule.
+pub const MAX_MILESTONES: usize = 8;
+
+/// Maximum number of programs a single vesting account can be restricted to.
+pub const MAX_PROGRAM_ALLOWLIST: usize = 4;
+
+/// Maximum number of keys, besides the beneficiary, that can initiate
+/// whitelist relays for a single vesting account.
+pub const MAX_RELAY_INITIATORS: usize = 4;
+
+/// Maximum number of whitelist entries returned by a single
+/// `get_whitelist_page`, so that the page fits in the 1024 bytes of return
+/// data.
+pub const MAX_WHITELIST_PAGE: u8 = 12;
+
+/// Maximum number of vaults, besides the primary one, a single vesting
+/// account can hold.
+pub const MAX_EXTRA_VAULTS: usize = 2;
+
+/// Maximum number of token accounts withdrawals from a single vesting account
+/// can be restricted to.
+pub const MAX_DESTINATION_ALLOWLIST: usize = 4;
+
+/// Seconds between a change to a destination allowlist being proposed and it
+/// taking effect, so that a compromised beneficiary key can't redirect
+/// withdrawals right away.
+pub const DESTINATION_ALLOWLIST_DELAY: i64 = 2 * 24 * 60 * 60;
+
+/// Largest curve exponent of a front-loaded schedule.
+pub const MAX_CURVE_EXPONENT: u8 = 8;
+
+/// Seed of the PDA tallying a grantor's grants, followed by the grantor.
+pub const GRANTOR_TALLY_SEED: &[u8] = b"grantor-tally";
+
 #[program]
 pub mod lockup {
     use super::*;
@@ -22,10 +68,51 @@ pub mod lockup {
         /// List of programs locked tokens can be sent to. These programs
         /// are completely trusted to maintain the locked property.
         pub whitelist: Vec<WhitelistEntry>,
+        /// The number of entries the state account has room for.
+        pub whitelist_capacity: u32,
+        /// The shortest cliff, in seconds from the start of the schedule,
+        /// that vesting accounts can be created with.
+        pub min_cliff_seconds: i64,
+        /// The most remaining accounts a whitelist relay can forward to the
+        /// whitelisted program.
+        pub max_relay_accounts: u32,
+        /// The sum of `outstanding` over all vesting accounts.
+        pub total_outstanding: u64,
+        /// The authority proposed by `propose_authority`, if not the default
+        /// pubkey. Only takes over once it accepts.
+        pub pending_authority: Pubkey,
+        /// True once the authority has been renounced. The whitelist can
+        /// never change again.
+        pub locked: bool,
+        /// The fraction of every withdrawal, in basis points, sent to the
+        /// `fee_treasury`. Zero charges nothing.
+        pub withdraw_fee_bps: u16,
+        /// Token account receiving withdrawal fees, or the default pubkey if
+        /// there is no fee.
+        pub fee_treasury: Pubkey,
+        /// If nonzero, the time withdrawals vest against instead of the
+        /// clock. Only honored with the `test-clock` feature.
+        pub test_clock: i64,
+        /// How withdrawal fees are rounded, and how new vesting accounts
+        /// round their per-period unlocks.
+        pub rounding: Rounding,
+        /// True while new vesting accounts can't be created. Existing
+        /// accounts are unaffected.
+        pub creation_paused: bool,
+        /// The smallest deposit that vesting accounts can be created with,
+        /// keeping negligible grants from bloating state.
+        pub min_deposit_amount: u64,
+        /// The longest schedule, in seconds from start to end, that vesting
+        /// accounts can be created with, catching, e.g., an `end_ts` given
+        /// in milliseconds. Zero is unbounded.
+        pub max_duration_seconds: u64,
     }
 
     impl Lockup {
         pub const WHITELIST_SIZE: usize = 10;
+        pub const MAX_WHITELIST_SIZE: usize = 64;
+        pub const MAX_RELAY_ACCOUNTS: u32 = 32;
+        pub const MAX_WITHDRAW_FEE_BPS: u16 = 500;
 
         pub fn new(ctx: Context<Auth>) -> Result<Self> {
             let mut whitelist = vec![];
@@ -33,18 +120,59 @@ pub mod lockup {
             Ok(Lockup {
                 authority: *ctx.accounts.authority.key,
                 whitelist,
+                whitelist_capacity: Self::WHITELIST_SIZE as u32,
+                min_cliff_seconds: 0,
+                max_relay_accounts: Self::MAX_RELAY_ACCOUNTS,
+                total_outstanding: 0,
+                pending_authority: Pubkey::default(),
+                locked: false,
+                withdraw_fee_bps: 0,
+                fee_treasury: Pubkey::default(),
+                test_clock: 0,
+                rounding: Rounding::Down,
+                creation_paused: false,
+                min_deposit_amount: 0,
+                max_duration_seconds: 0,
             })
         }
 
         #[access_control(whitelist_auth(self, &ctx))]
         pub fn whitelist_add(&mut self, ctx: Context<Auth>, entry: WhitelistEntry) -> Result<()> {
-            if self.whitelist.len() == Self::WHITELIST_SIZE {
+            if self.whitelist.len() >= self.whitelist_capacity as usize {
                 return err!(ErrorCode::WhitelistFull);
             }
             if self.whitelist.contains(&entry) {
                 return err!(ErrorCode::WhitelistEntryAlreadyExists);
             }
             self.whitelist.push(entry);
+            emit!(WhitelistAdded {
+                authority: *ctx.accounts.authority.key,
+                entry,
+            });
+            Ok(())
+        }
+
+        // Adds several entries at once. The whole batch is rejected if any
+        // entry is already whitelisted or repeated within the batch.
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn whitelist_add_many(
+            &mut self,
+            ctx: Context<Auth>,
+            entries: Vec<WhitelistEntry>,
+        ) -> Result<()> {
+            if self.whitelist.len() + entries.len() > self.whitelist_capacity as usize {
+                return err!(ErrorCode::WhitelistFull);
+            }
+            for entry in entries {
+                if self.whitelist.contains(&entry) {
+                    return err!(ErrorCode::WhitelistEntryAlreadyExists);
+                }
+                self.whitelist.push(entry);
+                emit!(WhitelistAdded {
+                    authority: *ctx.accounts.authority.key,
+                    entry,
+                });
+            }
             Ok(())
         }
 
@@ -54,217 +182,2496 @@ pub mod lockup {
             ctx: Context<Auth>,
             entry: WhitelistEntry,
         ) -> Result<()> {
-            if !self.whitelist.contains(&entry) {
+            let entry = *self
+                .whitelist
+                .iter()
+                .find(|e| *e == &entry)
+                .ok_or(ErrorCode::WhitelistEntryNotFound)?;
+            self.whitelist.retain(|e| e != &entry);
+            emit!(WhitelistDeleted {
+                authority: *ctx.accounts.authority.key,
+                entry,
+            });
+            Ok(())
+        }
+
+        // Deletes several entries at once. The whole batch is rejected if any
+        // entry isn't whitelisted.
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn whitelist_delete_many(
+            &mut self,
+            ctx: Context<Auth>,
+            entries: Vec<WhitelistEntry>,
+        ) -> Result<()> {
+            if !entries.iter().all(|entry| self.whitelist.contains(entry)) {
                 return err!(ErrorCode::WhitelistEntryNotFound);
             }
-            self.whitelist.retain(|e| e != &entry);
+            self.whitelist.retain(|e| !entries.contains(e));
+            for entry in entries {
+                emit!(WhitelistDeleted {
+                    authority: *ctx.accounts.authority.key,
+                    entry,
+                });
+            }
+            Ok(())
+        }
+
+        // Pins the time withdrawals and views vest against, for
+        // deterministic tests. Zero goes back to the clock. Builds without
+        // the `test-clock` feature reject this, so production deployments
+        // always use the clock.
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn set_test_clock(&mut self, ctx: Context<Auth>, ts: i64) -> Result<()> {
+            if !cfg!(feature = "test-clock") {
+                return err!(ErrorCode::TestClockDisabled);
+            }
+            if ts < 0 {
+                return err!(ErrorCode::InvalidTimestamp);
+            }
+            self.test_clock = ts;
+            Ok(())
+        }
+
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn set_min_cliff(&mut self, ctx: Context<Auth>, min_cliff_seconds: i64) -> Result<()> {
+            if min_cliff_seconds < 0 {
+                return err!(ErrorCode::InvalidCliff);
+            }
+            self.min_cliff_seconds = min_cliff_seconds;
+            Ok(())
+        }
+
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn set_min_deposit(
+            &mut self,
+            ctx: Context<Auth>,
+            min_deposit_amount: u64,
+        ) -> Result<()> {
+            self.min_deposit_amount = min_deposit_amount;
+            Ok(())
+        }
+
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn set_max_duration(
+            &mut self,
+            ctx: Context<Auth>,
+            max_duration_seconds: u64,
+        ) -> Result<()> {
+            self.max_duration_seconds = max_duration_seconds;
+            Ok(())
+        }
+
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn set_max_relay_accounts(
+            &mut self,
+            ctx: Context<Auth>,
+            max_relay_accounts: u32,
+        ) -> Result<()> {
+            self.max_relay_accounts = max_relay_accounts;
+            Ok(())
+        }
+
+        // Charges a fee on withdrawals, capped at `MAX_WITHDRAW_FEE_BPS`. A
+        // zero fee turns it off.
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn set_withdraw_fee(
+            &mut self,
+            ctx: Context<Auth>,
+            withdraw_fee_bps: u16,
+            fee_treasury: Pubkey,
+        ) -> Result<()> {
+            if withdraw_fee_bps > Self::MAX_WITHDRAW_FEE_BPS
+                || (withdraw_fee_bps != 0 && fee_treasury == Pubkey::default())
+            {
+                return err!(ErrorCode::InvalidWithdrawFee);
+            }
+            self.withdraw_fee_bps = withdraw_fee_bps;
+            self.fee_treasury = fee_treasury;
+            Ok(())
+        }
+
+        // Sets the rounding direction. Withdrawal fees follow it right away,
+        // but vesting accounts keep the direction they were created with, so
+        // that what has vested never changes.
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn set_rounding(&mut self, ctx: Context<Auth>, rounding: Rounding) -> Result<()> {
+            self.rounding = rounding;
+            Ok(())
+        }
+
+        // Stops new grants, e.g., during an incident, while existing vesting
+        // accounts keep vesting and withdrawing as usual.
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn pause_creation(&mut self, ctx: Context<Auth>) -> Result<()> {
+            self.creation_paused = true;
+            Ok(())
+        }
+
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn unpause_creation(&mut self, ctx: Context<Auth>) -> Result<()> {
+            self.creation_paused = false;
             Ok(())
         }
 
         #[access_control(whitelist_auth(self, &ctx))]
         pub fn set_authority(&mut self, ctx: Context<Auth>, new_authority: Pubkey) -> Result<()> {
+            emit!(AuthorityChanged {
+                authority: self.authority,
+                new_authority,
+            });
             self.authority = new_authority;
+            self.pending_authority = Pubkey::default();
+            Ok(())
+        }
+
+        // Hands the authority over in two steps, so that a mistyped key can't
+        // take over the whitelist. Until accepted, the current authority stays
+        // in control and can cancel, or propose another key.
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn propose_authority(
+            &mut self,
+            ctx: Context<Auth>,
+            new_authority: Pubkey,
+        ) -> Result<()> {
+            self.pending_authority = new_authority;
+            Ok(())
+        }
+
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn cancel_authority_proposal(&mut self, ctx: Context<Auth>) -> Result<()> {
+            if self.pending_authority == Pubkey::default() {
+                return err!(ErrorCode::NoPendingAuthority);
+            }
+            self.pending_authority = Pubkey::default();
+            Ok(())
+        }
+
+        // Signed by the pending authority.
+        pub fn accept_authority(&mut self, ctx: Context<Auth>) -> Result<()> {
+            if self.pending_authority == Pubkey::default() {
+                return err!(ErrorCode::NoPendingAuthority);
+            }
+            if &self.pending_authority != ctx.accounts.authority.key {
+                return err!(ErrorCode::PendingAuthorityMismatch);
+            }
+            emit!(AuthorityChanged {
+                authority: self.authority,
+                new_authority: self.pending_authority,
+            });
+            self.authority = self.pending_authority;
+            self.pending_authority = Pubkey::default();
+            Ok(())
+        }
+
+        // Permanently gives up control of the whitelist, so that users can
+        // rely on it never changing. There is no way back.
+        #[access_control(whitelist_auth(self, &ctx))]
+        pub fn renounce_authority(&mut self, ctx: Context<Auth>) -> Result<()> {
+            emit!(AuthorityChanged {
+                authority: self.authority,
+                new_authority: Pubkey::default(),
+            });
+            self.authority = Pubkey::default();
+            self.pending_authority = Pubkey::default();
+            self.locked = true;
             Ok(())
         }
     }
 
-    #[access_control(CreateVesting::accounts(&ctx, nonce))]
-    pub fn create_vesting(
-        ctx: Context<CreateVesting>,
-        beneficiary: Pubkey,
-        deposit_amount: u64,
-        nonce: u8,
-        start_ts: i64,
-        end_ts: i64,
-        period_count: u64,
-        realizor: Option<Realizor>,
+    pub fn create_vesting(ctx: Context<CreateVesting>, params: VestingParams) -> Result<()> {
+        params.validate(&ctx.accounts.lockup)?;
+        let nonce = CreateVesting::accounts(&ctx)?;
+        params.init(
+            &mut ctx.accounts.vesting,
+            *ctx.accounts.vault.to_account_info().key,
+            ctx.accounts.vault.mint,
+            *ctx.accounts.depositor_authority.key,
+            nonce,
+            ctx.accounts.clock.unix_timestamp,
+        );
+        ctx.accounts.vesting.rounding = ctx.accounts.lockup.rounding;
+
+        let deposit_amount = params.deposit_amount;
+        let decimals = ctx.accounts.mint.decimals;
+        token_interface::transfer_checked(ctx.accounts.into(), deposit_amount, decimals)?;
+        tally_granted(
+            &mut ctx.accounts.grantor_tally,
+            ctx.accounts.depositor_authority.key,
+            deposit_amount,
+        )?;
+
+        let lockup = &mut ctx.accounts.lockup;
+        lockup.total_outstanding = lockup
+            .total_outstanding
+            .checked_add(deposit_amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(VestingCreated {
+            vesting: *ctx.accounts.vesting.to_account_info().key,
+            beneficiary: params.beneficiary,
+            deposit_amount,
+            end_ts: params.end_ts,
+        });
+
+        Ok(())
+    }
+
+    // Same as `create_vesting`, except the vesting account is allocated by
+    // this instruction rather than a preceding one, so that a client can
+    // safely retry a transaction that may or may not have landed. If the
+    // account already exists and was created with exactly these parameters,
+    // from the same vault and grantor, the instruction succeeds without
+    // depositing again. Any difference is an error.
+    pub fn create_vesting_idempotent(
+        ctx: Context<CreateVestingIdempotent>,
+        params: VestingParams,
     ) -> Result<()> {
-        if deposit_amount == 0 {
-            return err!(ErrorCode::InvalidDepositAmount);
-        }
-        if !is_valid_schedule(start_ts, end_ts, period_count) {
-            return err!(ErrorCode::InvalidSchedule);
+        let vault = *ctx.accounts.vault.to_account_info().key;
+        let grantor = *ctx.accounts.depositor_authority.key;
+        // Freshly allocated accounts are zeroed, and every initialized
+        // account carries a non-zero version.
+        if ctx.accounts.vesting.version != 0 {
+            if !params.matches(&ctx.accounts.vesting, vault, grantor) {
+                return err!(ErrorCode::VestingAlreadyExists);
+            }
+            return Ok(());
         }
-        let vesting = &mut ctx.accounts.vesting;
-        vesting.beneficiary = beneficiary;
-        vesting.mint = ctx.accounts.vault.mint;
-        vesting.vault = *ctx.accounts.vault.to_account_info().key;
-        vesting.period_count = period_count;
-        vesting.start_balance = deposit_amount;
-        vesting.end_ts = end_ts;
-        vesting.start_ts = start_ts;
-        vesting.created_ts = ctx.accounts.clock.unix_timestamp;
-        vesting.outstanding = deposit_amount;
-        vesting.whitelist_owned = 0;
-        vesting.grantor = *ctx.accounts.depositor_authority.key;
-        vesting.nonce = nonce;
-        vesting.realizor = realizor;
 
-        token::transfer(ctx.accounts.into(), deposit_amount)?;
+        params.validate(&ctx.accounts.lockup)?;
+        let nonce = vault_nonce(
+            ctx.accounts.vesting.to_account_info().key,
+            &ctx.accounts.vault,
+            ctx.program_id,
+        )?;
+        params.init(
+            &mut ctx.accounts.vesting,
+            vault,
+            ctx.accounts.vault.mint,
+            grantor,
+            nonce,
+            ctx.accounts.clock.unix_timestamp,
+        );
+        ctx.accounts.vesting.rounding = ctx.accounts.lockup.rounding;
+
+        let decimals = ctx.accounts.mint.decimals;
+        token_interface::transfer_checked(ctx.accounts.into(), params.deposit_amount, decimals)?;
+        tally_granted(
+            &mut ctx.accounts.grantor_tally,
+            &grantor,
+            params.deposit_amount,
+        )?;
+
+        let lockup = &mut ctx.accounts.lockup;
+        lockup.total_outstanding = lockup
+            .total_outstanding
+            .checked_add(params.deposit_amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(VestingCreated {
+            vesting: *ctx.accounts.vesting.to_account_info().key,
+            beneficiary: params.beneficiary,
+            deposit_amount: params.deposit_amount,
+            end_ts: params.end_ts,
+        });
 
         Ok(())
     }
 
-    #[access_control(is_realized(&ctx))]
-    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
-        // Has the given amount vested?
-        if amount
-            > calculator::available_for_withdrawal(
-                &ctx.accounts.vesting,
-                ctx.accounts.clock.unix_timestamp,
-            )
+    // Same as `create_vesting`, except the deposit is drawn from an allowance
+    // approved to the vesting signer beforehand, e.g., set aside by a grant
+    // factory, capping what can be granted. The depositor's owner must still
+    // sign, since anyone could otherwise spend the allowance, and becomes the
+    // grantor.
+    pub fn create_vesting_delegated(
+        ctx: Context<CreateVestingDelegated>,
+        params: VestingParams,
+    ) -> Result<()> {
+        params.validate(&ctx.accounts.lockup)?;
+        let nonce = vault_nonce(
+            ctx.accounts.vesting.to_account_info().key,
+            &ctx.accounts.vault,
+            ctx.program_id,
+        )?;
+        let depositor = &ctx.accounts.depositor;
+        if Option::<Pubkey>::from(depositor.delegate) != Some(ctx.accounts.vesting_signer.key())
+            || depositor.delegated_amount < params.deposit_amount
         {
-            return err!(ErrorCode::InsufficientWithdrawalBalance);
+            return err!(ErrorCode::InsufficientDelegation);
         }
+        params.init(
+            &mut ctx.accounts.vesting,
+            ctx.accounts.vault.key(),
+            ctx.accounts.vault.mint,
+            depositor.owner,
+            nonce,
+            ctx.accounts.clock.unix_timestamp,
+        );
+        ctx.accounts.vesting.rounding = ctx.accounts.lockup.rounding;
 
-        // Transfer funds out.
         let seeds = &[
             ctx.accounts.vesting.to_account_info().key.as_ref(),
-            &[ctx.accounts.vesting.nonce],
+            &[nonce],
         ];
         let signer = &[&seeds[..]];
-        let cpi_ctx = CpiContext::from(&*ctx.accounts).with_signer(signer);
-        token::transfer(cpi_ctx, amount)?;
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.depositor.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                to: ctx.accounts.vault.to_account_info(),
+                authority: ctx.accounts.vesting_signer.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(
+            cpi_ctx,
+            params.deposit_amount,
+            ctx.accounts.mint.decimals,
+        )?;
+        let grantor = ctx.accounts.depositor.owner;
+        tally_granted(
+            &mut ctx.accounts.grantor_tally,
+            &grantor,
+            params.deposit_amount,
+        )?;
 
-        // Bookeeping.
-        let vesting = &mut ctx.accounts.vesting;
-        vesting.outstanding -= amount;
+        let lockup = &mut ctx.accounts.lockup;
+        lockup.total_outstanding = lockup
+            .total_outstanding
+            .checked_add(params.deposit_amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(VestingCreated {
+            vesting: *ctx.accounts.vesting.to_account_info().key,
+            beneficiary: params.beneficiary,
+            deposit_amount: params.deposit_amount,
+            end_ts: params.end_ts,
+        });
 
         Ok(())
     }
 
-    // Sends funds from the lockup program to a whitelisted program.
-    pub fn whitelist_withdraw<'a, 'b, 'c, 'info>(
-        ctx: Context<'a, 'b, 'c, 'info, WhitelistWithdraw<'info>>,
-        instruction_data: Vec<u8>,
-        amount: u64,
-    ) -> Result<()> {
-        let before_amount = ctx.accounts.transfer.vault.amount;
-        whitelist_relay_cpi(
-            &ctx.accounts.transfer,
-            ctx.remaining_accounts,
-            instruction_data,
-        )?;
-        ctx.accounts.transfer.vault.reload()?;
-        let after_amount = ctx.accounts.transfer.vault.amount;
+    // Creates the PDA tallying the signing grantor's grants. Creations and
+    // revocations passing it are counted from then on.
+    pub fn init_grantor_tally(ctx: Context<InitGrantorTally>) -> Result<()> {
+        let tally = &mut ctx.accounts.grantor_tally;
+        tally.grantor = ctx.accounts.grantor.key();
+        tally.bump = *ctx.bumps.get("grantor_tally").unwrap();
+        Ok(())
+    }
 
-        // CPI safety checks.
-        let withdraw_amount = before_amount - after_amount;
-        if withdraw_amount > amount {
-            return err!(ErrorCode::WhitelistWithdrawLimit);
+    // Adds funds to an existing vesting account. The deposit vests on the
+    // same `start_ts`/`end_ts` timeline as the rest of the account, since
+    // the calculator unlocks a fraction of `start_balance` per period. As a
+    // result, the share of the deposit belonging to periods that have already
+    // passed is available for withdrawal immediately.
+    #[access_control(is_mutable(&ctx.accounts.vesting))]
+    pub fn deposit_to_vesting(ctx: Context<DepositToVesting>, amount: u64) -> Result<()> {
+        if amount == 0 {
+            return err!(ErrorCode::InvalidDepositAmount);
         }
 
-        // Bookeeping.
-        ctx.accounts.transfer.vesting.whitelist_owned += withdraw_amount;
+        let decimals = ctx.accounts.mint.decimals;
+        token_interface::transfer_checked(ctx.accounts.into(), amount, decimals)?;
+
+        // Bookkeeping.
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.outstanding = vesting
+            .outstanding
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        vesting.start_balance = vesting
+            .start_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        let lockup = &mut ctx.accounts.lockup;
+        lockup.total_outstanding = lockup
+            .total_outstanding
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
 
         Ok(())
     }
 
-    // Sends funds from a whitelisted program back to the lockup program.
-    pub fn whitelist_deposit<'a, 'b, 'c, 'info>(
-        ctx: Context<'a, 'b, 'c, 'info, WhitelistDeposit<'info>>,
-        instruction_data: Vec<u8>,
-    ) -> Result<()> {
-        let before_amount = ctx.accounts.transfer.vault.amount;
-        whitelist_relay_cpi(
-            &ctx.accounts.transfer,
-            ctx.remaining_accounts,
-            instruction_data,
-        )?;
-        ctx.accounts.transfer.vault.reload()?;
-        let after_amount = ctx.accounts.transfer.vault.amount;
-
-        // CPI safety checks.
-        let deposit_amount = after_amount - before_amount;
-        if deposit_amount <= 0 {
-            return err!(ErrorCode::InsufficientWhitelistDepositAmount);
+    // Adds a vault of another mint to the vesting account, funded with
+    // `amount`, that vests on the same schedule as the primary vault. The
+    // primary vault is unaffected, so single-vault clients keep working,
+    // and `withdraw_from_vault` withdraws from the added ones.
+    //
+    // Milestones and partial revocations are denominated in the primary
+    // mint, so accounts with either can't hold other vaults.
+    #[access_control(
+        grantor_auth(&ctx.accounts.vesting, &ctx.accounts.grantor)
+        is_mutable(&ctx.accounts.vesting)
+    )]
+    pub fn add_vault(ctx: Context<AddVault>, amount: u64) -> Result<()> {
+        if amount == 0 {
+            return err!(ErrorCode::InvalidDepositAmount);
         }
-        if deposit_amount > ctx.accounts.transfer.vesting.whitelist_owned {
-            return err!(ErrorCode::WhitelistDepositOverflow)?;
+        let vesting = &ctx.accounts.vesting;
+        if vesting.milestone_count > 0
+            || vesting.partially_revoked > 0
+            || vesting.realizor.is_some()
+            || vesting.revoke_effective_ts != 0
+        {
+            return err!(ErrorCode::MultipleVaultsUnsupported);
         }
+        let mint = ctx.accounts.vault.mint;
+        if vesting.extra_vault_count as usize == MAX_EXTRA_VAULTS
+            || mint == vesting.mint
+            || vesting.extra_vaults().iter().any(|v| v.mint == mint)
+        {
+            return err!(ErrorCode::InvalidExtraVault);
+        }
+        vault_nonce(
+            ctx.accounts.vesting.to_account_info().key,
+            &ctx.accounts.vault,
+            ctx.program_id,
+        )?;
+
+        let cpi_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.depositor.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                to: ctx.accounts.vault.to_account_info(),
+                authority: ctx.accounts.grantor.to_account_info(),
+            },
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
 
         // Bookkeeping.
-        ctx.accounts.transfer.vesting.whitelist_owned -= deposit_amount;
+        let vesting = &mut ctx.accounts.vesting;
+        let idx = vesting.extra_vault_count as usize;
+        vesting.extra_vaults[idx] = ExtraVault {
+            vault: ctx.accounts.vault.key(),
+            mint,
+            start_balance: amount,
+            outstanding: amount,
+        };
+        vesting.extra_vault_count += 1;
+        let lockup = &mut ctx.accounts.lockup;
+        lockup.total_outstanding = lockup
+            .total_outstanding
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
 
-        Ok(())
-    }
+        emit!(VaultAdded {
+            vesting: ctx.accounts.vesting.key(),
+            vault: ctx.accounts.vault.key(),
+            mint,
+            amount,
+        });
 
-    // Convenience function for UI's to calculate the withdrawable amount.
-    pub fn available_for_withdrawal(ctx: Context<AvailableForWithdrawal>) -> Result<()> {
-        let available = calculator::available_for_withdrawal(
-            &ctx.accounts.vesting,
-            ctx.accounts.clock.unix_timestamp,
-        );
-        // Log as string so that JS can read as a BN.
-        msg!(&format!("{{ \"result\": \"{}\" }}", available));
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct Auth<'info> {
-    #[account(signer)]
-    authority: AccountInfo<'info>,
-}
+    // Returns the unvested funds to the grantor, ending the schedule.
+    //
+    // If the account has a revocation notice period, the first call only
+    // schedules the revocation, and the beneficiary can keep withdrawing
+    // until it takes effect. A second call after that claws back whatever
+    // is still unvested.
+    #[access_control(
+        grantor_auth(&ctx.accounts.vesting, &ctx.accounts.grantor)
+        is_mutable(&ctx.accounts.vesting)
+        is_not_in_relay(&ctx.accounts.vesting)
+    )]
+    pub fn revoke(ctx: Context<Revoke>) -> Result<()> {
+        if !ctx.accounts.vesting.revocable {
+            return err!(ErrorCode::VestingNotRevocable);
+        }
+        // Only the primary vault would be clawed back.
+        if ctx.accounts.vesting.extra_vault_count > 0 {
+            return err!(ErrorCode::MultipleVaultsUnsupported);
+        }
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        let vesting = &mut ctx.accounts.vesting;
+        if vesting.revoke_notice_seconds > 0 {
+            if vesting.revoke_effective_ts == 0 {
+                vesting.revoke_effective_ts = now
+                    .checked_add(vesting.revoke_notice_seconds)
+                    .ok_or(ErrorCode::Overflow)?;
+                emit!(RevocationScheduled {
+                    vesting: *vesting.to_account_info().key,
+                    effective_ts: vesting.revoke_effective_ts,
+                });
+                return Ok(());
+            }
+            if now < vesting.revoke_effective_ts {
+                return err!(ErrorCode::RevocationPending);
+            }
+        }
+        let unvested = ctx.accounts.vesting.unvested(now);
+        if unvested > ctx.accounts.vault.amount {
+            return err!(ErrorCode::InsufficientWithdrawalBalance);
+        }
 
-#[derive(Accounts)]
-pub struct CreateVesting<'info> {
-    // Vesting.
-    #[account(zero)]
-    pub vesting: Account<'info, Vesting>,
-    #[account(mut)]
-    pub vault: Account<'info, TokenAccount>,
-    // Depositor.
-    #[account(mut)]
-    pub depositor: AccountInfo<'info>,
-    #[account(signer)]
-    pub depositor_authority: AccountInfo<'info>,
-    // Misc.
-    #[account(constraint = token_program.key == &token::ID)]
-    pub token_program: AccountInfo<'info>,
-    pub clock: Sysvar<'info, Clock>,
-}
+        // Transfer unvested funds back to the grantor.
+        let seeds = &[
+            ctx.accounts.vesting.to_account_info().key.as_ref(),
+            &[ctx.accounts.vesting.nonce],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::from(&*ctx.accounts).with_signer(signer);
+        token_interface::transfer_checked(cpi_ctx, unvested, ctx.accounts.mint.decimals)?;
+        let grantor = ctx.accounts.vesting.grantor;
+        tally_revoked(&mut ctx.accounts.grantor_tally, &grantor, unvested)?;
 
-impl<'info> CreateVesting<'info> {
-    fn accounts(ctx: &Context<CreateVesting>, nonce: u8) -> Result<()> {
-        let vault_authority = Pubkey::create_program_address(
-            &[
-                ctx.accounts.vesting.to_account_info().key.as_ref(),
-                &[nonce],
-            ],
-            ctx.program_id,
-        )
-        .map_err(|_| error!(ErrorCode::InvalidProgramAddress))?;
-        if ctx.accounts.vault.owner != vault_authority {
-            return err!(ErrorCode::InvalidVaultOwner)?;
-        }
+        // Bookkeeping. End the schedule now so that everything vested up to
+        // this point remains withdrawable and nothing further vests.
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.outstanding = vesting
+            .outstanding
+            .checked_sub(unvested)
+            .ok_or(ErrorCode::Overflow)?;
+        vesting.start_balance = vesting
+            .start_balance
+            .checked_sub(unvested)
+            .ok_or(ErrorCode::Overflow)?;
+        vesting.end_ts = now;
+        let lockup = &mut ctx.accounts.lockup;
+        lockup.total_outstanding = lockup
+            .total_outstanding
+            .checked_sub(unvested)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(VestingRevoked {
+            vesting: *vesting.to_account_info().key,
+            amount: unvested,
+        });
 
         Ok(())
     }
-}
 
-// All accounts not included here, i.e., the "remaining accounts" should be
-// ordered according to the realization interface.
-#[derive(Accounts)]
-pub struct Withdraw<'info> {
-    // Vesting.
-    #[account(mut, has_one = beneficiary, has_one = vault)]
+    // Returns part of the unvested funds to the grantor, e.g., to reduce a
+    // grant by a quarter. The amount comes off the end of the schedule, so
+    // everything keeps vesting at the same rate until the smaller balance
+    // has vested. With a notice period, the revocation must first have been
+    // scheduled with `revoke`, and taken effect.
+    #[access_control(
+        grantor_auth(&ctx.accounts.vesting, &ctx.accounts.grantor)
+        is_mutable(&ctx.accounts.vesting)
+        is_not_in_relay(&ctx.accounts.vesting)
+    )]
+    pub fn revoke_partial(ctx: Context<Revoke>, amount: u64) -> Result<()> {
+        let vesting = &ctx.accounts.vesting;
+        if !vesting.revocable {
+            return err!(ErrorCode::VestingNotRevocable);
+        }
+        if vesting.extra_vault_count > 0 {
+            return err!(ErrorCode::MultipleVaultsUnsupported);
+        }
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        if vesting.revoke_notice_seconds > 0
+            && (vesting.revoke_effective_ts == 0 || now < vesting.revoke_effective_ts)
+        {
+            return err!(ErrorCode::RevocationPending);
+        }
+        if amount == 0 || amount > vesting.unvested(now) {
+            return err!(ErrorCode::InvalidRevokeAmount);
+        }
+        if amount > ctx.accounts.vault.amount {
+            return err!(ErrorCode::InsufficientWithdrawalBalance);
+        }
+
+        let seeds = &[
+            ctx.accounts.vesting.to_account_info().key.as_ref(),
+            &[ctx.accounts.vesting.nonce],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::from(&*ctx.accounts).with_signer(signer);
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+        let grantor = ctx.accounts.vesting.grantor;
+        tally_revoked(&mut ctx.accounts.grantor_tally, &grantor, amount)?;
+
+        // Bookkeeping.
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.outstanding = vesting
+            .outstanding
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        vesting.start_balance = vesting
+            .start_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        vesting.partially_revoked = vesting
+            .partially_revoked
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        let lockup = &mut ctx.accounts.lockup;
+        lockup.total_outstanding = lockup
+            .total_outstanding
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(VestingPartiallyRevoked {
+            vesting: *vesting.to_account_info().key,
+            amount,
+            start_balance: vesting.start_balance,
+            outstanding: vesting.outstanding,
+        });
+
+        Ok(())
+    }
+
+    // Returns any residual vault balance, e.g., rounding remainders or
+    // direct transfers, to the grantor once the beneficiary has withdrawn
+    // everything. Returns the amount swept.
+    #[access_control(
+        grantor_auth(&ctx.accounts.vesting, &ctx.accounts.grantor)
+        is_not_in_relay(&ctx.accounts.vesting)
+    )]
+    pub fn sweep_dust(ctx: Context<Revoke>) -> Result<u64> {
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        let vesting = &ctx.accounts.vesting;
+        if vesting.outstanding != 0 || vesting.loaned() != 0 || vesting.available(now) != 0 {
+            return err!(ErrorCode::VestingNotComplete);
+        }
+        let amount = ctx.accounts.vault.amount;
+
+        let seeds = &[
+            ctx.accounts.vesting.to_account_info().key.as_ref(),
+            &[ctx.accounts.vesting.nonce],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::from(&*ctx.accounts).with_signer(signer);
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+        Ok(amount)
+    }
+
+    // Vests everything immediately, e.g., on acquisition.
+    #[access_control(
+        grantor_auth(&ctx.accounts.vesting, &ctx.accounts.grantor)
+        is_mutable(&ctx.accounts.vesting)
+    )]
+    pub fn accelerate(ctx: Context<Accelerate>) -> Result<()> {
+        let vesting = &mut ctx.accounts.vesting;
+        if !vesting.accelerable {
+            return err!(ErrorCode::VestingNotAccelerable);
+        }
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        if now < vesting.end_ts {
+            // Pull the start and cliff in too, in case they are still in
+            // the future.
+            vesting.start_ts = std::cmp::min(vesting.start_ts, now);
+            vesting.cliff_ts = std::cmp::min(vesting.cliff_ts, now);
+            vesting.end_ts = now;
+        }
+
+        emit!(VestingAccelerated {
+            vesting: *vesting.to_account_info().key,
+            end_ts: vesting.end_ts,
+        });
+
+        Ok(())
+    }
+
+    #[access_control(
+        withdraw_auth(&ctx.accounts)
+        is_realized(&ctx)
+        is_not_frozen(&ctx.accounts.vesting)
+        co_beneficiary_auth(&ctx.accounts.vesting, &ctx.accounts.co_beneficiary)
+        grantor_approval(&ctx.accounts.vesting, &ctx.accounts.grantor)
+        is_not_in_relay(&ctx.accounts.vesting)
+    )]
+    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        // Has the given amount vested?
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        if amount > ctx.accounts.vesting.available(now) {
+            return err!(ErrorCode::InsufficientWithdrawalBalance);
+        }
+
+        transfer_withdrawal(ctx.accounts, amount)
+    }
+
+    // Same as `withdraw`, but only to the beneficiary's associated token
+    // account, guarding against passing the wrong `token` account.
+    #[access_control(is_associated_token_account(&ctx.accounts))]
+    pub fn withdraw_to_associated(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        withdraw(ctx, amount)
+    }
+
+    // Same as `withdraw`, but for wrapped SOL grants, delivering native SOL.
+    // The `token` account is a temporary wrapped SOL account of the
+    // beneficiary's, which is closed afterwards, sending its lamports to the
+    // beneficiary.
+    #[access_control(is_unwrappable(&ctx.accounts))]
+    pub fn withdraw_unwrap(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        let token = ctx.accounts.token.to_account_info();
+        let beneficiary = ctx.accounts.beneficiary.clone();
+        let token_program = ctx.accounts.token_program.clone();
+        withdraw(ctx, amount)?;
+
+        let cpi_ctx = CpiContext::new(
+            token_program,
+            CloseAccount {
+                account: token,
+                destination: beneficiary.clone(),
+                authority: beneficiary,
+            },
+        );
+        token_interface::close_account(cpi_ctx)
+    }
+
+    // Withdraws everything currently available, returning the amount
+    // withdrawn. Unlike `withdraw`, this can't race the vesting schedule.
+    #[access_control(
+        withdraw_auth(&ctx.accounts)
+        is_realized(&ctx)
+        is_not_frozen(&ctx.accounts.vesting)
+        co_beneficiary_auth(&ctx.accounts.vesting, &ctx.accounts.co_beneficiary)
+        grantor_approval(&ctx.accounts.vesting, &ctx.accounts.grantor)
+        is_not_in_relay(&ctx.accounts.vesting)
+    )]
+    pub fn withdraw_all(ctx: Context<Withdraw>) -> Result<u64> {
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        let amount = ctx.accounts.vesting.available(now);
+        transfer_withdrawal(ctx.accounts, amount)?;
+        Ok(amount)
+    }
+
+    // Withdraws `amount` from the vault added by `add_vault` at `index`,
+    // vested in proportion to that vault's own deposit.
+    #[access_control(
+        is_not_frozen(&ctx.accounts.vesting)
+        co_beneficiary_auth(&ctx.accounts.vesting, &ctx.accounts.co_beneficiary)
+        grantor_approval(&ctx.accounts.vesting, &ctx.accounts.grantor)
+        is_not_in_relay(&ctx.accounts.vesting)
+    )]
+    pub fn withdraw_from_vault(
+        ctx: Context<WithdrawFromVault>,
+        index: u8,
+        amount: u64,
+    ) -> Result<()> {
+        let extra = match ctx.accounts.vesting.extra_vaults().get(index as usize) {
+            Some(extra) if extra.vault == ctx.accounts.vault.key() => *extra,
+            _ => return err!(ErrorCode::InvalidExtraVault),
+        };
+        // Fees and withdraw callbacks are denominated in the primary mint.
+        if ctx.accounts.lockup.withdraw_fee_bps != 0
+            || ctx.accounts.vesting.withdraw_callback_program.is_some()
+        {
+            return err!(ErrorCode::MultipleVaultsUnsupported);
+        }
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        if calculator::is_withdraw_throttled(&ctx.accounts.vesting, now) {
+            return err!(ErrorCode::WithdrawTooSoon);
+        }
+        if amount > ctx.accounts.vesting.extra_available(&extra, now) {
+            return err!(ErrorCode::InsufficientWithdrawalBalance);
+        }
+        if !ctx
+            .accounts
+            .vesting
+            .is_destination_allowed(&ctx.accounts.token.key())
+        {
+            return err!(ErrorCode::DestinationNotAllowed);
+        }
+
+        let seeds = &[
+            ctx.accounts.vesting.to_account_info().key.as_ref(),
+            &[ctx.accounts.vesting.nonce],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.vault.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                to: ctx.accounts.token.to_account_info(),
+                authority: ctx.accounts.vesting_signer.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+        // Bookkeeping.
+        let vesting = &mut ctx.accounts.vesting;
+        let remaining = extra
+            .outstanding
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        vesting.extra_vaults[index as usize].outstanding = remaining;
+        vesting.record_withdrawal(now)?;
+        let lockup = &mut ctx.accounts.lockup;
+        lockup.total_outstanding = lockup
+            .total_outstanding
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        emit!(VaultWithdrawn {
+            vesting: ctx.accounts.vesting.key(),
+            vault: extra.vault,
+            amount,
+            remaining,
+        });
+
+        Ok(())
+    }
+
+    // Withdraws everything currently available from several vesting accounts
+    // of the same beneficiary, returning the total amount withdrawn. The
+    // remaining accounts are `(vesting, vault, vesting_signer, token)`
+    // groups. Vesting accounts with a realizor, co-beneficiary, withdraw
+    // callback or requiring grantor approval must be withdrawn from
+    // individually.
+    pub fn withdraw_many<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, WithdrawMany<'info>>,
+    ) -> Result<u64> {
+        if ctx.remaining_accounts.is_empty() || ctx.remaining_accounts.len() % 4 != 0 {
+            return err!(ErrorCode::InvalidWithdrawManyAccounts);
+        }
+        // There's no fee treasury to pay into.
+        if ctx.accounts.lockup.withdraw_fee_bps != 0 {
+            return err!(ErrorCode::WithdrawManyUnsupported);
+        }
+        let mut total: u64 = 0;
+        for accounts in ctx.remaining_accounts.chunks(4) {
+            let amount = withdraw_one(ctx.accounts, ctx.program_id, accounts)?;
+            total = total.checked_add(amount).ok_or(ErrorCode::Overflow)?;
+        }
+        let lockup = &mut ctx.accounts.lockup;
+        lockup.total_outstanding = lockup
+            .total_outstanding
+            .checked_sub(total)
+            .ok_or(ErrorCode::Overflow)?;
+        Ok(total)
+    }
+
+    #[access_control(is_mutable(&ctx.accounts.vesting))]
+    pub fn set_beneficiary(ctx: Context<SetBeneficiary>, new_beneficiary: Pubkey) -> Result<()> {
+        if new_beneficiary == Pubkey::default() {
+            return err!(ErrorCode::InvalidBeneficiary);
+        }
+        // Whitelisted programs may key their records off the beneficiary
+        // (e.g. the registry's member), so don't strand funds in flight.
+        if ctx.accounts.vesting.loaned() != 0 {
+            return err!(ErrorCode::WhitelistFundsOutstanding);
+        }
+        // The delegate and relay initiators were picked by the previous
+        // beneficiary, so they don't carry over.
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.beneficiary = new_beneficiary;
+        vesting.withdraw_delegate = None;
+        vesting.relay_initiators = [Pubkey::default(); MAX_RELAY_INITIATORS];
+        Ok(())
+    }
+
+    // Moves `amount` of the vault into a new vesting account for
+    // `new_beneficiary`, on the same terms and schedule. Both accounts then
+    // vest in proportion to their balances, so that together they unlock as
+    // the original would have. Any partially revoked amount is divided up
+    // in the same proportion, since it sets the rate both unlock at.
+    // Whatever was already withdrawn stays with the original, which must
+    // have vested at least that much afterwards.
+    #[access_control(
+        is_mutable(&ctx.accounts.vesting)
+        is_not_frozen(&ctx.accounts.vesting)
+        co_beneficiary_auth(&ctx.accounts.vesting, &ctx.accounts.co_beneficiary)
+        is_not_in_relay(&ctx.accounts.vesting)
+    )]
+    pub fn split_vesting(
+        ctx: Context<SplitVesting>,
+        amount: u64,
+        new_beneficiary: Pubkey,
+    ) -> Result<()> {
+        if new_beneficiary == Pubkey::default() {
+            return err!(ErrorCode::InvalidBeneficiary);
+        }
+        let vesting = &ctx.accounts.vesting;
+        // Milestones are absolute amounts, which can't be divided up.
+        if vesting.milestone_count > 0 {
+            return err!(ErrorCode::SplitUnsupported);
+        }
+        if vesting.extra_vault_count > 0 {
+            return err!(ErrorCode::MultipleVaultsUnsupported);
+        }
+        if vesting.revoke_effective_ts != 0 {
+            return err!(ErrorCode::RevocationPending);
+        }
+        // Only funds in the vault can be moved.
+        let in_vault = vesting
+            .outstanding
+            .checked_sub(vesting.loaned())
+            .ok_or(ErrorCode::Overflow)?;
+        if amount == 0 || amount > in_vault {
+            return err!(ErrorCode::InvalidSplitAmount);
+        }
+        let new_vesting_key = *ctx.accounts.new_vesting.to_account_info().key;
+        let nonce = vault_nonce(&new_vesting_key, &ctx.accounts.new_vault, ctx.program_id)?;
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+
+        // Bookkeeping.
+        let vesting = &mut ctx.accounts.vesting;
+        let withdrawn = vesting
+            .start_balance
+            .checked_sub(vesting.outstanding)
+            .ok_or(ErrorCode::Overflow)?;
+        let revoked = (vesting.partially_revoked as u128 * amount as u128
+            / vesting.start_balance as u128) as u64;
+        vesting.start_balance = vesting
+            .start_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        vesting.outstanding = vesting
+            .outstanding
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        vesting.partially_revoked = vesting
+            .partially_revoked
+            .checked_sub(revoked)
+            .ok_or(ErrorCode::Overflow)?;
+        if calculator::vested_at(vesting, now) < withdrawn {
+            return err!(ErrorCode::InvalidSplitAmount);
+        }
+        let params = VestingParams {
+            beneficiary: new_beneficiary,
+            deposit_amount: amount,
+            start_ts: vesting.start_ts,
+            end_ts: vesting.end_ts,
+            period_count: vesting.period_count,
+            cliff_ts: vesting.cliff_ts,
+            revocable: vesting.revocable,
+            accelerable: vesting.accelerable,
+            period_rounding: vesting.period_rounding,
+            min_withdraw_interval: vesting.min_withdraw_interval,
+            memo: vesting.memo,
+            milestones: vec![],
+            final_bps: vesting.final_bps,
+            revoke_notice_seconds: vesting.revoke_notice_seconds,
+            requires_grantor_approval: vesting.requires_grantor_approval,
+            immutable: vesting.immutable,
+            schedule_kind: vesting.schedule_kind,
+            curve_exponent: vesting.curve_exponent,
+            recovery_authority: vesting.recovery_authority,
+            realizor: vesting.realizor.clone(),
+        };
+        let new_vesting = &mut ctx.accounts.new_vesting;
+        params.init(
+            new_vesting,
+            ctx.accounts.new_vault.key(),
+            vesting.mint,
+            vesting.grantor,
+            nonce,
+            now,
+        );
+        new_vesting.partially_revoked = revoked;
+        // Otherwise splitting would get around the withdrawal throttle.
+        new_vesting.last_withdraw_ts = vesting.last_withdraw_ts;
+        new_vesting.program_allowlist = vesting.program_allowlist;
+        new_vesting.rounding = vesting.rounding;
+        // Otherwise splitting would get around the destination allowlist.
+        new_vesting.destination_allowlist = vesting.destination_allowlist;
+
+        // Move the funds.
+        let seeds = &[
+            ctx.accounts.vesting.to_account_info().key.as_ref(),
+            &[ctx.accounts.vesting.nonce],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.vault.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                to: ctx.accounts.new_vault.to_account_info(),
+                authority: ctx.accounts.vesting_signer.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+        emit!(VestingSplit {
+            vesting: *ctx.accounts.vesting.to_account_info().key,
+            new_vesting: new_vesting_key,
+            new_beneficiary,
+            amount,
+        });
+
+        Ok(())
+    }
+
+    // Sends the outstanding balance back to the grantor, for when the
+    // beneficiary's key is compromised. Only the recovery authority the
+    // grantor appointed at creation can do this, so the beneficiary knew of
+    // this path from the start. Nothing further vests. Anything else sent
+    // to the vault is left for `sweep_dust`.
+    #[access_control(is_not_in_relay(&ctx.accounts.vesting))]
+    pub fn recover(ctx: Context<Recover>) -> Result<()> {
+        // Funds held by whitelisted programs can't be recovered from here.
+        if ctx.accounts.vesting.loaned() != 0 {
+            return err!(ErrorCode::WhitelistFundsOutstanding);
+        }
+        // Only the primary vault would be recovered.
+        if ctx.accounts.vesting.extra_vault_count > 0 {
+            return err!(ErrorCode::MultipleVaultsUnsupported);
+        }
+        let amount = std::cmp::min(ctx.accounts.vesting.outstanding, ctx.accounts.vault.amount);
+
+        let seeds = &[
+            ctx.accounts.vesting.to_account_info().key.as_ref(),
+            &[ctx.accounts.vesting.nonce],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.vault.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                to: ctx.accounts.destination.to_account_info(),
+                authority: ctx.accounts.vesting_signer.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+        // Bookkeeping, as in a full revocation.
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.outstanding = vesting
+            .outstanding
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        vesting.start_balance = vesting
+            .start_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        vesting.end_ts = std::cmp::min(vesting.end_ts, now);
+        let grantor = vesting.grantor;
+        let lockup = &mut ctx.accounts.lockup;
+        lockup.total_outstanding = lockup
+            .total_outstanding
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        tally_revoked(&mut ctx.accounts.grantor_tally, &grantor, amount)?;
+
+        emit!(VestingRecovered {
+            vesting: *ctx.accounts.vesting.to_account_info().key,
+            recovery_authority: *ctx.accounts.recovery_authority.key,
+            destination: *ctx.accounts.destination.to_account_info().key,
+            amount,
+        });
+
+        Ok(())
+    }
+
+    // Sets or clears a delegate that can sign withdrawals in place of the
+    // beneficiary. The delegate can only withdraw to a token account owned by
+    // the beneficiary, and can't change the beneficiary, so it can never
+    // redirect funds.
+    pub fn set_withdraw_delegate(
+        ctx: Context<SetBeneficiary>,
+        withdraw_delegate: Option<Pubkey>,
+    ) -> Result<()> {
+        ctx.accounts.vesting.withdraw_delegate = withdraw_delegate;
+        Ok(())
+    }
+
+    // Sets or clears the co-beneficiary, who must also sign withdrawals.
+    // Replacing an existing co-beneficiary requires their signature as well.
+    #[access_control(co_beneficiary_auth(&ctx.accounts.vesting, &ctx.accounts.co_beneficiary))]
+    pub fn set_co_beneficiary(
+        ctx: Context<SetCoBeneficiary>,
+        co_beneficiary: Option<Pubkey>,
+    ) -> Result<()> {
+        if co_beneficiary == Some(Pubkey::default()) {
+            return err!(ErrorCode::InvalidBeneficiary);
+        }
+        ctx.accounts.vesting.co_beneficiary = co_beneficiary;
+        Ok(())
+    }
+
+    // Sets or clears a program to notify after every withdrawal, e.g., to
+    // update a staking position. The program must be whitelisted, since a
+    // failing callback fails the withdrawal.
+    pub fn set_withdraw_callback(
+        ctx: Context<SetWithdrawCallback>,
+        program: Option<Pubkey>,
+    ) -> Result<()> {
+        if let Some(program_id) = &program {
+            if !whitelist_contains(&ctx.accounts.lockup, program_id) {
+                return err!(ErrorCode::InvalidWithdrawCallback);
+            }
+        }
+        ctx.accounts.vesting.withdraw_callback_program = program;
+        Ok(())
+    }
+
+    // Restricts the vesting account to relaying to the given subset of the
+    // lockup's whitelist. An empty list allows the entire whitelist.
+    //
+    // Only the grantor may widen or clear the list. The beneficiary may only
+    // narrow it to a non-empty subset of the current one.
+    pub fn set_program_allowlist(
+        ctx: Context<SetProgramAllowlist>,
+        programs: Vec<Pubkey>,
+    ) -> Result<()> {
+        let vesting = &mut ctx.accounts.vesting;
+        let authority = ctx.accounts.authority.key;
+        if authority != &vesting.beneficiary && authority != &vesting.grantor {
+            return err!(ErrorCode::Unauthorized);
+        }
+        if programs.len() > MAX_PROGRAM_ALLOWLIST || programs.contains(&Pubkey::default()) {
+            return err!(ErrorCode::InvalidProgramAllowlist);
+        }
+        if authority != &vesting.grantor {
+            let current = &vesting.program_allowlist;
+            let narrows = !programs.is_empty()
+                && !current.iter().all(|p| p == &Pubkey::default())
+                && programs.iter().all(|p| current.contains(p));
+            if !narrows {
+                return err!(ErrorCode::Unauthorized);
+            }
+        }
+        vesting.program_allowlist = [Pubkey::default(); MAX_PROGRAM_ALLOWLIST];
+        vesting.program_allowlist[..programs.len()].copy_from_slice(&programs);
+        Ok(())
+    }
+
+    // Authorizes keys other than the beneficiary, e.g., a staking operator,
+    // to initiate whitelist relays. An empty list leaves only the
+    // beneficiary. Funds relayed this way are still owed back to the vault.
+    pub fn set_relay_initiators(
+        ctx: Context<SetBeneficiary>,
+        initiators: Vec<Pubkey>,
+    ) -> Result<()> {
+        if initiators.len() > MAX_RELAY_INITIATORS || initiators.contains(&Pubkey::default()) {
+            return err!(ErrorCode::InvalidRelayInitiators);
+        }
+        let vesting = &mut ctx.accounts.vesting;
+        vesting.relay_initiators = [Pubkey::default(); MAX_RELAY_INITIATORS];
+        vesting.relay_initiators[..initiators.len()].copy_from_slice(&initiators);
+        Ok(())
+    }
+
+    // Proposes restricting withdrawals to the given token accounts. An empty
+    // list allows any. While no allowlist is in effect, the new one applies
+    // right away, since it only narrows where funds can go. Otherwise it
+    // applies with `apply_destination_allowlist` once
+    // `DESTINATION_ALLOWLIST_DELAY` has passed, giving the beneficiary time to
+    // cancel a change made with a compromised key.
+    pub fn set_destination_allowlist(
+        ctx: Context<SetDestinationAllowlist>,
+        destinations: Vec<Pubkey>,
+    ) -> Result<()> {
+        if destinations.len() > MAX_DESTINATION_ALLOWLIST
+            || destinations.contains(&Pubkey::default())
+        {
+            return err!(ErrorCode::InvalidDestinationAllowlist);
+        }
+        let mut allowlist = [Pubkey::default(); MAX_DESTINATION_ALLOWLIST];
+        allowlist[..destinations.len()].copy_from_slice(&destinations);
+
+        let now = ctx.accounts.clock.unix_timestamp;
+        let vesting = &mut ctx.accounts.vesting;
+        let unrestricted = vesting
+            .destination_allowlist
+            .iter()
+            .all(|d| d == &Pubkey::default());
+        if unrestricted {
+            vesting.destination_allowlist = allowlist;
+            vesting.pending_destination_allowlist = [Pubkey::default(); MAX_DESTINATION_ALLOWLIST];
+            vesting.destination_allowlist_effective_ts = 0;
+            return Ok(());
+        }
+        vesting.pending_destination_allowlist = allowlist;
+        vesting.destination_allowlist_effective_ts = now
+            .checked_add(DESTINATION_ALLOWLIST_DELAY)
+            .ok_or(ErrorCode::Overflow)?;
+        emit!(DestinationAllowlistProposed {
+            vesting: vesting.key(),
+            effective_ts: vesting.destination_allowlist_effective_ts,
+        });
+        Ok(())
+    }
+
+    // Applies the pending destination allowlist once its delay has passed.
+    pub fn apply_destination_allowlist(ctx: Context<SetDestinationAllowlist>) -> Result<()> {
+        let now = ctx.accounts.clock.unix_timestamp;
+        let vesting = &mut ctx.accounts.vesting;
+        if vesting.destination_allowlist_effective_ts == 0
+            || now < vesting.destination_allowlist_effective_ts
+        {
+            return err!(ErrorCode::DestinationAllowlistNotReady);
+        }
+        vesting.destination_allowlist = vesting.pending_destination_allowlist;
+        vesting.pending_destination_allowlist = [Pubkey::default(); MAX_DESTINATION_ALLOWLIST];
+        vesting.destination_allowlist_effective_ts = 0;
+        Ok(())
+    }
+
+    // Drops the pending destination allowlist, keeping the current one.
+    pub fn cancel_destination_allowlist(ctx: Context<SetDestinationAllowlist>) -> Result<()> {
+        let vesting = &mut ctx.accounts.vesting;
+        if vesting.destination_allowlist_effective_ts == 0 {
+            return err!(ErrorCode::DestinationAllowlistNotReady);
+        }
+        vesting.pending_destination_allowlist = [Pubkey::default(); MAX_DESTINATION_ALLOWLIST];
+        vesting.destination_allowlist_effective_ts = 0;
+        Ok(())
+    }
+
+    // Any vaults added by `add_vault` are given as remaining accounts, in
+    // order, and closed along with the primary vault.
+    pub fn close_vesting<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, CloseVesting<'info>>,
+    ) -> Result<()> {
+        let vesting = &ctx.accounts.vesting;
+        if vesting.outstanding != 0
+            || vesting.loaned() != 0
+            || vesting.extra_vaults().iter().any(|v| v.outstanding != 0)
+        {
+            return err!(ErrorCode::VestingNotComplete);
+        }
+        if ctx.accounts.vault.amount != 0 {
+            return err!(ErrorCode::InvalidVaultAmount);
+        }
+        if ctx.remaining_accounts.len() != vesting.extra_vaults().len() {
+            return err!(ErrorCode::InvalidExtraVault);
+        }
+        for (extra, info) in vesting.extra_vaults().iter().zip(ctx.remaining_accounts) {
+            if info.key != &extra.vault {
+                return err!(ErrorCode::InvalidExtraVault);
+            }
+            let vault: InterfaceAccount<TokenAccount> = InterfaceAccount::try_from(info)?;
+            if vault.amount != 0 {
+                return err!(ErrorCode::InvalidVaultAmount);
+            }
+        }
+
+        // Close the vaults, returning their rent to the beneficiary. The
+        // vesting account itself is closed by the `close` constraint.
+        let seeds = &[
+            ctx.accounts.vesting.to_account_info().key.as_ref(),
+            &[ctx.accounts.vesting.nonce],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::from(&*ctx.accounts).with_signer(signer);
+        token_interface::close_account(cpi_ctx)?;
+        for info in ctx.remaining_accounts {
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account: info.clone(),
+                    destination: ctx.accounts.beneficiary.to_account_info(),
+                    authority: ctx.accounts.vesting_signer.to_account_info(),
+                },
+                signer,
+            );
+            token_interface::close_account(cpi_ctx)?;
+        }
+
+        Ok(())
+    }
+
+    // Moves the vault's full balance into a new token account owned by the
+    // same vesting signer, e.g., when rotating to a new vault address.
+    #[access_control(is_not_in_relay(&ctx.accounts.vesting))]
+    pub fn migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+        if ctx.accounts.new_vault.owner != ctx.accounts.vesting_signer.key() {
+            return err!(ErrorCode::InvalidVaultOwner);
+        }
+        if ctx.accounts.new_vault.mint != ctx.accounts.vesting.mint {
+            return err!(ErrorCode::MintMismatch);
+        }
+        if ctx.accounts.new_vault.key() == ctx.accounts.vault.key() {
+            return err!(ErrorCode::InvalidVaultMigration);
+        }
+
+        let amount = ctx.accounts.vault.amount;
+        let before_amount = ctx.accounts.new_vault.amount;
+        let seeds = &[
+            ctx.accounts.vesting.to_account_info().key.as_ref(),
+            &[ctx.accounts.vesting.nonce],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::from(&*ctx.accounts).with_signer(signer);
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+        // Safety checks.
+        ctx.accounts.vault.reload()?;
+        ctx.accounts.new_vault.reload()?;
+        let after_amount = before_amount
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        if ctx.accounts.vault.amount != 0 || ctx.accounts.new_vault.amount != after_amount {
+            return err!(ErrorCode::InvalidVaultMigration);
+        }
+
+        ctx.accounts.vesting.vault = ctx.accounts.new_vault.key();
+
+        Ok(())
+    }
+
+    // Hands the vault to the vesting signer derived with `new_nonce`, e.g.,
+    // to move accounts created with a non-canonical bump onto the canonical
+    // one, and signs with it from then on.
+    //
+    // Extra vaults are owned by the same signer, so accounts holding any
+    // can't be rotated.
+    #[access_control(
+        lockup_auth(&ctx.accounts.lockup, &ctx.accounts.authority)
+        is_not_in_relay(&ctx.accounts.vesting)
+    )]
+    pub fn rotate_signer(ctx: Context<RotateSigner>, new_nonce: u8) -> Result<()> {
+        let vesting_key = ctx.accounts.vesting.key();
+        let new_signer =
+            Pubkey::create_program_address(&[vesting_key.as_ref(), &[new_nonce]], ctx.program_id)
+                .map_err(|_| ErrorCode::InvalidSignerRotation)?;
+        if new_signer != ctx.accounts.new_vesting_signer.key()
+            || new_nonce == ctx.accounts.vesting.nonce
+        {
+            return err!(ErrorCode::InvalidSignerRotation);
+        }
+        if ctx.accounts.vesting.extra_vault_count != 0 {
+            return err!(ErrorCode::MultipleVaultsUnsupported);
+        }
+
+        let seeds = &[vesting_key.as_ref(), &[ctx.accounts.vesting.nonce]];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            SetAuthority {
+                current_authority: ctx.accounts.vesting_signer.to_account_info(),
+                account_or_mint: ctx.accounts.vault.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::set_authority(cpi_ctx, AuthorityType::AccountOwner, Some(new_signer))?;
+
+        // Safety checks.
+        ctx.accounts.vault.reload()?;
+        if ctx.accounts.vault.owner != new_signer {
+            return err!(ErrorCode::InvalidSignerRotation);
+        }
+
+        ctx.accounts.vesting.nonce = new_nonce;
+        emit!(VestingSignerRotated {
+            vesting: vesting_key,
+            vesting_signer: new_signer,
+            nonce: new_nonce,
+        });
+
+        Ok(())
+    }
+
+    // Blocks withdrawals from a vesting account, e.g., while funds held by a
+    // compromised whitelisted program are reconciled. Whitelist deposits
+    // remain allowed so that funds can be returned.
+    #[access_control(lockup_auth(&ctx.accounts.lockup, &ctx.accounts.authority))]
+    pub fn freeze_vesting(ctx: Context<AuthVesting>) -> Result<()> {
+        ctx.accounts.vesting.frozen = true;
+        Ok(())
+    }
+
+    #[access_control(lockup_auth(&ctx.accounts.lockup, &ctx.accounts.authority))]
+    pub fn thaw_vesting(ctx: Context<AuthVesting>) -> Result<()> {
+        ctx.accounts.vesting.frozen = false;
+        Ok(())
+    }
+
+    // Sets or clears how long, in seconds, funds may stay loaned to
+    // whitelisted programs. Takes effect from the next loan made while
+    // nothing is loaned out.
+    #[access_control(lockup_auth(&ctx.accounts.lockup, &ctx.accounts.authority))]
+    pub fn set_relay_deadline(
+        ctx: Context<AuthVesting>,
+        relay_deadline: Option<i64>,
+    ) -> Result<()> {
+        if matches!(relay_deadline, Some(seconds) if seconds < 0) {
+            return err!(ErrorCode::InvalidTimestamp);
+        }
+        ctx.accounts.vesting.relay_deadline = relay_deadline;
+        Ok(())
+    }
+
+    // Marks a vesting account delinquent once its loans to whitelisted
+    // programs are overdue, blocking further loans until everything is
+    // returned.
+    #[access_control(lockup_auth(&ctx.accounts.lockup, &ctx.accounts.authority))]
+    pub fn force_reconcile(ctx: Context<AuthVesting>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vesting = &mut ctx.accounts.vesting;
+        if vesting.loaned() == 0 || vesting.loan_due_ts == 0 || now <= vesting.loan_due_ts {
+            return err!(ErrorCode::RelayDeadlineNotPassed);
+        }
+        vesting.delinquent = true;
+
+        emit!(RelayDeadlineBreached {
+            vesting: *vesting.to_account_info().key,
+            whitelist_owned: vesting.whitelist_owned,
+            due_ts: vesting.loan_due_ts,
+        });
+
+        Ok(())
+    }
+
+    // Compares `outstanding` against the funds actually backing the vesting
+    // account, i.e., the vault and whitelist loans, emitting any difference.
+    // If `correct` is set, the difference is applied to both `outstanding`
+    // and `start_balance`, as with a deposit or revocation.
+    #[access_control(lockup_auth(&ctx.accounts.lockup, &ctx.accounts.authority))]
+    pub fn reconcile(ctx: Context<Reconcile>, correct: bool) -> Result<()> {
+        let vesting = &mut ctx.accounts.vesting;
+        let actual = ctx
+            .accounts
+            .vault
+            .amount
+            .checked_add(vesting.whitelist_owned)
+            .ok_or(ErrorCode::Overflow)?;
+        if actual == vesting.outstanding {
+            return Ok(());
+        }
+
+        emit!(ReconcileEvent {
+            vesting: *vesting.to_account_info().key,
+            outstanding: vesting.outstanding,
+            actual,
+            delta: (actual as i128 - vesting.outstanding as i128) as i64,
+            corrected: correct,
+        });
+
+        if correct {
+            is_mutable(vesting)?;
+            let lockup = &mut ctx.accounts.lockup;
+            if actual > vesting.outstanding {
+                let delta = actual - vesting.outstanding;
+                vesting.start_balance = vesting
+                    .start_balance
+                    .checked_add(delta)
+                    .ok_or(ErrorCode::Overflow)?;
+                lockup.total_outstanding = lockup
+                    .total_outstanding
+                    .checked_add(delta)
+                    .ok_or(ErrorCode::Overflow)?;
+            } else {
+                let delta = vesting.outstanding - actual;
+                vesting.start_balance = vesting
+                    .start_balance
+                    .checked_sub(delta)
+                    .ok_or(ErrorCode::Overflow)?;
+                lockup.total_outstanding = lockup
+                    .total_outstanding
+                    .checked_sub(delta)
+                    .ok_or(ErrorCode::Overflow)?;
+            }
+            vesting.outstanding = actual;
+        }
+
+        Ok(())
+    }
+
+    // Makes room for more whitelist entries, reallocating the state account
+    // and topping up its rent from the payer.
+    #[access_control(
+        is_whitelist_unlocked(&ctx.accounts.lockup)
+        lockup_auth(&ctx.accounts.lockup, &ctx.accounts.authority)
+    )]
+    pub fn grow_whitelist(ctx: Context<GrowWhitelist>, additional: u32) -> Result<()> {
+        let capacity = ctx
+            .accounts
+            .lockup
+            .whitelist_capacity
+            .checked_add(additional)
+            .ok_or(ErrorCode::Overflow)?;
+        if capacity as usize > Lockup::MAX_WHITELIST_SIZE {
+            return err!(ErrorCode::WhitelistTooLarge);
+        }
+
+        let lockup = ctx.accounts.lockup.to_account_info();
+        let entry_len = WhitelistEntry::default().try_to_vec()?.len();
+        let new_len = lockup
+            .data_len()
+            .checked_add(additional as usize * entry_len)
+            .ok_or(ErrorCode::Overflow)?;
+        let rent = Rent::get()?
+            .minimum_balance(new_len)
+            .saturating_sub(lockup.lamports());
+        if rent > 0 {
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.payer.to_account_info(),
+                    to: lockup.clone(),
+                },
+            );
+            system_program::transfer(cpi_ctx, rent)?;
+        }
+        lockup.realloc(new_len, false)?;
+
+        ctx.accounts.lockup.whitelist_capacity = capacity;
+
+        Ok(())
+    }
+
+    // Tears down the deployment, returning the state account's rent to the
+    // authority. Only allowed once the whitelist is empty and nothing is
+    // locked anymore, since every withdrawal needs the state account.
+    #[access_control(lockup_auth(&ctx.accounts.lockup, &ctx.accounts.authority))]
+    pub fn close_lockup(ctx: Context<CloseLockup>) -> Result<()> {
+        if !ctx.accounts.lockup.whitelist.is_empty() {
+            return err!(ErrorCode::WhitelistNotEmpty);
+        }
+        if ctx.accounts.lockup.total_outstanding != 0 {
+            return err!(ErrorCode::LockupNotEmpty);
+        }
+        Ok(())
+    }
+
+    // Upgrades a vesting account created with an older layout to the
+    // current `Vesting::VERSION`. Fields are only ever appended to `Vesting`,
+    // so upgrading reallocates the account, zero filling the new fields,
+    // whose zero values are the defaults. Per version:
+    //
+    // * 0 -> 1: adds the fields after `realizor`, i.e., no cliff, freeze,
+    //   throttle, co-beneficiary, delegate, memo, milestones or allowlist,
+    //   along with `version` itself.
+    // * 1 -> 2: adds `final_bps`, i.e., no backloading.
+    // * 2 -> 3: adds `revoke_notice_seconds` and `revoke_effective_ts`, i.e.,
+    //   revocation without notice.
+    // * 3 -> 4: adds `requires_grantor_approval`, i.e., no approval needed.
+    // * 4 -> 5: adds `relay_initiators`, i.e., only the beneficiary relays.
+    // * 5 -> 6: adds `immutable`, i.e., mutable terms.
+    // * 6 -> 7: adds `partially_revoked`, i.e., nothing revoked.
+    // * 7 -> 8: adds `schedule_kind`, i.e., the `Linear` schedule.
+    // * 8 -> 9: adds `withdraw_callback_program`, i.e., no callback.
+    // * 9 -> 10: adds `recovery_authority`, i.e., no recovery.
+    // * 10 -> 11: adds `relay_deadline`, `loan_due_ts` and `delinquent`,
+    //   i.e., open-ended loans.
+    // * 11 -> 12: adds `extra_vaults` and `extra_vault_count`, i.e., only
+    //   the primary vault.
+    // * 12 -> 13: adds `rounding`, i.e., rounding down.
+    // * 13 -> 14: adds the destination allowlist fields, i.e., withdrawals
+    //   to any token account.
+    // * 14 -> 15: adds `last_poke_ts`, i.e., never poked.
+    // * 15 -> 16: adds `curve_exponent`, i.e., no front-loaded curve.
+    // * 16 -> 17: adds `withdraw_count`, counting from the migration.
+    //
+    // Accounts created before versioning also predate the lockup's
+    // `total_outstanding` and the per program loan breakdown, so migrating
+    // one adds its `outstanding` to the total and attributes its
+    // `whitelist_owned` to `loan_program`, which is required if nonzero.
+    #[access_control(lockup_auth(&ctx.accounts.lockup, &ctx.accounts.authority))]
+    pub fn migrate_vesting(
+        ctx: Context<MigrateVesting>,
+        loan_program: Option<Pubkey>,
+    ) -> Result<()> {
+        let vesting = ctx.accounts.vesting.to_account_info();
+        if vesting.owner != ctx.program_id
+            || !vesting
+                .try_borrow_data()?
+                .starts_with(&Vesting::DISCRIMINATOR)
+        {
+            return err!(ErrorCode::InvalidVestingAccount);
+        }
+
+        let new_len = 8 + Vesting::INIT_SPACE;
+        if vesting.data_len() < new_len {
+            let rent = Rent::get()?
+                .minimum_balance(new_len)
+                .saturating_sub(vesting.lamports());
+            if rent > 0 {
+                let cpi_ctx = CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: vesting.clone(),
+                    },
+                );
+                system_program::transfer(cpi_ctx, rent)?;
+            }
+            vesting.realloc(new_len, true)?;
+        }
+
+        let mut account: Account<Vesting> = Account::try_from(&vesting)?;
+        if account.version >= Vesting::VERSION {
+            return err!(ErrorCode::VestingUpToDate);
+        }
+        if account.version == 0 {
+            account.migrate_baseline(loan_program)?;
+            let lockup = &mut ctx.accounts.lockup;
+            lockup.total_outstanding = lockup
+                .total_outstanding
+                .checked_add(account.outstanding)
+                .ok_or(ErrorCode::Overflow)?;
+        }
+        account.version = Vesting::VERSION;
+        account.exit(ctx.program_id)?;
+
+        Ok(())
+    }
+
+    // Sends funds from the lockup program to a whitelisted program.
+    #[access_control(is_not_frozen(&ctx.accounts.transfer.vesting))]
+    pub fn whitelist_withdraw<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, WhitelistWithdraw<'info>>,
+        instruction_data: Vec<u8>,
+        amount: u64,
+    ) -> Result<()> {
+        // Checks.
+        if ctx.accounts.transfer.vesting.delinquent {
+            return err!(ErrorCode::VestingDelinquent);
+        }
+        let program_id = *ctx.accounts.transfer.whitelisted_program.key;
+        let max_amount = ctx
+            .accounts
+            .transfer
+            .lockup
+            .whitelist
+            .iter()
+            .find(|e| e.program_id == program_id)
+            .map(|e| e.max_amount)
+            .ok_or(ErrorCode::WhitelistEntryNotFound)?;
+
+        // Effects. Up to `amount` of the vault counts as loaned before the
+        // whitelisted program gets control, and is persisted along with the
+        // relay guard, so that the vault is never observed without its loan.
+        // It's trued up to what actually left the vault afterwards.
+        let before_amount = ctx.accounts.transfer.vault.amount;
+        let reserved = std::cmp::min(amount, before_amount);
+        let vesting = &mut ctx.accounts.transfer.vesting;
+        vesting.whitelist_owned = vesting
+            .whitelist_owned
+            .checked_add(reserved)
+            .ok_or(ErrorCode::Overflow)?;
+
+        // Interactions.
+        whitelist_relay_cpi(
+            &mut ctx.accounts.transfer,
+            ctx.remaining_accounts,
+            instruction_data,
+        )?;
+        ctx.accounts.transfer.vault.reload()?;
+        let after_amount = ctx.accounts.transfer.vault.amount;
+
+        // CPI safety checks.
+        let withdraw_amount = before_amount
+            .checked_sub(after_amount)
+            .ok_or(ErrorCode::Overflow)?;
+        if withdraw_amount > amount {
+            return err!(ErrorCode::WhitelistWithdrawLimit);
+        }
+
+        // Bookeeping.
+        let vesting = &mut ctx.accounts.transfer.vesting;
+        vesting.whitelist_owned = vesting
+            .whitelist_owned
+            .checked_sub(reserved)
+            .ok_or(ErrorCode::Overflow)?;
+        // The first loan starts the countdown to the relay deadline.
+        if let (Some(seconds), 0) = (vesting.relay_deadline, vesting.whitelist_owned) {
+            if withdraw_amount > 0 {
+                vesting.loan_due_ts = Clock::get()?
+                    .unix_timestamp
+                    .checked_add(seconds)
+                    .ok_or(ErrorCode::Overflow)?;
+            }
+        }
+        vesting.whitelist_owned = vesting
+            .whitelist_owned
+            .checked_add(withdraw_amount)
+            .ok_or(ErrorCode::Overflow)?;
+        let loan = vesting.whitelist_loan_mut(&program_id)?;
+        loan.amount = loan
+            .amount
+            .checked_add(withdraw_amount)
+            .ok_or(ErrorCode::Overflow)?;
+        if loan.amount > max_amount {
+            return err!(ErrorCode::WhitelistEntryWithdrawLimit);
+        }
+
+        emit!(WhitelistBalanceChanged {
+            vesting: *vesting.to_account_info().key,
+            program: program_id,
+            whitelist_owned: vesting.whitelist_owned,
+        });
+        if vesting.loan_due_ts != 0 && withdraw_amount > 0 {
+            emit!(RelayLoaned {
+                vesting: *vesting.to_account_info().key,
+                program: program_id,
+                amount: withdraw_amount,
+                due_ts: vesting.loan_due_ts,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Sends funds from a whitelisted program back to the lockup program. If
+    // an `expected_amount` is given, the amount actually returned must be
+    // within `tolerance` of it, as a check on the relayed program.
+    pub fn whitelist_deposit<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, WhitelistDeposit<'info>>,
+        instruction_data: Vec<u8>,
+        expected_amount: Option<u64>,
+        tolerance: u64,
+    ) -> Result<()> {
+        let before_amount = ctx.accounts.transfer.vault.amount;
+        whitelist_relay_cpi(
+            &mut ctx.accounts.transfer,
+            ctx.remaining_accounts,
+            instruction_data,
+        )?;
+        ctx.accounts.transfer.vault.reload()?;
+        let after_amount = ctx.accounts.transfer.vault.amount;
+
+        // CPI safety checks.
+        if ctx.accounts.transfer.vault.mint != ctx.accounts.transfer.vesting.mint {
+            return err!(ErrorCode::MintMismatch);
+        }
+        // The vault must have grown, which also rejects a relayed program
+        // taking funds out instead.
+        let deposit_amount = match after_amount.checked_sub(before_amount) {
+            Some(amount) if amount > 0 => amount,
+            _ => return err!(ErrorCode::InsufficientWhitelistDepositAmount),
+        };
+        if deposit_amount > ctx.accounts.transfer.vesting.loaned() {
+            return err!(ErrorCode::WhitelistDepositOverflow)?;
+        }
+        if let Some(expected_amount) = expected_amount {
+            if deposit_amount.abs_diff(expected_amount) > tolerance {
+                return err!(ErrorCode::UnexpectedDepositAmount);
+            }
+        }
+
+        // Bookkeeping.
+        let program_id = *ctx.accounts.transfer.whitelisted_program.key;
+        let vesting = &mut ctx.accounts.transfer.vesting;
+        vesting.repay_loan(&program_id, deposit_amount)?;
+        // Everything's back, so there's nothing left to be late with.
+        if vesting.whitelist_owned == 0 {
+            vesting.loan_due_ts = 0;
+            vesting.delinquent = false;
+        }
+
+        emit!(WhitelistBalanceChanged {
+            vesting: *vesting.to_account_info().key,
+            program: program_id,
+            whitelist_owned: vesting.whitelist_owned,
+        });
+
+        Ok(())
+    }
+
+    // Convenience function for UI's to calculate the withdrawable amount.
+    // The amount is returned via return data, e.g., by simulating the
+    // transaction.
+    pub fn available_for_withdrawal(ctx: Context<AvailableForWithdrawal>) -> Result<u64> {
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        let available = ctx.accounts.vesting.available(now);
+        // Deprecated: log as string so that older JS clients can read as a BN.
+        msg!(&format!("{{ \"result\": \"{}\" }}", available));
+        Ok(available)
+    }
+
+    // Same as `available_for_withdrawal`, but also returns the amount scaled
+    // by the mint's decimals for display.
+    pub fn available_for_withdrawal_ui(
+        ctx: Context<AvailableForWithdrawalUi>,
+    ) -> Result<UiWithdrawableAmount> {
+        if ctx.accounts.mint.to_account_info().key != &ctx.accounts.vesting.mint {
+            return err!(ErrorCode::MintMismatch);
+        }
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        let amount = ctx.accounts.vesting.available(now);
+        let decimals = ctx.accounts.mint.decimals;
+        Ok(UiWithdrawableAmount {
+            amount,
+            decimals,
+            ui_amount: token::spl_token::amount_to_ui_amount_string_trimmed(amount, decimals),
+        })
+    }
+
+    // Convenience function for UI's to display the vesting schedule without
+    // replicating the calculator.
+    pub fn vesting_schedule(ctx: Context<ViewVesting>) -> Result<VestingSchedule> {
+        Ok(calculator::schedule(
+            &ctx.accounts.vesting,
+            current_ts(&ctx.accounts.lockup, &ctx.accounts.clock),
+        ))
+    }
+
+    // Returns the vesting signer, i.e., the program derived address owning
+    // the vault, so that clients don't need to replicate its seeds.
+    pub fn vesting_signer_address(ctx: Context<VestingSignerAddress>) -> Result<SignerAddress> {
+        let vesting = &ctx.accounts.vesting;
+        let address = Pubkey::create_program_address(
+            &[vesting.to_account_info().key.as_ref(), &[vesting.nonce]],
+            ctx.program_id,
+        )
+        .map_err(|_| error!(ErrorCode::InvalidProgramAddress))?;
+        Ok(SignerAddress {
+            address,
+            bump: vesting.nonce,
+        })
+    }
+
+    // Previews the effect of withdrawing the given amount, without
+    // transferring anything. Realization isn't checked.
+    #[access_control(is_not_frozen(&ctx.accounts.vesting))]
+    pub fn simulate_withdraw(
+        ctx: Context<ViewVesting>,
+        amount: u64,
+    ) -> Result<SimulatedWithdrawal> {
+        let vesting = &ctx.accounts.vesting;
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        if calculator::is_withdraw_throttled(vesting, now) {
+            return err!(ErrorCode::WithdrawTooSoon);
+        }
+        let available = vesting.available(now);
+        if amount > available {
+            return err!(ErrorCode::InsufficientWithdrawalBalance);
+        }
+        Ok(SimulatedWithdrawal {
+            new_outstanding: vesting
+                .outstanding
+                .checked_sub(amount)
+                .ok_or(ErrorCode::Overflow)?,
+            remaining_available: available - amount,
+        })
+    }
+
+    // Preflight check for a withdrawal, returning whether the given amount
+    // could be withdrawn right now along with the amount available, instead
+    // of failing. As with `simulate_withdraw`, realization isn't checked.
+    pub fn can_withdraw(ctx: Context<ViewVesting>, amount: u64) -> Result<WithdrawCheck> {
+        let vesting = &ctx.accounts.vesting;
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        let available = vesting.available(now);
+        Ok(WithdrawCheck {
+            allowed: !vesting.frozen
+                && !calculator::is_withdraw_throttled(vesting, now)
+                && amount <= available,
+            available,
+        })
+    }
+
+    // Convenience function for UI's to display when, and how much, the
+    // vesting account next unlocks.
+    pub fn next_unlock(ctx: Context<ViewVesting>) -> Result<NextUnlock> {
+        Ok(calculator::next_unlock(
+            &ctx.accounts.vesting,
+            current_ts(&ctx.accounts.lockup, &ctx.accounts.clock),
+        ))
+    }
+
+    // Convenience function for UI's to chart the vesting schedule, returning
+    // the total amount vested at any timestamp, past or future.
+    pub fn vested_at(ctx: Context<ViewVesting>, ts: i64) -> Result<u64> {
+        Ok(calculator::vested_at(&ctx.accounts.vesting, ts))
+    }
+
+    // Convenience function for UI's to display how much of the vesting
+    // account has vested, in basis points.
+    pub fn vested_bps(ctx: Context<ViewVesting>) -> Result<u64> {
+        Ok(calculator::vested_bps(
+            &ctx.accounts.vesting,
+            current_ts(&ctx.accounts.lockup, &ctx.accounts.clock),
+        ))
+    }
+
+    // Permissionless. Emits a `PeriodUnlocked` event if more has vested
+    // since the last poke, so that indexers can follow each unlock without
+    // polling. Does nothing otherwise, so poking again is harmless.
+    pub fn poke(ctx: Context<Poke>) -> Result<()> {
+        let now = current_ts(&ctx.accounts.lockup, &ctx.accounts.clock);
+        let vesting = &mut ctx.accounts.vesting;
+        let amount = calculator::unlocked_since(vesting, vesting.last_poke_ts, now);
+        if amount == 0 {
+            return Ok(());
+        }
+        vesting.last_poke_ts = now;
+        emit!(PeriodUnlocked {
+            vesting: vesting.key(),
+            amount,
+            available: vesting.available(now),
+            ts: now,
+        });
+        Ok(())
+    }
+
+    // Convenience function for wallets to read the most-used fields of a
+    // vesting account without fetching and deserializing all of it.
+    pub fn vesting_summary(ctx: Context<ViewVesting>) -> Result<VestingSummary> {
+        let vesting = &ctx.accounts.vesting;
+        Ok(VestingSummary {
+            beneficiary: vesting.beneficiary,
+            outstanding: vesting.outstanding,
+            available: vesting.available(current_ts(&ctx.accounts.lockup, &ctx.accounts.clock)),
+            end_ts: vesting.end_ts,
+            withdraw_count: vesting.withdraw_count,
+        })
+    }
+
+    // Convenience function for UI's to count down to the end of the
+    // schedule, in seconds. Zero once fully vested.
+    pub fn time_to_full_vest(ctx: Context<ViewVesting>) -> Result<u64> {
+        Ok(calculator::time_to_full_vest(
+            &ctx.accounts.vesting,
+            current_ts(&ctx.accounts.lockup, &ctx.accounts.clock),
+        ))
+    }
+
+    // Convenience function for clients to check a program can be relayed to
+    // before sending a transaction.
+    pub fn is_program_whitelisted(ctx: Context<ViewLockup>, program_id: Pubkey) -> Result<bool> {
+        Ok(whitelist_contains(&ctx.accounts.lockup, &program_id))
+    }
+
+    // Convenience function for dashboards to display a vesting account's
+    // exposure to a whitelisted program. Zero if it holds nothing.
+    pub fn loaned_to_program(ctx: Context<ViewVesting>, program_id: Pubkey) -> Result<u64> {
+        Ok(ctx.accounts.vesting.loaned_to(&program_id))
+    }
+
+    // Convenience function for dashboards to display the amount a grantor
+    // has committed, i.e., granted less revoked, across tallied grants.
+    pub fn grantor_committed(ctx: Context<ViewGrantorTally>) -> Result<u64> {
+        let tally = &ctx.accounts.grantor_tally;
+        Ok(tally.total_granted.saturating_sub(tally.total_revoked))
+    }
+
+    // Convenience function for dashboards to display the total amount
+    // locked across all vesting accounts.
+    pub fn total_outstanding(ctx: Context<ViewLockup>) -> Result<u64> {
+        Ok(ctx.accounts.lockup.total_outstanding)
+    }
+
+    // Convenience function for clients to read the whitelist without
+    // deserializing the whole lockup state. Returns at most `limit` entries
+    // starting at `offset`, fewer at the end of the whitelist.
+    pub fn get_whitelist_page(
+        ctx: Context<ViewLockup>,
+        offset: u8,
+        limit: u8,
+    ) -> Result<Vec<WhitelistEntry>> {
+        let whitelist = &ctx.accounts.lockup.whitelist;
+        let offset = offset as usize;
+        if offset > whitelist.len() || limit == 0 || limit > MAX_WHITELIST_PAGE {
+            return err!(ErrorCode::InvalidWhitelistPage);
+        }
+        let end = whitelist.len().min(offset + limit as usize);
+        Ok(whitelist[offset..end].to_vec())
+    }
+
+    // Convenience function for clients to compute the `period_count` of a
+    // schedule unlocking at the given frequency, e.g., monthly over 4 years.
+    pub fn periods_for(
+        _ctx: Context<PeriodsFor>,
+        start_ts: i64,
+        end_ts: i64,
+        frequency: Frequency,
+    ) -> Result<u64> {
+        if end_ts <= start_ts {
+            return err!(ErrorCode::InvalidTimestamp);
+        }
+        Ok(calculator::periods_for(start_ts, end_ts, frequency))
+    }
+}
+
+#[derive(Accounts)]
+pub struct Auth<'info> {
+    #[account(signer)]
+    authority: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateVesting<'info> {
+    // Vesting.
+    #[account(zero)]
+    pub vesting: Account<'info, Vesting>,
+    #[account(mut, rent_exempt = enforce)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    // Depositor.
+    #[account(mut, constraint = depositor.mint == vault.mint @ ErrorCode::MintMismatch)]
+    pub depositor: InterfaceAccount<'info, TokenAccount>,
+    #[account(signer)]
+    pub depositor_authority: AccountInfo<'info>,
+    // Misc.
+    #[account(constraint = is_token_program(token_program.key))]
+    pub token_program: AccountInfo<'info>,
+    pub clock: Sysvar<'info, Clock>,
+    // Lockup policy.
+    #[account(mut)]
+    pub lockup: ProgramState<'info, Lockup>,
+    #[account(constraint = mint.key() == vault.mint @ ErrorCode::MintMismatch)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    // Grantor accounting, if the grantor keeps a tally.
+    #[account(mut)]
+    pub grantor_tally: Option<Account<'info, GrantorTally>>,
+}
+
+impl<'info> CreateVesting<'info> {
+    fn accounts(ctx: &Context<CreateVesting>) -> Result<u8> {
+        vault_nonce(
+            ctx.accounts.vesting.to_account_info().key,
+            &ctx.accounts.vault,
+            ctx.program_id,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct CreateVestingIdempotent<'info> {
+    // Vesting. Signs only when it's being allocated.
+    #[account(init_if_needed, payer = payer, space = 8 + Vesting::INIT_SPACE)]
+    pub vesting: Account<'info, Vesting>,
+    #[account(mut, rent_exempt = enforce)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    // Depositor.
+    #[account(mut, constraint = depositor.mint == vault.mint @ ErrorCode::MintMismatch)]
+    pub depositor: InterfaceAccount<'info, TokenAccount>,
+    #[account(signer)]
+    pub depositor_authority: AccountInfo<'info>,
+    // Misc.
+    #[account(constraint = is_token_program(token_program.key))]
+    pub token_program: AccountInfo<'info>,
+    pub clock: Sysvar<'info, Clock>,
+    // Lockup policy.
+    #[account(mut)]
+    pub lockup: ProgramState<'info, Lockup>,
+    #[account(constraint = mint.key() == vault.mint @ ErrorCode::MintMismatch)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    // Funds the vesting account's rent.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    #[account(mut)]
+    pub grantor_tally: Option<Account<'info, GrantorTally>>,
+}
+
+#[derive(Accounts)]
+pub struct CreateVestingDelegated<'info> {
+    // Vesting.
+    #[account(zero)]
+    pub vesting: Account<'info, Vesting>,
+    #[account(mut, rent_exempt = enforce)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+    // Owns the vault, and is the depositor's delegate.
+    #[account(constraint = vesting_signer.key() == vault.owner @ ErrorCode::InvalidVaultOwner)]
+    pub vesting_signer: AccountInfo<'info>,
+    // Depositor. Its owner becomes the grantor.
+    #[account(mut, constraint = depositor.mint == vault.mint @ ErrorCode::MintMismatch)]
+    pub depositor: InterfaceAccount<'info, TokenAccount>,
+    #[account(constraint = depositor_authority.key() == depositor.owner @ ErrorCode::Unauthorized)]
+    pub depositor_authority: Signer<'info>,
+    // Misc.
+    #[account(constraint = is_token_program(token_program.key))]
+    pub token_program: AccountInfo<'info>,
+    pub clock: Sysvar<'info, Clock>,
+    // Lockup policy.
+    #[account(mut)]
+    pub lockup: ProgramState<'info, Lockup>,
+    #[account(constraint = mint.key() == vault.mint @ ErrorCode::MintMismatch)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub grantor_tally: Option<Account<'info, GrantorTally>>,
+}
+
+#[derive(Accounts)]
+pub struct InitGrantorTally<'info> {
+    #[account(
+        init,
+        payer = grantor,
+        space = 8 + GrantorTally::INIT_SPACE,
+        seeds = [GRANTOR_TALLY_SEED, grantor.key().as_ref()],
+        bump,
+    )]
+    grantor_tally: Account<'info, GrantorTally>,
+    #[account(mut)]
+    grantor: Signer<'info>,
+    system_program: Program<'info, System>,
+}
+
+// Returns the canonical bump of the vesting signer, which must own the
+// initially empty vault.
+fn vault_nonce(
+    vesting: &Pubkey,
+    vault: &InterfaceAccount<TokenAccount>,
+    program_id: &Pubkey,
+) -> Result<u8> {
+    let (vault_authority, nonce) = Pubkey::find_program_address(&[vesting.as_ref()], program_id);
+    if vault.owner != vault_authority {
+        return err!(ErrorCode::InvalidVaultOwner)?;
+    }
+    // Otherwise `outstanding` wouldn't match the vault's balance.
+    if vault.amount != 0 {
+        return err!(ErrorCode::InvalidVaultAmount);
+    }
+
+    Ok(nonce)
+}
+
+// All accounts not included here, i.e., the "remaining accounts" should be
+// ordered according to the realization interface.
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    // Vesting.
+    #[account(mut, has_one = beneficiary, has_one = vault, has_one = mint)]
+    vesting: Account<'info, Vesting>,
+    // Signs, unless the withdrawal is signed by the withdraw delegate.
+    beneficiary: AccountInfo<'info>,
+    #[account(mut)]
+    vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        seeds = [vesting.to_account_info().key.as_ref()],
+        bump = vesting.nonce,
+    )]
+    vesting_signer: AccountInfo<'info>,
+    // Withdraw receiving target..
+    #[account(mut, constraint = token.mint == vesting.mint @ ErrorCode::MintMismatch)]
+    token: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    // Misc.
+    #[account(constraint = is_token_program(token_program.key))]
+    token_program: AccountInfo<'info>,
+    clock: Sysvar<'info, Clock>,
+    // Required if the vesting account has a co-beneficiary.
+    co_beneficiary: Option<Signer<'info>>,
+    // Signs in place of the beneficiary, if set on the vesting account.
+    withdraw_delegate: Option<Signer<'info>>,
+    #[account(mut)]
+    lockup: ProgramState<'info, Lockup>,
+    // Required if the lockup charges a withdrawal fee.
+    #[account(mut)]
+    fee_treasury: Option<InterfaceAccount<'info, TokenAccount>>,
+    // Required if the vesting account requires grantor approval.
+    grantor: Option<Signer<'info>>,
+    // Required if the vesting account has a withdraw callback.
+    withdraw_callback_program: Option<AccountInfo<'info>>,
+}
+
+// All vesting accounts are given as remaining accounts.
+#[derive(Accounts)]
+pub struct WithdrawMany<'info> {
+    beneficiary: Signer<'info>,
+    // The mint of every vesting account withdrawn from.
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = is_token_program(token_program.key))]
+    token_program: AccountInfo<'info>,
+    clock: Sysvar<'info, Clock>,
+    #[account(mut)]
+    lockup: ProgramState<'info, Lockup>,
+}
+
+#[derive(Accounts)]
+pub struct DepositToVesting<'info> {
+    // Vesting.
+    #[account(mut, has_one = vault)]
+    vesting: Account<'info, Vesting>,
+    #[account(mut)]
+    vault: InterfaceAccount<'info, TokenAccount>,
+    // Depositor.
+    #[account(mut)]
+    depositor: AccountInfo<'info>,
+    #[account(signer)]
+    depositor_authority: AccountInfo<'info>,
+    #[account(constraint = mint.key() == vault.mint @ ErrorCode::MintMismatch)]
+    mint: InterfaceAccount<'info, Mint>,
+    // Misc.
+    #[account(constraint = is_token_program(token_program.key))]
+    token_program: AccountInfo<'info>,
+    #[account(mut)]
+    lockup: ProgramState<'info, Lockup>,
+}
+
+#[derive(Accounts)]
+pub struct AddVault<'info> {
+    #[account(mut)]
+    vesting: Account<'info, Vesting>,
+    // Funds the new vault.
+    grantor: Signer<'info>,
+    // The new vault, initially empty and owned by the vesting signer.
+    #[account(mut, rent_exempt = enforce)]
+    vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = depositor.mint == vault.mint @ ErrorCode::MintMismatch)]
+    depositor: InterfaceAccount<'info, TokenAccount>,
+    #[account(constraint = mint.key() == vault.mint @ ErrorCode::MintMismatch)]
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = is_token_program(token_program.key))]
+    token_program: AccountInfo<'info>,
+    #[account(mut)]
+    lockup: ProgramState<'info, Lockup>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFromVault<'info> {
+    #[account(mut, has_one = beneficiary)]
+    vesting: Account<'info, Vesting>,
+    beneficiary: Signer<'info>,
+    // One of the vesting account's extra vaults.
+    #[account(mut)]
+    vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        seeds = [vesting.to_account_info().key.as_ref()],
+        bump = vesting.nonce,
+    )]
+    vesting_signer: AccountInfo<'info>,
+    #[account(mut, constraint = token.mint == vault.mint @ ErrorCode::MintMismatch)]
+    token: InterfaceAccount<'info, TokenAccount>,
+    #[account(constraint = mint.key() == vault.mint @ ErrorCode::MintMismatch)]
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = is_token_program(token_program.key))]
+    token_program: AccountInfo<'info>,
+    clock: Sysvar<'info, Clock>,
+    // Required if the vesting account has a co-beneficiary.
+    co_beneficiary: Option<Signer<'info>>,
+    // Required if the vesting account requires grantor approval.
+    grantor: Option<Signer<'info>>,
+    #[account(mut)]
+    lockup: ProgramState<'info, Lockup>,
+}
+
+#[derive(Accounts)]
+pub struct Revoke<'info> {
+    // Vesting. The grantor is checked by `grantor_auth`.
+    #[account(mut, has_one = vault)]
+    vesting: Account<'info, Vesting>,
+    grantor: Signer<'info>,
+    #[account(mut)]
+    vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        seeds = [vesting.to_account_info().key.as_ref()],
+        bump = vesting.nonce,
+    )]
+    vesting_signer: AccountInfo<'info>,
+    // Revoke receiving target.
+    #[account(mut)]
+    token: InterfaceAccount<'info, TokenAccount>,
+    #[account(constraint = mint.key() == vault.mint @ ErrorCode::MintMismatch)]
+    mint: InterfaceAccount<'info, Mint>,
+    // Misc.
+    #[account(constraint = is_token_program(token_program.key))]
+    token_program: AccountInfo<'info>,
+    clock: Sysvar<'info, Clock>,
+    #[account(mut)]
+    lockup: ProgramState<'info, Lockup>,
+    // Grantor accounting, if the grantor keeps a tally.
+    #[account(mut)]
+    grantor_tally: Option<Account<'info, GrantorTally>>,
+}
+
+#[derive(Accounts)]
+pub struct Accelerate<'info> {
+    #[account(mut)]
+    vesting: Account<'info, Vesting>,
+    grantor: Signer<'info>,
+    clock: Sysvar<'info, Clock>,
+    lockup: ProgramState<'info, Lockup>,
+}
+
+#[derive(Accounts)]
+pub struct SplitVesting<'info> {
+    #[account(mut, has_one = beneficiary, has_one = vault, has_one = mint)]
+    vesting: Account<'info, Vesting>,
+    beneficiary: Signer<'info>,
+    #[account(mut)]
+    vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        seeds = [vesting.to_account_info().key.as_ref()],
+        bump = vesting.nonce,
+    )]
+    vesting_signer: AccountInfo<'info>,
+    // The new vesting account and its empty vault, owned by its signer.
+    #[account(zero)]
+    new_vesting: Account<'info, Vesting>,
+    #[account(mut, rent_exempt = enforce, constraint = new_vault.mint == vesting.mint @ ErrorCode::MintMismatch)]
+    new_vault: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = is_token_program(token_program.key))]
+    token_program: AccountInfo<'info>,
+    clock: Sysvar<'info, Clock>,
+    lockup: ProgramState<'info, Lockup>,
+    // Required if the vesting account has a co-beneficiary.
+    co_beneficiary: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct Recover<'info> {
+    #[account(
+        mut,
+        has_one = vault,
+        has_one = mint,
+        constraint = vesting.recovery_authority == Some(recovery_authority.key()) @ ErrorCode::Unauthorized,
+    )]
+    vesting: Account<'info, Vesting>,
+    recovery_authority: Signer<'info>,
+    #[account(mut)]
+    vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        seeds = [vesting.to_account_info().key.as_ref()],
+        bump = vesting.nonce,
+    )]
+    vesting_signer: AccountInfo<'info>,
+    // Any token account of the grantor's.
+    #[account(
+        mut,
+        constraint = destination.owner == vesting.grantor @ ErrorCode::InvalidRecoveryDestination,
+        constraint = destination.mint == vesting.mint @ ErrorCode::MintMismatch,
+    )]
+    destination: InterfaceAccount<'info, TokenAccount>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = is_token_program(token_program.key))]
+    token_program: AccountInfo<'info>,
+    clock: Sysvar<'info, Clock>,
+    #[account(mut)]
+    lockup: ProgramState<'info, Lockup>,
+    // Grantor accounting, if the grantor keeps a tally.
+    #[account(mut)]
+    grantor_tally: Option<Account<'info, GrantorTally>>,
+}
+
+#[derive(Accounts)]
+pub struct SetBeneficiary<'info> {
+    #[account(mut, has_one = beneficiary)]
+    vesting: Account<'info, Vesting>,
+    beneficiary: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetCoBeneficiary<'info> {
+    #[account(mut, has_one = beneficiary)]
+    vesting: Account<'info, Vesting>,
+    beneficiary: Signer<'info>,
+    // Required if the vesting account already has a co-beneficiary.
+    co_beneficiary: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct SetWithdrawCallback<'info> {
+    #[account(mut, has_one = beneficiary)]
+    vesting: Account<'info, Vesting>,
+    beneficiary: Signer<'info>,
+    lockup: ProgramState<'info, Lockup>,
+}
+
+#[derive(Accounts)]
+pub struct SetDestinationAllowlist<'info> {
+    #[account(mut, has_one = beneficiary)]
+    vesting: Account<'info, Vesting>,
+    beneficiary: Signer<'info>,
+    clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct SetProgramAllowlist<'info> {
+    #[account(mut)]
+    vesting: Account<'info, Vesting>,
+    // Either the beneficiary or the grantor.
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseVesting<'info> {
+    // Vesting.
+    #[account(mut, has_one = beneficiary, has_one = vault, close = beneficiary)]
     vesting: Account<'info, Vesting>,
+    #[account(mut)]
     beneficiary: Signer<'info>,
     #[account(mut)]
-    vault: Account<'info, TokenAccount>,
+    vault: InterfaceAccount<'info, TokenAccount>,
     #[account(
         seeds = [vesting.to_account_info().key.as_ref()],
         bump = vesting.nonce,
     )]
     vesting_signer: AccountInfo<'info>,
-    // Withdraw receiving target..
+    // Misc.
+    #[account(constraint = is_token_program(token_program.key))]
+    token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateVault<'info> {
+    // Vesting.
+    #[account(mut, has_one = beneficiary, has_one = vault)]
+    vesting: Account<'info, Vesting>,
+    beneficiary: Signer<'info>,
+    #[account(mut)]
+    vault: InterfaceAccount<'info, TokenAccount>,
     #[account(mut)]
-    token: Account<'info, TokenAccount>,
+    new_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        seeds = [vesting.to_account_info().key.as_ref()],
+        bump = vesting.nonce,
+    )]
+    vesting_signer: AccountInfo<'info>,
+    #[account(constraint = mint.key() == vault.mint @ ErrorCode::MintMismatch)]
+    mint: InterfaceAccount<'info, Mint>,
     // Misc.
-    #[account(constraint = token_program.key == &token::ID)]
+    #[account(constraint = is_token_program(token_program.key))]
     token_program: AccountInfo<'info>,
-    clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct RotateSigner<'info> {
+    lockup: ProgramState<'info, Lockup>,
+    authority: Signer<'info>,
+    #[account(mut, has_one = vault)]
+    vesting: Account<'info, Vesting>,
+    #[account(mut)]
+    vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        seeds = [vesting.to_account_info().key.as_ref()],
+        bump = vesting.nonce,
+    )]
+    vesting_signer: AccountInfo<'info>,
+    // Checked against the new nonce by the instruction.
+    new_vesting_signer: AccountInfo<'info>,
+    // Misc.
+    #[account(constraint = is_token_program(token_program.key))]
+    token_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Reconcile<'info> {
+    #[account(mut)]
+    lockup: ProgramState<'info, Lockup>,
+    authority: Signer<'info>,
+    #[account(mut, has_one = vault)]
+    vesting: Account<'info, Vesting>,
+    vault: InterfaceAccount<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct GrowWhitelist<'info> {
+    #[account(mut)]
+    lockup: ProgramState<'info, Lockup>,
+    authority: Signer<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseLockup<'info> {
+    #[account(mut, close = authority)]
+    lockup: ProgramState<'info, Lockup>,
+    #[account(mut)]
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateVesting<'info> {
+    #[account(mut)]
+    lockup: ProgramState<'info, Lockup>,
+    authority: Signer<'info>,
+    // Not an `Account`, since old layouts don't deserialize.
+    #[account(mut)]
+    vesting: AccountInfo<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    system_program: Program<'info, System>,
+}
+
+// Accounts for the lockup authority to manage a vesting account.
+#[derive(Accounts)]
+pub struct AuthVesting<'info> {
+    lockup: ProgramState<'info, Lockup>,
+    authority: Signer<'info>,
+    #[account(mut)]
+    vesting: Account<'info, Vesting>,
 }
 
 #[derive(Accounts)]
@@ -280,20 +2687,31 @@ pub struct WhitelistDeposit<'info> {
 #[derive(Accounts)]
 pub struct WhitelistTransfer<'info> {
     lockup: ProgramState<'info, Lockup>,
+    // The beneficiary, or one of the vesting account's relay initiators.
     beneficiary: Signer<'info>,
+    #[account(
+        constraint = whitelisted_program.executable @ ErrorCode::WhitelistedProgramNotExecutable,
+    )]
     whitelisted_program: AccountInfo<'info>,
 
     // Whitelist interface.
-    #[account(mut, has_one = beneficiary, has_one = vault)]
+    #[account(
+        mut,
+        constraint = vesting.is_relay_initiator(beneficiary.key) @ ErrorCode::Unauthorized,
+        has_one = vault @ ErrorCode::VaultVestingMismatch,
+    )]
     vesting: Account<'info, Vesting>,
-    #[account(mut, constraint = &vault.owner == vesting_signer.key)]
-    vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = &vault.owner == vesting_signer.key @ ErrorCode::VaultSignerMismatch,
+    )]
+    vault: InterfaceAccount<'info, TokenAccount>,
     #[account(
         seeds = [vesting.to_account_info().key.as_ref()],
         bump = vesting.nonce,
     )]
     vesting_signer: AccountInfo<'info>,
-    #[account("token_program.key == &token::ID")]
+    #[account(constraint = is_token_program(token_program.key))]
     token_program: AccountInfo<'info>,
     #[account(mut)]
     whitelisted_program_vault: AccountInfo<'info>,
@@ -304,9 +2722,67 @@ pub struct WhitelistTransfer<'info> {
 pub struct AvailableForWithdrawal<'info> {
     vesting: Account<'info, Vesting>,
     clock: Sysvar<'info, Clock>,
+    lockup: ProgramState<'info, Lockup>,
+}
+
+#[derive(Accounts)]
+pub struct AvailableForWithdrawalUi<'info> {
+    vesting: Account<'info, Vesting>,
+    mint: InterfaceAccount<'info, Mint>,
+    clock: Sysvar<'info, Clock>,
+    lockup: ProgramState<'info, Lockup>,
+}
+
+// Accounts for read only instructions returning vesting account state.
+#[derive(Accounts)]
+pub struct VestingSignerAddress<'info> {
+    vesting: Account<'info, Vesting>,
+}
+
+#[derive(Accounts)]
+pub struct Poke<'info> {
+    #[account(mut)]
+    vesting: Account<'info, Vesting>,
+    clock: Sysvar<'info, Clock>,
+    lockup: ProgramState<'info, Lockup>,
+}
+
+#[derive(Accounts)]
+pub struct ViewVesting<'info> {
+    vesting: Account<'info, Vesting>,
+    clock: Sysvar<'info, Clock>,
+    lockup: ProgramState<'info, Lockup>,
+}
+
+#[derive(Accounts)]
+pub struct PeriodsFor {}
+
+#[derive(Accounts)]
+pub struct ViewLockup<'info> {
+    lockup: ProgramState<'info, Lockup>,
+}
+
+#[derive(Accounts)]
+pub struct ViewGrantorTally<'info> {
+    grantor_tally: Account<'info, GrantorTally>,
+}
+
+/// Running totals of a grantor's grants, at the PDA of `GRANTOR_TALLY_SEED`
+/// and the grantor. Only creations and revocations passing it are counted.
+#[account]
+#[derive(InitSpace)]
+pub struct GrantorTally {
+    /// The grantor whose grants are tallied.
+    pub grantor: Pubkey,
+    /// The sum of deposits of vesting accounts created by the grantor.
+    pub total_granted: u64,
+    /// The sum of unvested amounts the grantor has revoked.
+    pub total_revoked: u64,
+    pub bump: u8,
 }
 
 #[account]
+#[derive(InitSpace)]
 pub struct Vesting {
     /// The owner of this Vesting account.
     pub beneficiary: Pubkey,
@@ -342,27 +2818,624 @@ pub struct Vesting {
     /// cannot receive the tokens until unstaking. As a result, if one never
     /// unstakes, one would never actually receive the locked tokens.
     pub realizor: Option<Realizor>,
+    /// The time before which nothing is vested. Once reached, everything
+    /// the linear schedule would have unlocked by then becomes available.
+    pub cliff_ts: i64,
+    /// True if the grantor can reclaim unvested tokens.
+    pub revocable: bool,
+    /// Per program breakdown of `whitelist_owned`.
+    pub whitelist_loans: [WhitelistLoan; MAX_WHITELIST_LOANS],
+    /// Determines which period unlocks the remainder of dividing the
+    /// balance into periods.
+    pub period_rounding: PeriodRounding,
+    /// True if the lockup authority has blocked withdrawals.
+    pub frozen: bool,
+    /// True while a whitelist relay is in progress. Guards against the
+    /// whitelisted program re-entering the lockup program.
+    pub in_relay: bool,
+    /// The minimum number of seconds between withdrawals. Zero disables the
+    /// throttle.
+    pub min_withdraw_interval: i64,
+    /// The time of the last withdrawal.
+    pub last_withdraw_ts: i64,
+    /// If set, must sign withdrawals along with the beneficiary.
+    pub co_beneficiary: Option<Pubkey>,
+    /// True if the grantor can vest everything immediately.
+    pub accelerable: bool,
+    /// If set, can sign withdrawals in place of the beneficiary. Funds are
+    /// still only sent to the beneficiary.
+    pub withdraw_delegate: Option<Pubkey>,
+    /// Free form reference set by the grantor, e.g., a grant id. Purely
+    /// informational.
+    pub memo: [u8; 64],
+    /// Number of milestones in use. If zero, the account vests linearly
+    /// over `period_count` periods.
+    pub milestone_count: u8,
+    /// Step vesting schedule, sorted by unlock time.
+    pub milestones: [Milestone; MAX_MILESTONES],
+    /// If any are set, the only whitelisted programs this account can relay
+    /// to. Unused slots are the default pubkey.
+    pub program_allowlist: [Pubkey; MAX_PROGRAM_ALLOWLIST],
+    /// Layout version, see `migrate_vesting`.
+    pub version: u8,
+    /// The fraction of the starting balance, in basis points, held back
+    /// until `end_ts`. The rest vests linearly.
+    pub final_bps: u16,
+    /// The number of seconds between a revocation being scheduled and it
+    /// taking effect. Zero revokes immediately.
+    pub revoke_notice_seconds: i64,
+    /// The time a scheduled revocation takes effect, or zero if none is
+    /// scheduled.
+    pub revoke_effective_ts: i64,
+    /// True if the grantor must co-sign every withdrawal, i.e., each tranche
+    /// is only released with the grantor's approval.
+    pub requires_grantor_approval: bool,
+    /// Keys that can initiate whitelist relays in addition to the
+    /// beneficiary. Unused slots are the default pubkey.
+    pub relay_initiators: [Pubkey; MAX_RELAY_INITIATORS],
+    /// True if the terms of the grant, i.e., the beneficiary, schedule and
+    /// balance, can never change after creation, overriding `revocable` and
+    /// `accelerable`.
+    pub immutable: bool,
+    /// The amount taken off the end of the schedule by `revoke_partial`.
+    /// The schedule still unlocks at the rate of the original balance.
+    pub partially_revoked: u64,
+    /// Whether funds unlock gradually, front-loaded or all at once at
+    /// `end_ts`.
+    pub schedule_kind: ScheduleKind,
+    /// Whitelisted program notified of every withdrawal through the
+    /// `WithdrawCallback` interface, if any.
+    pub withdraw_callback_program: Option<Pubkey>,
+    /// Key appointed by the grantor at creation that can `recover` the vault
+    /// to the grantor if the beneficiary's key is compromised.
+    pub recovery_authority: Option<Pubkey>,
+    /// Seconds funds may stay loaned to whitelisted programs, counted from
+    /// the first loan, before `force_reconcile` can mark the account
+    /// delinquent. `None` leaves loans open-ended.
+    pub relay_deadline: Option<i64>,
+    /// When outstanding loans are due back, or zero if there are none or
+    /// no deadline.
+    pub loan_due_ts: i64,
+    /// True once loans were found overdue. Blocks further loans until all
+    /// funds are returned.
+    pub delinquent: bool,
+    /// Vaults of other mints vesting on the same schedule as `vault`, see
+    /// `add_vault`. Only the first `extra_vault_count` are in use.
+    pub extra_vaults: [ExtraVault; MAX_EXTRA_VAULTS],
+    pub extra_vault_count: u8,
+    /// How the linear balance divides into per-period unlocks, set from the
+    /// lockup at creation.
+    pub rounding: Rounding,
+    /// If any are set, the only token accounts withdrawals can go to. Unused
+    /// slots are the default pubkey.
+    pub destination_allowlist: [Pubkey; MAX_DESTINATION_ALLOWLIST],
+    /// The allowlist replacing `destination_allowlist` once
+    /// `destination_allowlist_effective_ts` has passed.
+    pub pending_destination_allowlist: [Pubkey; MAX_DESTINATION_ALLOWLIST],
+    /// When the pending destination allowlist can be applied, or zero if
+    /// there is none.
+    pub destination_allowlist_effective_ts: i64,
+    /// The last time `poke` emitted a `PeriodUnlocked` event, or zero if it
+    /// never has.
+    pub last_poke_ts: i64,
+    /// The exponent `k` of a `FrontLoaded` schedule, which has vested
+    /// `1 - (1 - p / n)^k` of the balance after `p` of `n` periods. Zero for
+    /// other schedules.
+    pub curve_exponent: u8,
+    /// The number of withdrawals made, including from extra vaults.
+    pub withdraw_count: u64,
+}
+
+impl Vesting {
+    pub const VERSION: u8 = 17;
+
+    // Every withdrawal restarts the throttle and counts towards
+    // `withdraw_count`.
+    fn record_withdrawal(&mut self, now: i64) -> Result<()> {
+        self.last_withdraw_ts = now;
+        self.withdraw_count = self
+            .withdraw_count
+            .checked_add(1)
+            .ok_or(ErrorCode::Overflow)?;
+        Ok(())
+    }
+
+    // Returns the milestones of a step vesting schedule, if any.
+    fn milestones(&self) -> &[Milestone] {
+        &self.milestones[..self.milestone_count as usize]
+    }
+
+    // Returns true if the account may relay to the given whitelisted program.
+    fn is_program_allowed(&self, program_id: &Pubkey) -> bool {
+        let unrestricted = self
+            .program_allowlist
+            .iter()
+            .all(|p| p == &Pubkey::default());
+        unrestricted || self.program_allowlist.contains(program_id)
+    }
+
+    // Returns true if withdrawals can go to the given token account.
+    fn is_destination_allowed(&self, token: &Pubkey) -> bool {
+        let unrestricted = self
+            .destination_allowlist
+            .iter()
+            .all(|d| d == &Pubkey::default());
+        unrestricted || self.destination_allowlist.contains(token)
+    }
+
+    // Returns true if the given key can initiate whitelist relays.
+    fn is_relay_initiator(&self, key: &Pubkey) -> bool {
+        key == &self.beneficiary
+            || (key != &Pubkey::default() && self.relay_initiators.contains(key))
+    }
+
+    /// The amount the beneficiary can withdraw at `now`, i.e., what has
+    /// vested, less past withdrawals, that's in the vault.
+    pub fn available(&self, now: i64) -> u64 {
+        calculator::available_for_withdrawal(self, now)
+    }
+
+    /// The amount of `outstanding` yet to vest at `now`.
+    pub fn unvested(&self, now: i64) -> u64 {
+        calculator::unvested(self, now)
+    }
+
+    /// The amount currently held by whitelisted programs.
+    pub fn loaned(&self) -> u64 {
+        self.whitelist_owned
+    }
+
+    /// The vaults added with `add_vault`.
+    pub fn extra_vaults(&self) -> &[ExtraVault] {
+        &self.extra_vaults[..self.extra_vault_count as usize]
+    }
+
+    /// The amount the beneficiary can withdraw from the given extra vault at
+    /// `now`.
+    pub fn extra_available(&self, extra: &ExtraVault, now: i64) -> u64 {
+        calculator::extra_vault_available(self, extra, now)
+    }
+
+    // Returns the amount of funds held by the given program.
+    fn loaned_to(&self, program_id: &Pubkey) -> u64 {
+        self.whitelist_loans
+            .iter()
+            .find(|l| &l.program_id == program_id)
+            .map_or(0, |l| l.amount)
+    }
+
+    // Returns the loan tracking funds held by the given program, claiming an
+    // unused slot if the program doesn't hold any funds yet.
+    fn whitelist_loan_mut(&mut self, program_id: &Pubkey) -> Result<&mut WhitelistLoan> {
+        let idx = self
+            .whitelist_loans
+            .iter()
+            .position(|l| &l.program_id == program_id)
+            .or_else(|| self.whitelist_loans.iter().position(|l| l.amount == 0))
+            .ok_or(ErrorCode::WhitelistLoansFull)?;
+        let loan = &mut self.whitelist_loans[idx];
+        loan.program_id = *program_id;
+        Ok(loan)
+    }
+
+    // Records funds returned to the vault by the given program.
+    fn repay_loan(&mut self, program_id: &Pubkey, amount: u64) -> Result<()> {
+        self.whitelist_owned = self
+            .whitelist_owned
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        let loan = self
+            .whitelist_loans
+            .iter_mut()
+            .find(|l| &l.program_id == program_id)
+            .ok_or(ErrorCode::WhitelistDepositOverflow)?;
+        loan.amount = loan
+            .amount
+            .checked_sub(amount)
+            .ok_or(ErrorCode::WhitelistDepositOverflow)?;
+        Ok(())
+    }
+
+    // Attributes the funds held by whitelisted programs before loans were
+    // tracked per program to the given one, so they can be deposited back.
+    fn migrate_baseline(&mut self, loan_program: Option<Pubkey>) -> Result<()> {
+        if self.whitelist_owned == 0 {
+            return Ok(());
+        }
+        let program_id = loan_program.ok_or(ErrorCode::LoanProgramRequired)?;
+        self.whitelist_loans[0] = WhitelistLoan {
+            program_id,
+            amount: self.whitelist_owned,
+        };
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Clone, Debug, InitSpace)]
+pub struct Realizor {
+    /// Program to invoke to check a realization condition. This program must
+    /// implement the `RealizeLock` trait.
+    pub program: Pubkey,
+    /// Address of an arbitrary piece of metadata interpretable by the realizor
+    /// program. For example, when a vesting account is allocated, the program
+    /// can define its realization condition as a function of some account
+    /// state. The metadata is the address of that account.
+    ///
+    /// In the case of staking, the metadata is a `Member` account address. When
+    /// the realization condition is checked, the staking program will check the
+    /// `Member` account defined by the `metadata` has no staked tokens.
+    pub metadata: Pubkey,
+}
+
+/// Creation parameters of a vesting account, taken by every instruction
+/// creating one. See the `Vesting` fields of the same name for the meaning
+/// of each.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct VestingParams {
+    pub beneficiary: Pubkey,
+    pub deposit_amount: u64,
+    pub start_ts: i64,
+    pub end_ts: i64,
+    pub period_count: u64,
+    pub cliff_ts: i64,
+    pub revocable: bool,
+    pub accelerable: bool,
+    pub period_rounding: PeriodRounding,
+    pub min_withdraw_interval: i64,
+    pub memo: [u8; 64],
+    pub milestones: Vec<Milestone>,
+    pub final_bps: u16,
+    pub revoke_notice_seconds: i64,
+    pub requires_grantor_approval: bool,
+    pub immutable: bool,
+    pub schedule_kind: ScheduleKind,
+    pub curve_exponent: u8,
+    pub recovery_authority: Option<Pubkey>,
+    pub realizor: Option<Realizor>,
+}
+
+impl VestingParams {
+    fn validate(&self, lockup: &Lockup) -> Result<()> {
+        if lockup.creation_paused {
+            return err!(ErrorCode::CreationPaused);
+        }
+        if self.deposit_amount == 0 {
+            return err!(ErrorCode::InvalidDepositAmount);
+        }
+        if self.deposit_amount < lockup.min_deposit_amount {
+            return err!(ErrorCode::DepositBelowMinimum);
+        }
+        if self.start_ts < 0 || self.end_ts.checked_sub(self.start_ts).is_none() {
+            return err!(ErrorCode::InvalidTimestamp);
+        }
+        if !is_valid_schedule(self.start_ts, self.end_ts, self.period_count) {
+            return err!(ErrorCode::InvalidSchedule);
+        }
+        let duration = (self.end_ts - self.start_ts) as u64;
+        if lockup.max_duration_seconds != 0 && duration > lockup.max_duration_seconds {
+            return err!(ErrorCode::DurationTooLong);
+        }
+        if !is_valid_cliff(self.start_ts, self.end_ts, self.cliff_ts) {
+            return err!(ErrorCode::InvalidCliff);
+        }
+        if !is_valid_milestones(
+            self.start_ts,
+            self.end_ts,
+            self.deposit_amount,
+            &self.milestones,
+        ) {
+            return err!(ErrorCode::InvalidMilestones);
+        }
+        if self.final_bps > 10_000 || (self.final_bps != 0 && !self.milestones.is_empty()) {
+            return err!(ErrorCode::InvalidFinalBps);
+        }
+        // A time lock releases everything at `end_ts`, and a front-loaded
+        // schedule follows its curve, leaving nothing for milestones or a
+        // final amount to shape.
+        if self.schedule_kind != ScheduleKind::Linear
+            && (!self.milestones.is_empty() || self.final_bps != 0)
+        {
+            return err!(ErrorCode::InvalidSchedule);
+        }
+        // An exponent of one would be the linear schedule.
+        let valid_exponent = match self.schedule_kind {
+            ScheduleKind::FrontLoaded => (2..=MAX_CURVE_EXPONENT).contains(&self.curve_exponent),
+            _ => self.curve_exponent == 0,
+        };
+        if !valid_exponent {
+            return err!(ErrorCode::InvalidCurveExponent);
+        }
+        if self.cliff_ts - self.start_ts < lockup.min_cliff_seconds {
+            return err!(ErrorCode::CliffTooShort);
+        }
+        if self.min_withdraw_interval < 0 {
+            return err!(ErrorCode::InvalidWithdrawInterval);
+        }
+        if self.revoke_notice_seconds < 0 {
+            return err!(ErrorCode::InvalidRevokeNotice);
+        }
+        Ok(())
+    }
+
+    fn init(
+        &self,
+        vesting: &mut Vesting,
+        vault: Pubkey,
+        mint: Pubkey,
+        grantor: Pubkey,
+        nonce: u8,
+        now: i64,
+    ) {
+        vesting.beneficiary = self.beneficiary;
+        vesting.mint = mint;
+        vesting.vault = vault;
+        vesting.period_count = self.period_count;
+        vesting.start_balance = self.deposit_amount;
+        vesting.end_ts = self.end_ts;
+        vesting.start_ts = self.start_ts;
+        vesting.cliff_ts = self.cliff_ts;
+        vesting.created_ts = now;
+        vesting.outstanding = self.deposit_amount;
+        vesting.whitelist_owned = 0;
+        vesting.grantor = grantor;
+        vesting.nonce = nonce;
+        vesting.realizor = self.realizor.clone();
+        vesting.revocable = self.revocable;
+        vesting.accelerable = self.accelerable;
+        vesting.period_rounding = self.period_rounding;
+        vesting.min_withdraw_interval = self.min_withdraw_interval;
+        vesting.last_withdraw_ts = 0;
+        vesting.memo = self.memo;
+        vesting.version = Vesting::VERSION;
+        vesting.milestone_count = self.milestones.len() as u8;
+        vesting.milestones[..self.milestones.len()].copy_from_slice(&self.milestones);
+        vesting.final_bps = self.final_bps;
+        vesting.revoke_notice_seconds = self.revoke_notice_seconds;
+        vesting.revoke_effective_ts = 0;
+        vesting.requires_grantor_approval = self.requires_grantor_approval;
+        vesting.immutable = self.immutable;
+        vesting.schedule_kind = self.schedule_kind;
+        vesting.curve_exponent = self.curve_exponent;
+        vesting.recovery_authority = self.recovery_authority;
+    }
+
+    // True if `vesting` is what `init` would have produced from these
+    // parameters, ignoring everything that moves once the account exists.
+    // Deposits, revocations and beneficiary changes all count as a mismatch.
+    fn matches(&self, vesting: &Vesting, vault: Pubkey, grantor: Pubkey) -> bool {
+        vesting.beneficiary == self.beneficiary
+            && vesting.vault == vault
+            && vesting.grantor == grantor
+            && vesting.start_balance == self.deposit_amount
+            && vesting.partially_revoked == 0
+            && vesting.start_ts == self.start_ts
+            && vesting.end_ts == self.end_ts
+            && vesting.period_count == self.period_count
+            && vesting.cliff_ts == self.cliff_ts
+            && vesting.revocable == self.revocable
+            && vesting.accelerable == self.accelerable
+            && vesting.period_rounding == self.period_rounding
+            && vesting.min_withdraw_interval == self.min_withdraw_interval
+            && vesting.memo == self.memo
+            && vesting.milestones() == &self.milestones[..]
+            && vesting.final_bps == self.final_bps
+            && vesting.revoke_notice_seconds == self.revoke_notice_seconds
+            && vesting.requires_grantor_approval == self.requires_grantor_approval
+            && vesting.immutable == self.immutable
+            && vesting.schedule_kind == self.schedule_kind
+            && vesting.curve_exponent == self.curve_exponent
+            && vesting.recovery_authority == self.recovery_authority
+            && vesting.realizor == self.realizor
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Copy, Clone, Debug, InitSpace)]
+pub enum PeriodRounding {
+    /// The remainder unlocks with the first period, acting as a small cliff.
+    FirstPeriod,
+    /// The remainder unlocks with the final period, so that every other
+    /// period unlocks the same amount.
+    FinalPeriod,
+}
+
+impl Default for PeriodRounding {
+    fn default() -> Self {
+        Self::FirstPeriod
+    }
+}
+
+/// Which way divisions that don't come out even are rounded, i.e., who bears
+/// the dust.
+#[derive(AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Copy, Clone, Debug, InitSpace)]
+pub enum Rounding {
+    /// The remainder is dropped. Fees undercharge, and the period rounding
+    /// decides which period unlocks the remainder of a schedule.
+    Down,
+    /// Any remainder rounds up. Fees overcharge, and every period unlocks
+    /// slightly more, leaving the final period short.
+    Up,
+    /// Halves and above round up, otherwise down.
+    Nearest,
+}
+
+impl Default for Rounding {
+    fn default() -> Self {
+        Self::Down
+    }
+}
+
+impl Rounding {
+    /// Divides `numerator` by the nonzero `denominator` in this direction.
+    pub fn div(self, numerator: u128, denominator: u128) -> u128 {
+        let quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+        let round_up = match self {
+            Rounding::Down => false,
+            Rounding::Up => remainder > 0,
+            Rounding::Nearest => remainder >= denominator - remainder,
+        };
+        quotient + round_up as u128
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Copy, Clone, Debug, InitSpace)]
+pub enum ScheduleKind {
+    /// Funds unlock over `period_count` periods, or at each milestone.
+    Linear,
+    /// Nothing unlocks until `end_ts`, when the whole balance does, e.g.,
+    /// for a lockdrop. Unlike a single period linear schedule, the intent is
+    /// explicit and `period_count` and the period rounding are ignored.
+    TimeLock,
+    /// Funds unlock over `period_count` periods, more early and less later,
+    /// along the curve of `curve_exponent`.
+    FrontLoaded,
+}
+
+impl Default for ScheduleKind {
+    fn default() -> Self {
+        Self::Linear
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    /// An average Gregorian month, i.e., a twelfth of 365.25 days.
+    Monthly,
+}
+
+impl Frequency {
+    pub fn seconds(&self) -> u64 {
+        match self {
+            Frequency::Daily => 24 * 60 * 60,
+            Frequency::Weekly => 7 * 24 * 60 * 60,
+            Frequency::Monthly => 2_629_800,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct UiWithdrawableAmount {
+    /// Raw amount available for withdrawal.
+    pub amount: u64,
+    pub decimals: u8,
+    /// Amount available for withdrawal, formatted with the mint's decimals.
+    pub ui_amount: String,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct VestingSchedule {
+    pub start_ts: i64,
+    pub end_ts: i64,
+    pub period_count: u64,
+    /// Amount unlocked each period. The remainder of dividing the balance
+    /// into periods is unlocked according to the account's `PeriodRounding`.
+    pub period_amount: u64,
+    /// Time at which tokens next unlock, or `end_ts` once fully vested.
+    pub next_unlock_ts: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct SignerAddress {
+    pub address: Pubkey,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct SimulatedWithdrawal {
+    /// The vesting account's `outstanding` balance after the withdrawal.
+    pub new_outstanding: u64,
+    /// The amount still available for withdrawal afterwards.
+    pub remaining_available: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct WithdrawCheck {
+    /// True if the amount can currently be withdrawn.
+    pub allowed: bool,
+    /// The amount currently available for withdrawal.
+    pub available: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct VestingSummary {
+    /// The owner of the vesting account.
+    pub beneficiary: Pubkey,
+    /// The amount not yet withdrawn.
+    pub outstanding: u64,
+    /// The amount currently available for withdrawal.
+    pub available: u64,
+    /// Time at which everything has vested.
+    pub end_ts: i64,
+    /// The number of withdrawals made.
+    pub withdraw_count: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct NextUnlock {
+    /// Time at which tokens next unlock, or `end_ts` once fully vested.
+    pub ts: i64,
+    /// Amount unlocked at `ts`, or zero once fully vested.
+    pub amount: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Default, Copy, Clone)]
+pub struct WhitelistEntry {
+    pub program_id: Pubkey,
+    /// The maximum amount of a single vesting account's funds the program
+    /// can hold at any one time.
+    pub max_amount: u64,
+    /// Human readable name of the program, zero padded, for auditing the
+    /// whitelist.
+    pub label: [u8; 32],
+    /// If nonzero, the discriminator of the only instruction that can be
+    /// relayed to the program. Zero allows any instruction.
+    pub discriminator: [u8; 8],
+}
+
+impl WhitelistEntry {
+    fn allows_instruction(&self, instruction_data: &[u8]) -> bool {
+        self.discriminator == [0; 8] || instruction_data.starts_with(&self.discriminator)
+    }
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
-pub struct Realizor {
-    /// Program to invoke to check a realization condition. This program must
-    /// implement the `RealizeLock` trait.
-    pub program: Pubkey,
-    /// Address of an arbitrary piece of metadata interpretable by the realizor
-    /// program. For example, when a vesting account is allocated, the program
-    /// can define its realization condition as a function of some account
-    /// state. The metadata is the address of that account.
-    ///
-    /// In the case of staking, the metadata is a `Member` account address. When
-    /// the realization condition is checked, the staking program will check the
-    /// `Member` account defined by the `metadata` has no staked tokens.
-    pub metadata: Pubkey,
+// Entries are identified by program, so that a program can't be whitelisted
+// twice with different caps or labels.
+impl PartialEq for WhitelistEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.program_id == other.program_id
+    }
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, PartialEq, Default, Copy, Clone)]
-pub struct WhitelistEntry {
+#[derive(
+    AnchorSerialize, AnchorDeserialize, Default, PartialEq, Eq, Copy, Clone, Debug, InitSpace,
+)]
+pub struct Milestone {
+    /// Time at which the milestone is reached.
+    pub unlock_ts: i64,
+    /// Total amount vested once the milestone is reached.
+    pub cumulative_amount: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Default, Copy, Clone, Debug, InitSpace)]
+pub struct ExtraVault {
+    /// Address of the vault.
+    pub vault: Pubkey,
+    /// The mint of the vault.
+    pub mint: Pubkey,
+    /// Amount deposited into the vault.
+    pub start_balance: u64,
+    /// Amount yet to be withdrawn from the vault.
+    pub outstanding: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Default, Copy, Clone, InitSpace)]
+pub struct WhitelistLoan {
+    /// Whitelisted program holding the funds.
     pub program_id: Pubkey,
+    /// Amount of the vesting account's funds held by the program.
+    pub amount: u64,
 }
 
 #[error_code]
@@ -405,14 +3478,193 @@ pub enum ErrorCode {
     UnrealizedVesting,
     #[msg("Invalid vesting schedule given.")]
     InvalidSchedule,
+    #[msg("Vesting cliff must be within the vesting schedule.")]
+    InvalidCliff,
+    #[msg("This vesting account cannot be revoked.")]
+    VestingNotRevocable,
+    #[msg("Vesting account still holds an outstanding balance.")]
+    VestingNotComplete,
+    #[msg("The beneficiary must not be the default address.")]
+    InvalidBeneficiary,
+    #[msg("Funds must be returned from whitelisted programs first.")]
+    WhitelistFundsOutstanding,
+    #[msg("Arithmetic overflow.")]
+    Overflow,
+    #[msg("Tried to withdraw over the whitelist entry's limit")]
+    WhitelistEntryWithdrawLimit,
+    #[msg("Funds are already held by the maximum number of whitelisted programs")]
+    WhitelistLoansFull,
+    #[msg("The token mint doesn't match the vesting account's mint.")]
+    MintMismatch,
+    #[msg("The vesting account is frozen.")]
+    VestingFrozen,
+    #[msg("The lockup program can't be re-entered during a whitelist relay.")]
+    ReentrancyDetected,
+    #[msg("The vault balance was not fully migrated.")]
+    InvalidVaultMigration,
+    #[msg("The withdrawal interval must not be negative.")]
+    InvalidWithdrawInterval,
+    #[msg("Not enough time has passed since the last withdrawal.")]
+    WithdrawTooSoon,
+    #[msg("The co-beneficiary must sign.")]
+    CoBeneficiaryRequired,
+    #[msg("The vesting account is not accelerable.")]
+    VestingNotAccelerable,
+    #[msg("Withdraw delegates can only withdraw to the beneficiary.")]
+    InvalidWithdrawDestination,
+    #[msg("The whitelist can't grow any larger.")]
+    WhitelistTooLarge,
+    #[msg("The cliff is shorter than the lockup's minimum.")]
+    CliffTooShort,
+    #[msg("Expected (vesting, vault, vesting_signer, token) accounts.")]
+    InvalidWithdrawManyAccounts,
+    #[msg("This vesting account must be withdrawn from individually.")]
+    WithdrawManyUnsupported,
+    #[msg("Milestones must be sorted and vest the full deposit within the schedule.")]
+    InvalidMilestones,
+    #[msg("A program allowlist holds at most 4 programs, none the default pubkey.")]
+    InvalidProgramAllowlist,
+    #[msg("The vesting account isn't allowed to relay to this program.")]
+    ProgramNotAllowed,
+    #[msg("The withdrawal would leave the vault short of funds owed by whitelisted programs.")]
+    WouldStrandWhitelistFunds,
+    #[msg("The whitelist entry doesn't allow relaying this instruction.")]
+    DisallowedRelayInstruction,
+    #[msg("The account isn't a vesting account of this program.")]
+    InvalidVestingAccount,
+    #[msg("The vesting account already has the current layout.")]
+    VestingUpToDate,
+    #[msg("The relay has more accounts than the lockup allows.")]
+    TooManyRelayAccounts,
+    #[msg("The token account isn't the beneficiary's associated token account.")]
+    NotAssociatedTokenAccount,
+    #[msg("The final unlock must be at most 10000 bps, and can't be used with milestones.")]
+    InvalidFinalBps,
+    #[msg("No authority has been proposed.")]
+    NoPendingAuthority,
+    #[msg("Only the proposed authority can accept.")]
+    PendingAuthorityMismatch,
+    #[msg("The revocation notice period must not be negative.")]
+    InvalidRevokeNotice,
+    #[msg("The scheduled revocation hasn't taken effect yet.")]
+    RevocationPending,
+    #[msg("The whitelist authority has been renounced.")]
+    WhitelistLocked,
+    #[msg("The vault isn't the vesting account's vault.")]
+    VaultVestingMismatch,
+    #[msg("The vault isn't owned by the vesting signer.")]
+    VaultSignerMismatch,
+    #[msg("The withdrawal fee is too high, or has no treasury.")]
+    InvalidWithdrawFee,
+    #[msg("The lockup's fee treasury must be given when it charges a fee.")]
+    InvalidFeeTreasury,
+    #[msg("The grantor must approve withdrawals from this vesting account.")]
+    GrantorApprovalRequired,
+    #[msg("Too many relay initiators, or an invalid one.")]
+    InvalidRelayInitiators,
+    #[msg("The whitelist must be emptied before closing the lockup.")]
+    WhitelistNotEmpty,
+    #[msg("Vesting accounts still hold funds.")]
+    LockupNotEmpty,
+    #[msg("This vesting account's terms can't be changed.")]
+    VestingImmutable,
+    #[msg("The amount must be nonzero and at most the unvested balance.")]
+    InvalidRevokeAmount,
+    #[msg("A vesting account with different parameters already exists.")]
+    VestingAlreadyExists,
+    #[msg("The whitelist page is out of range.")]
+    InvalidWhitelistPage,
+    #[msg("The withdraw callback program must be whitelisted and given.")]
+    InvalidWithdrawCallback,
+    #[msg("The whitelist deposit differs from the expected amount.")]
+    UnexpectedDepositAmount,
+    #[msg("The split must move some of the vault, leaving enough vested for past withdrawals.")]
+    InvalidSplitAmount,
+    #[msg("Vesting accounts with milestones can't be split.")]
+    SplitUnsupported,
+    #[msg("Recovered funds must go to a token account of the grantor.")]
+    InvalidRecoveryDestination,
+    #[msg("The test clock is only available with the test-clock feature.")]
+    TestClockDisabled,
+    #[msg("The vesting account's loans aren't overdue.")]
+    RelayDeadlineNotPassed,
+    #[msg("The vesting account is delinquent and can't loan funds until they're returned.")]
+    VestingDelinquent,
+    #[msg("The vault isn't one of the vesting account's extra vaults, or can't be added.")]
+    InvalidExtraVault,
+    #[msg("This isn't supported for vesting accounts with several vaults.")]
+    MultipleVaultsUnsupported,
+    #[msg("The whitelisted program isn't an executable account.")]
+    WhitelistedProgramNotExecutable,
+    #[msg("Withdrawals from this vesting account can't go to this token account.")]
+    DestinationNotAllowed,
+    #[msg("Too many allowed destinations, or an invalid one.")]
+    InvalidDestinationAllowlist,
+    #[msg("No destination allowlist change is pending, or it hasn't taken effect yet.")]
+    DestinationAllowlistNotReady,
+    #[msg("Only wrapped SOL can be unwrapped, into a token account of the signing beneficiary.")]
+    InvalidUnwrap,
+    #[msg("The depositor must approve the vesting signer for at least the deposit.")]
+    InsufficientDelegation,
+    #[msg("Creating vesting accounts is paused.")]
+    CreationPaused,
+    #[msg("The new vesting signer must derive from the vesting account and a new nonce.")]
+    InvalidSignerRotation,
+    #[msg("The deposit is below the lockup's minimum.")]
+    DepositBelowMinimum,
+    #[msg("The grantor tally belongs to another grantor.")]
+    InvalidGrantorTally,
+    #[msg("Front-loaded schedules need a curve exponent from 2 to 8, and others none.")]
+    InvalidCurveExponent,
+    #[msg("The schedule is longer than the lockup's maximum duration.")]
+    DurationTooLong,
+    #[msg("Migrating funds held by a whitelisted program requires the program.")]
+    LoanProgramRequired,
 }
 
 impl<'a, 'b, 'c, 'info> From<&mut CreateVesting<'info>>
-    for CpiContext<'a, 'b, 'c, 'info, Transfer<'info>>
+    for CpiContext<'a, 'b, 'c, 'info, TransferChecked<'info>>
+{
+    fn from(
+        accounts: &mut CreateVesting<'info>,
+    ) -> CpiContext<'a, 'b, 'c, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: accounts.depositor.to_account_info(),
+            mint: accounts.mint.to_account_info(),
+            to: accounts.vault.to_account_info(),
+            authority: accounts.depositor_authority.clone(),
+        };
+        let cpi_program = accounts.token_program.clone();
+        CpiContext::new(cpi_program, cpi_accounts)
+    }
+}
+
+impl<'a, 'b, 'c, 'info> From<&mut CreateVestingIdempotent<'info>>
+    for CpiContext<'a, 'b, 'c, 'info, TransferChecked<'info>>
+{
+    fn from(
+        accounts: &mut CreateVestingIdempotent<'info>,
+    ) -> CpiContext<'a, 'b, 'c, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: accounts.depositor.to_account_info(),
+            mint: accounts.mint.to_account_info(),
+            to: accounts.vault.to_account_info(),
+            authority: accounts.depositor_authority.clone(),
+        };
+        let cpi_program = accounts.token_program.clone();
+        CpiContext::new(cpi_program, cpi_accounts)
+    }
+}
+
+impl<'a, 'b, 'c, 'info> From<&mut DepositToVesting<'info>>
+    for CpiContext<'a, 'b, 'c, 'info, TransferChecked<'info>>
 {
-    fn from(accounts: &mut CreateVesting<'info>) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
-        let cpi_accounts = Transfer {
+    fn from(
+        accounts: &mut DepositToVesting<'info>,
+    ) -> CpiContext<'a, 'b, 'c, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
             from: accounts.depositor.clone(),
+            mint: accounts.mint.to_account_info(),
             to: accounts.vault.to_account_info(),
             authority: accounts.depositor_authority.clone(),
         };
@@ -421,10 +3673,28 @@ impl<'a, 'b, 'c, 'info> From<&mut CreateVesting<'info>>
     }
 }
 
-impl<'a, 'b, 'c, 'info> From<&Withdraw<'info>> for CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
-    fn from(accounts: &Withdraw<'info>) -> CpiContext<'a, 'b, 'c, 'info, Transfer<'info>> {
-        let cpi_accounts = Transfer {
+impl<'a, 'b, 'c, 'info> From<&Withdraw<'info>>
+    for CpiContext<'a, 'b, 'c, 'info, TransferChecked<'info>>
+{
+    fn from(accounts: &Withdraw<'info>) -> CpiContext<'a, 'b, 'c, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: accounts.vault.to_account_info(),
+            mint: accounts.mint.to_account_info(),
+            to: accounts.token.to_account_info(),
+            authority: accounts.vesting_signer.to_account_info(),
+        };
+        let cpi_program = accounts.token_program.to_account_info();
+        CpiContext::new(cpi_program, cpi_accounts)
+    }
+}
+
+impl<'a, 'b, 'c, 'info> From<&Revoke<'info>>
+    for CpiContext<'a, 'b, 'c, 'info, TransferChecked<'info>>
+{
+    fn from(accounts: &Revoke<'info>) -> CpiContext<'a, 'b, 'c, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
             from: accounts.vault.to_account_info(),
+            mint: accounts.mint.to_account_info(),
             to: accounts.token.to_account_info(),
             authority: accounts.vesting_signer.to_account_info(),
         };
@@ -433,12 +3703,239 @@ impl<'a, 'b, 'c, 'info> From<&Withdraw<'info>> for CpiContext<'a, 'b, 'c, 'info,
     }
 }
 
+impl<'a, 'b, 'c, 'info> From<&MigrateVault<'info>>
+    for CpiContext<'a, 'b, 'c, 'info, TransferChecked<'info>>
+{
+    fn from(
+        accounts: &MigrateVault<'info>,
+    ) -> CpiContext<'a, 'b, 'c, 'info, TransferChecked<'info>> {
+        let cpi_accounts = TransferChecked {
+            from: accounts.vault.to_account_info(),
+            mint: accounts.mint.to_account_info(),
+            to: accounts.new_vault.to_account_info(),
+            authority: accounts.vesting_signer.to_account_info(),
+        };
+        let cpi_program = accounts.token_program.to_account_info();
+        CpiContext::new(cpi_program, cpi_accounts)
+    }
+}
+
+impl<'a, 'b, 'c, 'info> From<&CloseVesting<'info>>
+    for CpiContext<'a, 'b, 'c, 'info, CloseAccount<'info>>
+{
+    fn from(accounts: &CloseVesting<'info>) -> CpiContext<'a, 'b, 'c, 'info, CloseAccount<'info>> {
+        let cpi_accounts = CloseAccount {
+            account: accounts.vault.to_account_info(),
+            destination: accounts.beneficiary.to_account_info(),
+            authority: accounts.vesting_signer.to_account_info(),
+        };
+        let cpi_program = accounts.token_program.to_account_info();
+        CpiContext::new(cpi_program, cpi_accounts)
+    }
+}
+
+// Transfers vested funds out of the vault to the beneficiary's token account.
+fn transfer_withdrawal(accounts: &mut Withdraw, amount: u64) -> Result<()> {
+    let now = current_ts(&accounts.lockup, &accounts.clock);
+    if calculator::is_withdraw_throttled(&accounts.vesting, now) {
+        return err!(ErrorCode::WithdrawTooSoon);
+    }
+    if !accounts
+        .vesting
+        .is_destination_allowed(&accounts.token.key())
+    {
+        return err!(ErrorCode::DestinationNotAllowed);
+    }
+    accounts.vault.reload()?;
+    if would_strand_whitelist_funds(&accounts.vesting, accounts.vault.amount, amount) {
+        return err!(ErrorCode::WouldStrandWhitelistFunds);
+    }
+    let fee = withdraw_fee(
+        amount,
+        accounts.lockup.withdraw_fee_bps,
+        accounts.lockup.rounding,
+    )?;
+    let fee_treasury = if accounts.lockup.withdraw_fee_bps != 0 {
+        match &accounts.fee_treasury {
+            Some(treasury) if treasury.key() == accounts.lockup.fee_treasury => {
+                Some(treasury.to_account_info())
+            }
+            _ => return err!(ErrorCode::InvalidFeeTreasury),
+        }
+    } else {
+        None
+    };
+
+    // Transfer funds out, net of the fee.
+    let seeds = &[
+        accounts.vesting.to_account_info().key.as_ref(),
+        &[accounts.vesting.nonce],
+    ];
+    let signer = &[&seeds[..]];
+    let decimals = accounts.mint.decimals;
+    let cpi_ctx = CpiContext::from(&*accounts).with_signer(signer);
+    token_interface::transfer_checked(cpi_ctx, amount - fee, decimals)?;
+    if let (Some(treasury), true) = (fee_treasury, fee > 0) {
+        let cpi_ctx = CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: accounts.vault.to_account_info(),
+                mint: accounts.mint.to_account_info(),
+                to: treasury,
+                authority: accounts.vesting_signer.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, fee, decimals)?;
+    }
+
+    // Bookeeping.
+    let vesting = &mut accounts.vesting;
+    vesting.outstanding = vesting
+        .outstanding
+        .checked_sub(amount)
+        .ok_or(ErrorCode::Overflow)?;
+    vesting.record_withdrawal(now)?;
+    accounts.lockup.total_outstanding = accounts
+        .lockup
+        .total_outstanding
+        .checked_sub(amount)
+        .ok_or(ErrorCode::Overflow)?;
+
+    emit!(VestingWithdrawn {
+        vesting: *vesting.to_account_info().key,
+        beneficiary: vesting.beneficiary,
+        amount,
+        remaining: vesting.outstanding,
+    });
+
+    notify_withdraw(accounts, amount)
+}
+
+// Invokes the vesting account's withdraw callback program, if any. The
+// program must still be whitelisted, since it's trusted not to block
+// withdrawals. The beneficiary can always clear it.
+fn notify_withdraw(accounts: &Withdraw, amount: u64) -> Result<()> {
+    let program_id = match accounts.vesting.withdraw_callback_program {
+        Some(program_id) => program_id,
+        None => return Ok(()),
+    };
+    if !whitelist_contains(&accounts.lockup, &program_id) {
+        return err!(ErrorCode::InvalidWithdrawCallback);
+    }
+    let cpi_program = match &accounts.withdraw_callback_program {
+        Some(program) if program.key == &program_id => program.clone(),
+        _ => return err!(ErrorCode::InvalidWithdrawCallback),
+    };
+    // Persist the bookkeeping first, so the callback reads the balance
+    // after the withdrawal.
+    accounts.vesting.exit(&crate::ID)?;
+    let cpi_accounts = vec![accounts.vesting.to_account_info()];
+    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+    withdraw_callback::on_withdraw(cpi_ctx, amount)
+}
+
+// Withdraws everything available from one group of `withdraw_many`'s
+// remaining accounts, applying the same checks as `withdraw`.
+fn withdraw_one<'info>(
+    ctx_accounts: &WithdrawMany<'info>,
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'info>],
+) -> Result<u64> {
+    let (vesting_info, vault, vesting_signer, token) =
+        (&accounts[0], &accounts[1], &accounts[2], &accounts[3]);
+    let mut vesting: Account<Vesting> = Account::try_from(vesting_info)?;
+    if &vesting.beneficiary != ctx_accounts.beneficiary.key {
+        return err!(ErrorCode::Unauthorized);
+    }
+    if &vesting.vault != vault.key {
+        return err!(ErrorCode::InvalidWithdrawManyAccounts);
+    }
+    if vesting.mint != ctx_accounts.mint.key() {
+        return err!(ErrorCode::MintMismatch);
+    }
+    if vesting.realizor.is_some()
+        || vesting.co_beneficiary.is_some()
+        || vesting.requires_grantor_approval
+        || vesting.withdraw_callback_program.is_some()
+    {
+        return err!(ErrorCode::WithdrawManyUnsupported);
+    }
+    is_not_frozen(&vesting)?;
+    is_not_in_relay(&vesting)?;
+    let now = current_ts(&ctx_accounts.lockup, &ctx_accounts.clock);
+    if calculator::is_withdraw_throttled(&vesting, now) {
+        return err!(ErrorCode::WithdrawTooSoon);
+    }
+    let token_account: InterfaceAccount<TokenAccount> = InterfaceAccount::try_from(token)?;
+    if token_account.mint != vesting.mint {
+        return err!(ErrorCode::MintMismatch);
+    }
+    if !vesting.is_destination_allowed(token.key) {
+        return err!(ErrorCode::DestinationNotAllowed);
+    }
+    let amount = vesting.available(now);
+    let vault_account: InterfaceAccount<TokenAccount> = InterfaceAccount::try_from(vault)?;
+    if would_strand_whitelist_funds(&vesting, vault_account.amount, amount) {
+        return err!(ErrorCode::WouldStrandWhitelistFunds);
+    }
+
+    // Transfer funds out.
+    let seeds = &[vesting_info.key.as_ref(), &[vesting.nonce]];
+    let signer_address = Pubkey::create_program_address(seeds, program_id)
+        .map_err(|_| error!(ErrorCode::InvalidProgramAddress))?;
+    if &signer_address != vesting_signer.key {
+        return err!(ErrorCode::InvalidProgramAddress);
+    }
+    let signer = &[&seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx_accounts.token_program.clone(),
+        TransferChecked {
+            from: vault.clone(),
+            mint: ctx_accounts.mint.to_account_info(),
+            to: token.clone(),
+            authority: vesting_signer.clone(),
+        },
+        signer,
+    );
+    token_interface::transfer_checked(cpi_ctx, amount, ctx_accounts.mint.decimals)?;
+
+    // Bookeeping.
+    vesting.outstanding = vesting
+        .outstanding
+        .checked_sub(amount)
+        .ok_or(ErrorCode::Overflow)?;
+    vesting.record_withdrawal(now)?;
+    emit!(VestingWithdrawn {
+        vesting: *vesting_info.key,
+        beneficiary: vesting.beneficiary,
+        amount,
+        remaining: vesting.outstanding,
+    });
+    vesting.exit(program_id)?;
+
+    Ok(amount)
+}
+
 #[access_control(is_whitelisted(transfer))]
 pub fn whitelist_relay_cpi<'info>(
-    transfer: &WhitelistTransfer<'info>,
+    transfer: &mut WhitelistTransfer<'info>,
     remaining_accounts: &[AccountInfo<'info>],
     instruction_data: Vec<u8>,
 ) -> Result<()> {
+    let allowed = transfer
+        .lockup
+        .whitelist
+        .iter()
+        .filter(|e| &e.program_id == transfer.whitelisted_program.key)
+        .any(|e| e.allows_instruction(&instruction_data));
+    if !allowed {
+        return err!(ErrorCode::DisallowedRelayInstruction);
+    }
+    if remaining_accounts.len() > transfer.lockup.max_relay_accounts as usize {
+        return err!(ErrorCode::TooManyRelayAccounts);
+    }
+
     let mut meta_accounts = vec![
         AccountMeta::new_readonly(*transfer.vesting.to_account_info().key, false),
         AccountMeta::new(*transfer.vault.to_account_info().key, false),
@@ -476,37 +3973,295 @@ pub fn whitelist_relay_cpi<'info>(
     let signer = &[&seeds[..]];
     let mut accounts = transfer.to_account_infos();
     accounts.extend_from_slice(&remaining_accounts);
-    solana_program::program::invoke_signed(&relay_instruction, &accounts, signer)
-        .map_err(Into::into)
+
+    // Persist the guard, along with any bookkeeping done so far, before
+    // handing control to the whitelisted program, so that any re-entrant
+    // relay or withdrawal sees it. The vesting account isn't reloaded
+    // afterwards, which is only sound because the guard keeps re-entrant
+    // instructions from changing it.
+    is_not_in_relay(&transfer.vesting)?;
+    transfer.vesting.in_relay = true;
+    transfer.vesting.exit(&crate::ID)?;
+
+    let vault_before = transfer.vault.amount;
+    solana_program::program::invoke_signed(&relay_instruction, &accounts, signer)?;
+    transfer.vault.reload()?;
+    let vault_after = transfer.vault.amount;
+
+    let mut discriminator = [0; 8];
+    let len = std::cmp::min(instruction_data.len(), discriminator.len());
+    discriminator[..len].copy_from_slice(&instruction_data[..len]);
+    emit!(RelayExecuted {
+        vesting: *transfer.vesting.to_account_info().key,
+        program: *transfer.whitelisted_program.key,
+        discriminator,
+        vault_before,
+        vault_after,
+        delta: (vault_after as i128 - vault_before as i128) as i64,
+    });
+
+    transfer.vesting.in_relay = false;
+    Ok(())
 }
 
 pub fn is_whitelisted<'info>(transfer: &WhitelistTransfer<'info>) -> Result<()> {
-    if !transfer.lockup.whitelist.contains(&WhitelistEntry {
-        program_id: *transfer.whitelisted_program.key,
-    }) {
+    if !whitelist_contains(&transfer.lockup, transfer.whitelisted_program.key) {
         return err!(ErrorCode::WhitelistEntryNotFound);
     }
+    if !transfer
+        .vesting
+        .is_program_allowed(transfer.whitelisted_program.key)
+    {
+        return err!(ErrorCode::ProgramNotAllowed);
+    }
+    Ok(())
+}
+
+// Returns the time withdrawals and views vest against, i.e., the clock,
+// unless pinned by `set_test_clock` in a `test-clock` build.
+fn current_ts(lockup: &Lockup, clock: &Clock) -> i64 {
+    if cfg!(feature = "test-clock") && lockup.test_clock != 0 {
+        return lockup.test_clock;
+    }
+    clock.unix_timestamp
+}
+
+fn whitelist_contains(lockup: &Lockup, program_id: &Pubkey) -> bool {
+    lockup.whitelist.iter().any(|e| &e.program_id == program_id)
+}
+
+// The vault may be held by either the SPL Token or the Token-2022 program.
+pub fn is_token_program(key: &Pubkey) -> bool {
+    key == &token::ID || key == &token_interface::ID
+}
+
+fn lockup_auth(lockup: &Lockup, authority: &AccountInfo) -> Result<()> {
+    if &lockup.authority != authority.key {
+        return err!(ErrorCode::Unauthorized);
+    }
+    Ok(())
+}
+
+// Checks the optional grantor tally belongs to the grantor.
+fn grantor_tally<'a, 'info>(
+    tally: &'a mut Option<Account<'info, GrantorTally>>,
+    grantor: &Pubkey,
+) -> Result<Option<&'a mut Account<'info, GrantorTally>>> {
+    if let Some(tally) = tally {
+        if &tally.grantor != grantor {
+            return err!(ErrorCode::InvalidGrantorTally);
+        }
+    }
+    Ok(tally.as_mut())
+}
+
+fn tally_granted(
+    tally: &mut Option<Account<GrantorTally>>,
+    grantor: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    if let Some(tally) = grantor_tally(tally, grantor)? {
+        tally.total_granted = tally
+            .total_granted
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+    }
+    Ok(())
+}
+
+fn tally_revoked(
+    tally: &mut Option<Account<GrantorTally>>,
+    grantor: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    if let Some(tally) = grantor_tally(tally, grantor)? {
+        tally.total_revoked = tally
+            .total_revoked
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+    }
+    Ok(())
+}
+
+// Shared by every instruction only the original grantor may sign.
+fn grantor_auth(vesting: &Vesting, grantor: &AccountInfo) -> Result<()> {
+    if &vesting.grantor != grantor.key {
+        return err!(ErrorCode::Unauthorized);
+    }
+    Ok(())
+}
+
+// The beneficiary can withdraw anywhere, the withdraw delegate only to the
+// beneficiary.
+fn withdraw_auth(accounts: &Withdraw) -> Result<()> {
+    if accounts.beneficiary.is_signer {
+        return Ok(());
+    }
+    match (
+        &accounts.vesting.withdraw_delegate,
+        &accounts.withdraw_delegate,
+    ) {
+        (Some(expected), Some(delegate)) if delegate.key == expected => {
+            if accounts.token.owner != accounts.vesting.beneficiary {
+                return err!(ErrorCode::InvalidWithdrawDestination);
+            }
+            Ok(())
+        }
+        _ => err!(ErrorCode::Unauthorized),
+    }
+}
+
+fn co_beneficiary_auth(vesting: &Vesting, co_beneficiary: &Option<Signer>) -> Result<()> {
+    if let Some(expected) = &vesting.co_beneficiary {
+        match co_beneficiary {
+            Some(signer) if signer.key == expected => {}
+            _ => return err!(ErrorCode::CoBeneficiaryRequired),
+        }
+    }
+    Ok(())
+}
+
+fn grantor_approval(vesting: &Vesting, grantor: &Option<Signer>) -> Result<()> {
+    if vesting.requires_grantor_approval {
+        match grantor {
+            Some(signer) if signer.key == &vesting.grantor => {}
+            _ => return err!(ErrorCode::GrantorApprovalRequired),
+        }
+    }
+    Ok(())
+}
+
+fn is_associated_token_account(accounts: &Withdraw) -> Result<()> {
+    let expected = get_associated_token_address_with_program_id(
+        &accounts.vesting.beneficiary,
+        &accounts.vesting.mint,
+        accounts.token_program.key,
+    );
+    if accounts.token.key() != expected {
+        return err!(ErrorCode::NotAssociatedTokenAccount);
+    }
+    Ok(())
+}
+
+fn is_unwrappable(accounts: &Withdraw) -> Result<()> {
+    if accounts.vesting.mint != token::spl_token::native_mint::ID
+        || !accounts.beneficiary.is_signer
+        || accounts.token.owner != accounts.vesting.beneficiary
+    {
+        return err!(ErrorCode::InvalidUnwrap);
+    }
+    Ok(())
+}
+
+// The part of a withdrawal charged as the lockup's fee.
+fn withdraw_fee(amount: u64, fee_bps: u16, rounding: Rounding) -> Result<u64> {
+    let fee = rounding.div(
+        (amount as u128)
+            .checked_mul(fee_bps as u128)
+            .ok_or(ErrorCode::Overflow)?,
+        10_000,
+    );
+    u64::try_from(fee).map_err(|_| error!(ErrorCode::Overflow))
+}
+
+// Funds loaned to whitelisted programs are owed back to the vault, so a
+// withdrawal must leave at least `whitelist_owned` behind.
+fn would_strand_whitelist_funds(vesting: &Vesting, vault_amount: u64, amount: u64) -> bool {
+    vault_amount
+        .checked_sub(amount)
+        .map_or(true, |remaining| remaining < vesting.whitelist_owned)
+}
+
+fn is_mutable(vesting: &Vesting) -> Result<()> {
+    if vesting.immutable {
+        return err!(ErrorCode::VestingImmutable);
+    }
+    Ok(())
+}
+
+fn is_not_frozen(vesting: &Vesting) -> Result<()> {
+    if vesting.frozen {
+        return err!(ErrorCode::VestingFrozen);
+    }
+    Ok(())
+}
+
+// Whitelisted programs could call back into the lockup program during a relay,
+// while the relay's own bookkeeping is in flight. The runtime already rejects
+// such direct CPI reentrancy, but in case that ever changes, anything moving
+// funds out of the vault is rejected until the relay completes.
+fn is_not_in_relay(vesting: &Vesting) -> Result<()> {
+    if vesting.in_relay {
+        return err!(ErrorCode::ReentrancyDetected);
+    }
     Ok(())
 }
 
 fn whitelist_auth(lockup: &Lockup, ctx: &Context<Auth>) -> Result<()> {
+    is_whitelist_unlocked(lockup)?;
     if &lockup.authority != ctx.accounts.authority.key {
         return err!(ErrorCode::Unauthorized);
     }
     Ok(())
 }
 
+fn is_whitelist_unlocked(lockup: &Lockup) -> Result<()> {
+    if lockup.locked {
+        return err!(ErrorCode::WhitelistLocked);
+    }
+    Ok(())
+}
+
 pub fn is_valid_schedule(start_ts: i64, end_ts: i64, period_count: u64) -> bool {
-    if end_ts <= start_ts {
+    if start_ts < 0 {
         return false;
     }
-    if period_count > (end_ts - start_ts) as u64 {
+    let duration = match end_ts.checked_sub(start_ts) {
+        Some(duration) if duration > 0 => duration as u64,
+        _ => return false,
+    };
+    if period_count > duration {
         return false;
     }
     if period_count == 0 {
         return false;
     }
-    true
+    // The calculator shifts the start of the schedule back by the remainder
+    // of dividing it into periods, which must not precede the epoch.
+    start_ts as u64 >= duration % period_count
+}
+
+pub fn is_valid_cliff(start_ts: i64, end_ts: i64, cliff_ts: i64) -> bool {
+    cliff_ts >= start_ts && cliff_ts <= end_ts
+}
+
+// An empty list of milestones is a linear schedule. Otherwise, milestones
+// must unlock in order within the schedule, never decrease, and add up to
+// the deposit.
+pub fn is_valid_milestones(
+    start_ts: i64,
+    end_ts: i64,
+    deposit_amount: u64,
+    milestones: &[Milestone],
+) -> bool {
+    if milestones.is_empty() {
+        return true;
+    }
+    if milestones.len() > MAX_MILESTONES {
+        return false;
+    }
+    if milestones
+        .iter()
+        .any(|m| m.unlock_ts < start_ts || m.unlock_ts > end_ts)
+    {
+        return false;
+    }
+    if milestones.windows(2).any(|w| {
+        w[0].unlock_ts >= w[1].unlock_ts || w[0].cumulative_amount > w[1].cumulative_amount
+    }) {
+        return false;
+    }
+    milestones[milestones.len() - 1].cumulative_amount == deposit_amount
 }
 
 // Returns Ok if the locked vesting account has been "realized". Realization
@@ -540,3 +4295,233 @@ fn is_realized(ctx: &Context<Withdraw>) -> Result<()> {
 pub trait RealizeLock<'info, T: Accounts<'info>> {
     fn is_realized(ctx: Context<T>, v: Vesting) -> Result<()>;
 }
+
+/// WithdrawCallback defines the interface an external program must implement
+/// to be notified of withdrawals from a vesting account, given as the only
+/// account, after the tokens have left the vault. Returning an error fails
+/// the withdrawal.
+#[interface]
+pub trait WithdrawCallback<'info, T: Accounts<'info>> {
+    fn on_withdraw(ctx: Context<T>, amount: u64) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule_bounds() {
+        assert!(is_valid_schedule(1_000, 1_001, 1));
+        assert!(is_valid_schedule(1_000, 1_010, 10));
+        assert!(!is_valid_schedule(1_000, 1_010, 11));
+        assert!(!is_valid_schedule(1_000, 1_010, 0));
+        assert!(!is_valid_schedule(1_000, 1_000, 1));
+        assert!(!is_valid_schedule(1_000, 999, 1));
+    }
+
+    #[test]
+    fn schedule_rejects_overflow() {
+        assert!(!is_valid_schedule(-1, 1_000, 1));
+        assert!(!is_valid_schedule(i64::MIN, i64::MAX, 1));
+        assert!(is_valid_schedule(0, i64::MAX, 1));
+    }
+
+    #[test]
+    fn schedule_start_cant_shift_before_epoch() {
+        // 5 seconds split in 2 shifts the start back by 1 second.
+        assert!(!is_valid_schedule(0, 5, 2));
+        assert!(is_valid_schedule(1, 6, 2));
+    }
+
+    #[test]
+    fn withdraw_fee_rounds_down() {
+        assert_eq!(withdraw_fee(100, 0, Rounding::Down).unwrap(), 0);
+        assert_eq!(withdraw_fee(100, 100, Rounding::Down).unwrap(), 1);
+        assert_eq!(withdraw_fee(99, 100, Rounding::Down).unwrap(), 0);
+        assert_eq!(
+            withdraw_fee(u64::MAX, 500, Rounding::Down).unwrap(),
+            u64::MAX / 20
+        );
+    }
+
+    #[test]
+    fn withdraw_fee_follows_the_rounding() {
+        // 1% of 150 is 1.5, and of 140, 1.4.
+        assert_eq!(withdraw_fee(150, 100, Rounding::Up).unwrap(), 2);
+        assert_eq!(withdraw_fee(140, 100, Rounding::Up).unwrap(), 2);
+        assert_eq!(withdraw_fee(100, 100, Rounding::Up).unwrap(), 1);
+        assert_eq!(withdraw_fee(150, 100, Rounding::Nearest).unwrap(), 2);
+        assert_eq!(withdraw_fee(140, 100, Rounding::Nearest).unwrap(), 1);
+        assert_eq!(withdraw_fee(100, 0, Rounding::Up).unwrap(), 0);
+    }
+
+    #[test]
+    fn milestones_must_be_sorted_and_complete() {
+        let m = |unlock_ts, cumulative_amount| Milestone {
+            unlock_ts,
+            cumulative_amount,
+        };
+        assert!(is_valid_milestones(0, 100, 10, &[]));
+        assert!(is_valid_milestones(0, 100, 10, &[m(50, 4), m(100, 10)]));
+        // Out of order.
+        assert!(!is_valid_milestones(0, 100, 10, &[m(100, 4), m(50, 10)]));
+        assert!(!is_valid_milestones(0, 100, 10, &[m(50, 4), m(50, 10)]));
+        assert!(!is_valid_milestones(0, 100, 10, &[m(50, 6), m(100, 4)]));
+        // Doesn't vest the deposit, or outside the schedule.
+        assert!(!is_valid_milestones(0, 100, 10, &[m(50, 4), m(100, 9)]));
+        assert!(!is_valid_milestones(0, 100, 10, &[m(50, 4), m(101, 10)]));
+        assert!(!is_valid_milestones(10, 100, 10, &[m(5, 4), m(100, 10)]));
+        // Too many.
+        let many = vec![m(100, 10); MAX_MILESTONES + 1];
+        assert!(!is_valid_milestones(0, 100, 10, &many));
+    }
+
+    // The layout of accounts created before versioning, i.e., version 0.
+    #[derive(AnchorSerialize)]
+    struct BaselineVesting {
+        beneficiary: Pubkey,
+        mint: Pubkey,
+        vault: Pubkey,
+        grantor: Pubkey,
+        outstanding: u64,
+        start_balance: u64,
+        created_ts: i64,
+        start_ts: i64,
+        end_ts: i64,
+        period_count: u64,
+        whitelist_owned: u64,
+        nonce: u8,
+        realizor: Option<Realizor>,
+    }
+
+    // Returns the error code of a failed check.
+    fn error_code(result: Result<()>) -> u32 {
+        match result {
+            Err(Error::AnchorError(e)) => e.error_code_number,
+            _ => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn relays_reject_reentrancy() {
+        let mut data = Vesting::DISCRIMINATOR.to_vec();
+        data.resize(8 + Vesting::INIT_SPACE, 0);
+        let mut vesting = Vesting::try_deserialize(&mut data.as_slice()).unwrap();
+        assert!(is_not_in_relay(&vesting).is_ok());
+        vesting.in_relay = true;
+        assert_eq!(
+            error_code(is_not_in_relay(&vesting)),
+            u32::from(ErrorCode::ReentrancyDetected)
+        );
+    }
+
+    // Mirrors `migrate_vesting`, which zero fills the reallocated account.
+    fn migrate(baseline: &BaselineVesting) -> Vesting {
+        let mut data = Vesting::DISCRIMINATOR.to_vec();
+        baseline.serialize(&mut data).unwrap();
+        data.resize(8 + Vesting::INIT_SPACE, 0);
+        Vesting::try_deserialize(&mut data.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn baseline_layout_migrates() {
+        for realizor in [
+            None,
+            Some(Realizor {
+                program: Pubkey::new_unique(),
+                metadata: Pubkey::new_unique(),
+            }),
+        ] {
+            let baseline = BaselineVesting {
+                beneficiary: Pubkey::new_unique(),
+                mint: Pubkey::new_unique(),
+                vault: Pubkey::new_unique(),
+                grantor: Pubkey::new_unique(),
+                outstanding: 70,
+                start_balance: 100,
+                created_ts: 900,
+                start_ts: 1_000,
+                end_ts: 2_000,
+                period_count: 10,
+                whitelist_owned: 5,
+                nonce: 254,
+                realizor: realizor.clone(),
+            };
+            let vesting = migrate(&baseline);
+            assert_eq!(vesting.beneficiary, baseline.beneficiary);
+            assert_eq!(vesting.mint, baseline.mint);
+            assert_eq!(vesting.vault, baseline.vault);
+            assert_eq!(vesting.grantor, baseline.grantor);
+            assert_eq!(vesting.outstanding, 70);
+            assert_eq!(vesting.start_balance, 100);
+            assert_eq!(vesting.created_ts, 900);
+            assert_eq!(vesting.start_ts, 1_000);
+            assert_eq!(vesting.end_ts, 2_000);
+            assert_eq!(vesting.period_count, 10);
+            assert_eq!(vesting.whitelist_owned, 5);
+            assert_eq!(vesting.nonce, 254);
+            assert_eq!(vesting.realizor, realizor);
+            // Every appended field takes its default.
+            assert_eq!(vesting.version, 0);
+            assert_eq!(vesting.cliff_ts, 0);
+            assert!(!vesting.revocable);
+            assert!(!vesting.frozen);
+            assert_eq!(vesting.schedule_kind, ScheduleKind::Linear);
+            assert_eq!(vesting.withdraw_count, 0);
+            // With no cliff, the schedule is unchanged.
+            assert_eq!(vesting.available(1_500), 50 - 30);
+            assert_eq!(vesting.available(1_800), 80 - 30);
+        }
+    }
+
+    #[test]
+    fn baseline_loans_migrate() {
+        let baseline = BaselineVesting {
+            beneficiary: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            vault: Pubkey::new_unique(),
+            grantor: Pubkey::new_unique(),
+            outstanding: 100,
+            start_balance: 100,
+            created_ts: 900,
+            start_ts: 1_000,
+            end_ts: 2_000,
+            period_count: 10,
+            whitelist_owned: 40,
+            nonce: 254,
+            realizor: None,
+        };
+        let program_id = Pubkey::new_unique();
+
+        // The loaned funds must be attributed to a program.
+        let mut vesting = migrate(&baseline);
+        assert_eq!(
+            error_code(vesting.migrate_baseline(None)),
+            u32::from(ErrorCode::LoanProgramRequired)
+        );
+
+        vesting.migrate_baseline(Some(program_id)).unwrap();
+        assert_eq!(vesting.loaned_to(&program_id), 40);
+        // Withdrawals must leave the loaned funds behind.
+        assert!(would_strand_whitelist_funds(&vesting, 100, 70));
+        assert!(!would_strand_whitelist_funds(&vesting, 100, 60));
+
+        // Depositing everything back frees up the vault.
+        assert_eq!(
+            error_code(vesting.repay_loan(&Pubkey::new_unique(), 40)),
+            u32::from(ErrorCode::WhitelistDepositOverflow)
+        );
+        vesting.repay_loan(&program_id, 40).unwrap();
+        assert_eq!(vesting.whitelist_owned, 0);
+        assert_eq!(vesting.loaned_to(&program_id), 0);
+        assert!(!would_strand_whitelist_funds(&vesting, 100, 100));
+
+        // Nothing to attribute without loans.
+        let mut vesting = migrate(&BaselineVesting {
+            whitelist_owned: 0,
+            ..baseline
+        });
+        vesting.migrate_baseline(None).unwrap();
+        assert!(vesting.whitelist_loans.iter().all(|l| l.amount == 0));
+    }
+}
@@ -6,7 +6,9 @@ use anchor_lang::prelude::*;
 use anchor_lang::solana_program::account_info::next_account_info;
 use anchor_lang::solana_program::program_option::COption;
 use anchor_spl::token::{self, Mint, TokenAccount, Transfer};
-use lockup::{CreateVesting, RealizeLock, Realizor, Vesting};
+use lockup::{
+    CreateVesting, PeriodRounding, RealizeLock, Realizor, ScheduleKind, Vesting, VestingParams,
+};
 use std::convert::Into;
 
 declare_id!("HmbTLCmaGvZhKnn1Zfa1JVnp7vkMV4DYVxPLWBVoN65L");
@@ -466,7 +468,6 @@ mod registry {
     #[access_control(reward_eligible(&ctx.accounts.cmn))]
     pub fn claim_reward_locked<'a, 'b, 'c, 'info>(
         ctx: Context<'a, 'b, 'c, 'info, ClaimRewardLocked<'info>>,
-        nonce: u8,
     ) -> Result<()> {
         let (start_ts, end_ts, period_count) = match ctx.accounts.cmn.vendor.kind {
             RewardVendorKind::Unlocked => return err!(ErrorCode::ExpectedLockedVendor),
@@ -512,18 +513,36 @@ mod registry {
                 depositor_authority: next_account_info(accs)?.to_account_info(),
                 token_program: next_account_info(accs)?.to_account_info(),
                 clock: next_account_info(accs)?.to_account_info(),
+                lockup: next_account_info(accs)?.to_account_info(),
+                mint: next_account_info(accs)?.to_account_info(),
+                grantor_tally: None,
             }
         };
         let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
         lockup::cpi::create_vesting(
             cpi_ctx,
-            ctx.accounts.cmn.member.beneficiary,
-            reward_amount,
-            nonce,
-            start_ts,
-            end_ts,
-            period_count,
-            realizor,
+            VestingParams {
+                beneficiary: ctx.accounts.cmn.member.beneficiary,
+                deposit_amount: reward_amount,
+                start_ts,
+                end_ts,
+                period_count,
+                cliff_ts: start_ts,
+                revocable: false,
+                accelerable: false,
+                period_rounding: PeriodRounding::FirstPeriod,
+                min_withdraw_interval: 0,
+                memo: [0; 64],
+                milestones: vec![],
+                final_bps: 0,
+                revoke_notice_seconds: 0,
+                requires_grantor_approval: false,
+                immutable: false,
+                schedule_kind: ScheduleKind::Linear,
+                curve_exponent: 0,
+                recovery_authority: None,
+                realizor,
+            },
         )?;
 
         // Make sure this reward can't be processed more than once.
@@ -0,0 +1,97 @@
+//! A whitelisted program used to exercise the lockup program's relay safety
+//! checks. It implements the whitelist transfer interface, but is free to
+//! misbehave in ways a real whitelisted program shouldn't.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_spl::token::{self, TokenAccount, Transfer};
+
+declare_id!("GWiihMwo5EaCuXtg6LgLpUaeBpfuZq1HZG8b8zqHA4Bt");
+
+#[program]
+pub mod relay_mock {
+    use super::*;
+
+    // Takes funds out of the lockup vault, as in a whitelist withdrawal.
+    pub fn take(ctx: Context<Relay>, amount: u64) -> Result<()> {
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.program_vault.to_account_info(),
+            authority: ctx.accounts.vesting_signer.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.clone(), cpi_accounts);
+        token::transfer(cpi_ctx, amount)
+    }
+
+    // Returns funds to the lockup vault, as in a whitelist deposit.
+    pub fn give(ctx: Context<Relay>, amount: u64) -> Result<()> {
+        let from = ctx.accounts.program_vault.to_account_info();
+        give_from(&ctx, from, amount)
+    }
+
+    // Returns funds from a token account of a different mint, given as the
+    // first remaining account.
+    pub fn give_other_mint(ctx: Context<Relay>, amount: u64) -> Result<()> {
+        let from = ctx.remaining_accounts[0].clone();
+        give_from(&ctx, from, amount)
+    }
+
+    // Calls back into the program given as the first remaining account,
+    // forwarding the rest of the remaining accounts.
+    pub fn reenter(ctx: Context<Relay>, data: Vec<u8>) -> Result<()> {
+        let accounts = &ctx.remaining_accounts[1..];
+        let ix = Instruction {
+            program_id: *ctx.remaining_accounts[0].key,
+            accounts: accounts
+                .iter()
+                .map(|a| AccountMeta {
+                    pubkey: *a.key,
+                    is_signer: a.is_signer,
+                    is_writable: a.is_writable,
+                })
+                .collect(),
+            data,
+        };
+        solana_program::program::invoke(&ix, accounts).map_err(Into::into)
+    }
+}
+
+fn give_from<'info>(
+    ctx: &Context<'_, '_, '_, 'info, Relay<'info>>,
+    from: AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    let seeds = &[
+        VAULT_AUTHORITY_SEED,
+        &[*ctx.bumps.get("program_vault_authority").unwrap()],
+    ];
+    let signer = &[&seeds[..]];
+    let cpi_accounts = Transfer {
+        from,
+        to: ctx.accounts.vault.to_account_info(),
+        authority: ctx.accounts.program_vault_authority.clone(),
+    };
+    let cpi_ctx =
+        CpiContext::new_with_signer(ctx.accounts.token_program.clone(), cpi_accounts, signer);
+    token::transfer(cpi_ctx, amount)
+}
+
+pub const VAULT_AUTHORITY_SEED: &[u8] = b"vault-authority";
+
+// Accounts ordered according to the lockup program's whitelist relay
+// interface.
+#[derive(Accounts)]
+pub struct Relay<'info> {
+    vesting: AccountInfo<'info>,
+    #[account(mut)]
+    vault: Account<'info, TokenAccount>,
+    #[account(signer)]
+    vesting_signer: AccountInfo<'info>,
+    #[account(constraint = token_program.key == &token::ID)]
+    token_program: AccountInfo<'info>,
+    #[account(mut)]
+    program_vault: Account<'info, TokenAccount>,
+    #[account(seeds = [VAULT_AUTHORITY_SEED], bump)]
+    program_vault_authority: AccountInfo<'info>,
+}
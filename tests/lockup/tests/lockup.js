@@ -1,6 +1,11 @@
 const anchor = require("@coral-xyz/anchor");
 const serumCmn = require("@project-serum/common");
-const { TOKEN_PROGRAM_ID } = require("@solana/spl-token");
+const {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  NATIVE_MINT,
+  Token,
+  TOKEN_PROGRAM_ID,
+} = require("@solana/spl-token");
 const utils = require("./utils");
 const { assert, expect } = require("chai");
 const nativeAssert = require("assert");
@@ -53,7 +58,12 @@ describe("Lockup and Registry", () => {
   });
 
   it("Deletes the default whitelisted addresses", async () => {
-    const defaultEntry = { programId: anchor.web3.PublicKey.default };
+    const defaultEntry = {
+      programId: anchor.web3.PublicKey.default,
+      maxAmount: new anchor.BN(0),
+      label: utils.whitelistLabel(""),
+      discriminator: Array(8).fill(0),
+    };
     await lockup.state.rpc.whitelistDelete(defaultEntry, {
       accounts: {
         authority: provider.wallet.publicKey,
@@ -69,201 +79,4090 @@ describe("Lockup and Registry", () => {
       },
     });
 
-    let lockupAccount = await lockup.state.fetch();
+    let lockupAccount = await lockup.state.fetch();
+    assert.isTrue(lockupAccount.authority.equals(newAuthority.publicKey));
+
+    await lockup.state.rpc.setAuthority(provider.wallet.publicKey, {
+      accounts: {
+        authority: newAuthority.publicKey,
+      },
+      signers: [newAuthority],
+    });
+
+    lockupAccount = await lockup.state.fetch();
+    assert.isTrue(lockupAccount.authority.equals(provider.wallet.publicKey));
+  });
+
+  const entries = [];
+
+  it("Adds to the whitelist", async () => {
+    const generateEntry = async () => {
+      let programId = anchor.web3.Keypair.generate().publicKey;
+      return {
+        programId,
+        maxAmount: new anchor.BN(1000),
+        label: utils.whitelistLabel(`program-${entries.length}`),
+        discriminator: Array(8).fill(0),
+      };
+    };
+
+    for (let k = 0; k < WHITELIST_SIZE; k += 1) {
+      entries.push(await generateEntry());
+    }
+
+    const accounts = {
+      authority: provider.wallet.publicKey,
+    };
+
+    await lockup.state.rpc.whitelistAdd(entries[0], { accounts });
+
+    let lockupAccount = await lockup.state.fetch();
+
+    assert.lengthOf(lockupAccount.whitelist, 1);
+    assert.strictEqual(
+      JSON.stringify(lockupAccount.whitelist),
+      JSON.stringify([entries[0]])
+    );
+
+    for (let k = 1; k < WHITELIST_SIZE; k += 1) {
+      await lockup.state.rpc.whitelistAdd(entries[k], { accounts });
+    }
+
+    lockupAccount = await lockup.state.fetch();
+
+    assert.strictEqual(
+      JSON.stringify(lockupAccount.whitelist),
+      JSON.stringify(entries)
+    );
+
+    await nativeAssert.rejects(
+      async () => {
+        const e = await generateEntry();
+        await lockup.state.rpc.whitelistAdd(e, { accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6008);
+        assert.strictEqual(err.error.errorMessage, "Whitelist is full");
+        return true;
+      }
+    );
+  });
+
+  it("Removes from the whitelist", async () => {
+    // Entries are matched by program, regardless of label.
+    const entry = { ...entries[0], label: utils.whitelistLabel("renamed") };
+    await lockup.state.rpc.whitelistDelete(entry, {
+      accounts: {
+        authority: provider.wallet.publicKey,
+      },
+    });
+    let lockupAccount = await lockup.state.fetch();
+    assert.strictEqual(
+      JSON.stringify(lockupAccount.whitelist),
+      JSON.stringify(entries.slice(1))
+    );
+  });
+
+  it("Adds many to the whitelist atomically", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
+    const entry = {
+      programId: anchor.web3.Keypair.generate().publicKey,
+      maxAmount: new anchor.BN(1000),
+      label: utils.whitelistLabel("batch"),
+      discriminator: Array(8).fill(0),
+    };
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.state.rpc.whitelistAddMany([entry, entry], { accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6009);
+        return true;
+      }
+    );
+    let lockupAccount = await lockup.state.fetch();
+    assert.lengthOf(lockupAccount.whitelist, WHITELIST_SIZE - 1);
+
+    await lockup.state.rpc.whitelistAddMany([entry], { accounts });
+    lockupAccount = await lockup.state.fetch();
+    assert.strictEqual(
+      JSON.stringify(lockupAccount.whitelist),
+      JSON.stringify([...entries.slice(1), entry])
+    );
+
+    await lockup.state.rpc.whitelistDelete(entry, { accounts });
+  });
+
+  it("Deletes many from the whitelist atomically", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
+    const entry = (name) => ({
+      programId: anchor.web3.Keypair.generate().publicKey,
+      maxAmount: new anchor.BN(1000),
+      label: utils.whitelistLabel(name),
+      discriminator: Array(8).fill(0),
+    });
+    const added = entry("added");
+    await lockup.state.rpc.whitelistAdd(added, { accounts });
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.state.rpc.whitelistDeleteMany([added, entry("missing")], {
+          accounts,
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6013);
+        return true;
+      }
+    );
+    let lockupAccount = await lockup.state.fetch();
+    assert.lengthOf(lockupAccount.whitelist, WHITELIST_SIZE);
+
+    await lockup.state.rpc.whitelistDeleteMany([added, entries[1]], {
+      accounts,
+    });
+    lockupAccount = await lockup.state.fetch();
+    assert.strictEqual(
+      JSON.stringify(lockupAccount.whitelist),
+      JSON.stringify(entries.slice(2))
+    );
+
+    await lockup.state.rpc.whitelistAdd(entries[1], { accounts });
+  });
+
+  const vesting = anchor.web3.Keypair.generate();
+  let vestingAccount = null;
+  let vestingSigner = null;
+
+  it("Creates a vesting account", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const endTs = new anchor.BN(startTs.toNumber() + 5);
+    const periodCount = new anchor.BN(2);
+    const beneficiary = provider.wallet.publicKey;
+    const depositAmount = new anchor.BN(100);
+
+    const vault = anchor.web3.Keypair.generate();
+    const [_vestingSigner, nonce] =
+      await anchor.web3.PublicKey.findProgramAddress(
+        [vesting.publicKey.toBuffer()],
+        lockup.programId
+      );
+    vestingSigner = _vestingSigner;
+
+    await lockup.rpc.createVesting(
+      {
+        beneficiary,
+        depositAmount,
+        startTs,
+        endTs,
+        periodCount,
+        cliffTs: startTs, // No cliff.
+        revocable: false,
+        accelerable: false,
+        periodRounding: { firstPeriod: {} },
+        minWithdrawInterval: new anchor.BN(0), // No withdrawal throttle.
+        memo: utils.vestingMemo("grant-1"),
+        milestones: [], // Linear schedule.
+        finalBps: 0, // Nothing held back until the end.
+        revokeNoticeSeconds: new anchor.BN(0), // Revocation is immediate.
+        requiresGrantorApproval: false,
+        immutable: false,
+        scheduleKind: { linear: {} }, // Vests gradually.
+        curveExponent: 0, // No front-loaded curve.
+        recoveryAuthority: null,
+        realizor: null, // Lock realizor is None.
+      },
+      {
+        accounts: {
+          vesting: vesting.publicKey,
+          vault: vault.publicKey,
+          depositor: god,
+          depositorAuthority: provider.wallet.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          lockup: lockupAddress,
+          mint,
+          grantorTally: lockup.programId,
+        },
+        signers: [vesting, vault],
+        instructions: [
+          await lockup.account.vesting.createInstruction(vesting),
+          ...(await serumCmn.createTokenAccountInstrs(
+            provider,
+            vault.publicKey,
+            mint,
+            vestingSigner
+          )),
+        ],
+      }
+    );
+
+    vestingAccount = await lockup.account.vesting.fetch(vesting.publicKey);
+
+    assert.isTrue(vestingAccount.beneficiary.equals(provider.wallet.publicKey));
+    assert.isTrue(vestingAccount.mint.equals(mint));
+    assert.isTrue(vestingAccount.grantor.equals(provider.wallet.publicKey));
+    assert.isTrue(vestingAccount.outstanding.eq(depositAmount));
+    assert.isTrue(vestingAccount.startBalance.eq(depositAmount));
+    assert.isTrue(vestingAccount.whitelistOwned.eq(new anchor.BN(0)));
+    assert.strictEqual(vestingAccount.nonce, nonce);
+    assert.isTrue(vestingAccount.createdTs.gt(new anchor.BN(0)));
+    assert.isTrue(vestingAccount.startTs.eq(startTs));
+    assert.isTrue(vestingAccount.endTs.eq(endTs));
+    assert.isTrue(vestingAccount.cliffTs.eq(startTs));
+    assert.isFalse(vestingAccount.revocable);
+    assert.isNull(vestingAccount.realizor);
+    assert.deepEqual(vestingAccount.memo, utils.vestingMemo("grant-1"));
+  });
+
+  it("Fails to create a vesting account with an invalid cliff", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const endTs = new anchor.BN(startTs.toNumber() + 5);
+    await nativeAssert.rejects(
+      async () => {
+        await utils.createVesting(lockup, provider, mint, god, {
+          startTs,
+          endTs,
+          cliffTs: endTs.addn(1),
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6019);
+        assert.strictEqual(
+          err.error.errorMessage,
+          "Vesting cliff must be within the vesting schedule."
+        );
+        return true;
+      }
+    );
+  });
+
+  it("Fails to create a vesting account from a different mint", async () => {
+    const [_otherMint, otherGod] = await serumCmn.createMintAndVault(
+      provider,
+      new anchor.BN(100),
+      provider.wallet.publicKey
+    );
+    await nativeAssert.rejects(
+      async () => {
+        await utils.createVesting(lockup, provider, mint, otherGod);
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6027);
+        return true;
+      }
+    );
+  });
+
+  it("Retries an idempotent vesting account creation", async () => {
+    const vesting = anchor.web3.Keypair.generate();
+    const [vestingSigner] = await anchor.web3.PublicKey.findProgramAddress(
+      [vesting.publicKey.toBuffer()],
+      lockup.programId
+    );
+    const vault = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      vestingSigner
+    );
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const params = {
+      beneficiary: provider.wallet.publicKey,
+      depositAmount: new anchor.BN(100),
+      startTs,
+      endTs: startTs.addn(60 * 60),
+      periodCount: new anchor.BN(2),
+      cliffTs: startTs,
+      revocable: false,
+      accelerable: false,
+      periodRounding: { firstPeriod: {} },
+      minWithdrawInterval: new anchor.BN(0),
+      memo: utils.vestingMemo("grant-retry"),
+      milestones: [],
+      finalBps: 0,
+      revokeNoticeSeconds: new anchor.BN(0),
+      requiresGrantorApproval: false,
+      immutable: false,
+      scheduleKind: { linear: {} },
+      curveExponent: 0,
+      recoveryAuthority: null,
+      realizor: null,
+    };
+    const create = (params) =>
+      lockup.rpc.createVestingIdempotent(params, {
+        accounts: {
+          vesting: vesting.publicKey,
+          vault,
+          depositor: god,
+          depositorAuthority: provider.wallet.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          lockup: lockupAddress,
+          mint,
+          payer: provider.wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          grantorTally: lockup.programId,
+        },
+        signers: [vesting],
+      });
+
+    await create(params);
+    // The retry lands on the existing account and deposits nothing.
+    await create(params);
+
+    const vestingAccount = await lockup.account.vesting.fetch(
+      vesting.publicKey
+    );
+    assert.isTrue(vestingAccount.startBalance.eq(new anchor.BN(100)));
+    const vaultAccount = await serumCmn.getTokenAccount(provider, vault);
+    assert.isTrue(vaultAccount.amount.eq(new anchor.BN(100)));
+
+    await nativeAssert.rejects(
+      async () => {
+        await create({ ...params, depositAmount: new anchor.BN(200) });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6065);
+        return true;
+      }
+    );
+  });
+
+  it("Creates a vesting account from a delegated allowance", async () => {
+    const vesting = anchor.web3.Keypair.generate();
+    const [vestingSigner] = await anchor.web3.PublicKey.findProgramAddress(
+      [vesting.publicKey.toBuffer()],
+      lockup.programId
+    );
+    const vault = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      vestingSigner
+    );
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const params = {
+      beneficiary: provider.wallet.publicKey,
+      depositAmount: new anchor.BN(100),
+      startTs,
+      endTs: startTs.addn(60 * 60),
+      periodCount: new anchor.BN(2),
+      cliffTs: startTs,
+      revocable: false,
+      accelerable: false,
+      periodRounding: { firstPeriod: {} },
+      minWithdrawInterval: new anchor.BN(0),
+      memo: utils.vestingMemo("grant-delegated"),
+      milestones: [],
+      finalBps: 0,
+      revokeNoticeSeconds: new anchor.BN(0),
+      requiresGrantorApproval: false,
+      immutable: false,
+      scheduleKind: { linear: {} },
+      curveExponent: 0,
+      recoveryAuthority: null,
+      realizor: null,
+    };
+    const create = async (allowance, depositorAuthority = null) =>
+      lockup.rpc.createVestingDelegated(params, {
+        accounts: {
+          vesting: vesting.publicKey,
+          vault,
+          vestingSigner,
+          depositor: god,
+          depositorAuthority: depositorAuthority
+            ? depositorAuthority.publicKey
+            : provider.wallet.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          lockup: lockupAddress,
+          mint,
+          grantorTally: lockup.programId,
+        },
+        signers: depositorAuthority ? [vesting, depositorAuthority] : [vesting],
+        instructions: [
+          await lockup.account.vesting.createInstruction(vesting),
+          Token.createApproveInstruction(
+            TOKEN_PROGRAM_ID,
+            god,
+            vestingSigner,
+            provider.wallet.publicKey,
+            [],
+            allowance
+          ),
+        ],
+      });
+
+    // An allowance short of the deposit is rejected.
+    await nativeAssert.rejects(
+      async () => {
+        await create(99);
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6082);
+        return true;
+      }
+    );
+    // Only the depositor's owner can spend the allowance.
+    await nativeAssert.rejects(
+      async () => {
+        await create(100, anchor.web3.Keypair.generate());
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6014);
+        return true;
+      }
+    );
+
+    await create(100);
+
+    const vestingAccount = await lockup.account.vesting.fetch(
+      vesting.publicKey
+    );
+    assert.isTrue(vestingAccount.grantor.equals(provider.wallet.publicKey));
+    assert.isTrue(vestingAccount.startBalance.eq(new anchor.BN(100)));
+    const vaultAccount = await serumCmn.getTokenAccount(provider, vault);
+    assert.isTrue(vaultAccount.amount.eq(new anchor.BN(100)));
+  });
+
+  it("Revokes unvested tokens from a vesting account", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      {
+        startTs,
+        endTs: startTs.addn(60 * 60),
+        revocable: true,
+      }
+    );
+    const godBefore = await serumCmn.getTokenAccount(provider, god);
+
+    await lockup.rpc.revoke({
+      accounts: {
+        vesting,
+        grantor: provider.wallet.publicKey,
+        vault,
+        vestingSigner,
+        token: god,
+        mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: lockupAddress,
+        grantorTally: lockup.programId,
+      },
+    });
+
+    const revoked = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(revoked.outstanding.eq(new anchor.BN(0)));
+    assert.isTrue(revoked.startBalance.eq(new anchor.BN(0)));
+    const godAfter = await serumCmn.getTokenAccount(provider, god);
+    assert.isTrue(godAfter.amount.eq(godBefore.amount.addn(100)));
+  });
+
+  it("Revokes part of the unvested tokens", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(60 * 60), revocable: true }
+    );
+    const accounts = {
+      vesting,
+      grantor: provider.wallet.publicKey,
+      vault,
+      vestingSigner,
+      token: god,
+      mint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      lockup: lockupAddress,
+      grantorTally: lockup.programId,
+    };
+    const godBefore = await serumCmn.getTokenAccount(provider, god);
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.revokePartial(new anchor.BN(101), { accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6064);
+        return true;
+      }
+    );
+
+    await lockup.rpc.revokePartial(new anchor.BN(25), { accounts });
+    const revoked = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(revoked.outstanding.eq(new anchor.BN(75)));
+    assert.isTrue(revoked.startBalance.eq(new anchor.BN(75)));
+    assert.isTrue(revoked.partiallyRevoked.eq(new anchor.BN(25)));
+    assert.isTrue(revoked.endTs.eq(startTs.addn(60 * 60)));
+    const godAfter = await serumCmn.getTokenAccount(provider, god);
+    assert.isTrue(godAfter.amount.eq(godBefore.amount.addn(25)));
+  });
+
+  it("Tallies a grantor's grants and revocations", async () => {
+    const [grantorTally] = await anchor.web3.PublicKey.findProgramAddress(
+      [Buffer.from("grantor-tally"), provider.wallet.publicKey.toBuffer()],
+      lockup.programId
+    );
+    await lockup.rpc.initGrantorTally({
+      accounts: {
+        grantorTally,
+        grantor: provider.wallet.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      },
+    });
+
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      {
+        startTs,
+        endTs: startTs.addn(60 * 60),
+        revocable: true,
+        grantorTally,
+      }
+    );
+    // Grants made without the tally aren't counted.
+    await utils.createVesting(lockup, provider, mint, god);
+
+    await lockup.rpc.revokePartial(new anchor.BN(25), {
+      accounts: {
+        vesting,
+        grantor: provider.wallet.publicKey,
+        vault,
+        vestingSigner,
+        token: god,
+        mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: lockupAddress,
+        grantorTally,
+      },
+    });
+
+    const tally = await lockup.account.grantorTally.fetch(grantorTally);
+    assert.isTrue(tally.grantor.equals(provider.wallet.publicKey));
+    assert.isTrue(tally.totalGranted.eq(new anchor.BN(100)));
+    assert.isTrue(tally.totalRevoked.eq(new anchor.BN(25)));
+    const committed = await lockup.methods
+      .grantorCommitted()
+      .accounts({ grantorTally })
+      .view();
+    assert.isTrue(committed.eq(new anchor.BN(75)));
+  });
+
+  it("Fails to revoke a non-revocable vesting account", async () => {
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god
+    );
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.revoke({
+          accounts: {
+            vesting,
+            grantor: provider.wallet.publicKey,
+            vault,
+            vestingSigner,
+            token: god,
+            mint,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+            lockup: lockupAddress,
+            grantorTally: lockup.programId,
+          },
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6020);
+        assert.strictEqual(
+          err.error.errorMessage,
+          "This vesting account cannot be revoked."
+        );
+        return true;
+      }
+    );
+  });
+
+  it("Schedules a revocation with a notice period", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      {
+        startTs,
+        endTs: startTs.addn(60 * 60),
+        revocable: true,
+        revokeNoticeSeconds: new anchor.BN(60 * 60),
+      }
+    );
+    const accounts = {
+      vesting,
+      grantor: provider.wallet.publicKey,
+      vault,
+      vestingSigner,
+      token: god,
+      mint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      lockup: lockupAddress,
+      grantorTally: lockup.programId,
+    };
+
+    // The first call only schedules the revocation.
+    await lockup.rpc.revoke({ accounts });
+    const scheduled = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(scheduled.outstanding.eq(new anchor.BN(100)));
+    assert.isTrue(
+      scheduled.revokeEffectiveTs.gte(scheduled.createdTs.addn(60 * 60))
+    );
+
+    // Nothing is clawed back until the notice period is over.
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.revoke({ accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6053);
+        assert.strictEqual(
+          err.error.errorMessage,
+          "The scheduled revocation hasn't taken effect yet."
+        );
+        return true;
+      }
+    );
+  });
+
+  it("Fails to withdraw from a vesting account before vesting", async () => {
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdraw(new anchor.BN(100), {
+          accounts: {
+            vesting: vesting.publicKey,
+            beneficiary: provider.wallet.publicKey,
+            token: god,
+            vault: vestingAccount.vault,
+            vestingSigner: vestingSigner,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+            coBeneficiary: lockup.programId,
+            withdrawDelegate: lockup.programId,
+            lockup: lockupAddress,
+            mint,
+            feeTreasury: lockup.programId,
+            grantor: lockup.programId,
+            withdrawCallbackProgram: lockup.programId,
+          },
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6007);
+        assert.strictEqual(
+          err.error.errorMessage,
+          "Insufficient withdrawal balance."
+        );
+        return true;
+      }
+    );
+  });
+
+  it("Creates a vesting account that starts next month", async () => {
+    const month = 60 * 60 * 24 * 30;
+    const startTs = new anchor.BN(Date.now() / 1000 + month);
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs: startTs.addn(12 * month),
+      periodCount: new anchor.BN(12),
+    });
+    const accounts = {
+      vesting,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      lockup: lockupAddress,
+    };
+
+    const check = await lockup.methods
+      .canWithdraw(new anchor.BN(1))
+      .accounts(accounts)
+      .view();
+    assert.isFalse(check.allowed);
+    assert.isTrue(check.available.eq(new anchor.BN(0)));
+
+    // The first unlock is a period after the start.
+    const next = await lockup.methods.nextUnlock().accounts(accounts).view();
+    assert.isTrue(next.ts.gt(startTs));
+
+    const remaining = await lockup.methods
+      .timeToFullVest()
+      .accounts(accounts)
+      .view();
+    assert.isTrue(remaining.gt(new anchor.BN(12 * month)));
+    assert.isTrue(remaining.lte(new anchor.BN(13 * month)));
+  });
+
+  it("Counts down to zero once fully vested", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs: startTs.addn(5),
+    });
+    const remaining = await lockup.methods
+      .timeToFullVest()
+      .accounts({
+        vesting,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: lockupAddress,
+      })
+      .view();
+    assert.isTrue(remaining.isZero());
+
+    const summary = await lockup.methods
+      .vestingSummary()
+      .accounts({
+        vesting,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: lockupAddress,
+      })
+      .view();
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(summary.beneficiary.equals(provider.wallet.publicKey));
+    assert.isTrue(summary.outstanding.eq(vestingAccount.outstanding));
+    assert.isTrue(summary.available.eq(vestingAccount.outstanding));
+    assert.isTrue(summary.endTs.eq(startTs.addn(5)));
+    assert.isTrue(summary.withdrawCount.isZero());
+  });
+
+  it("Pokes a vesting account to announce unlocks", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs: startTs.addn(5),
+    });
+    const accounts = {
+      vesting,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      lockup: lockupAddress,
+    };
+
+    let listener = null;
+    const [event] = await Promise.all([
+      new Promise((resolve) => {
+        listener = lockup.addEventListener("PeriodUnlocked", (event) =>
+          resolve(event)
+        );
+      }),
+      lockup.rpc.poke({ accounts }),
+    ]);
+    await lockup.removeEventListener(listener);
+
+    assert.isTrue(event.vesting.equals(vesting));
+    assert.isTrue(event.amount.eq(new anchor.BN(100)));
+    assert.isTrue(event.available.eq(new anchor.BN(100)));
+    let vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.lastPokeTs.eq(event.ts));
+
+    // Nothing more can vest, so poking again is a no-op.
+    await lockup.rpc.poke({ accounts });
+    vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.lastPokeTs.eq(event.ts));
+  });
+
+  it("Waits for a vesting period to pass", async () => {
+    await serumCmn.sleep(10 * 1000);
+  });
+
+  it("Withdraws from the vesting account", async () => {
+    const token = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      provider.wallet.publicKey
+    );
+
+    await lockup.rpc.withdraw(new anchor.BN(100), {
+      accounts: {
+        vesting: vesting.publicKey,
+        beneficiary: provider.wallet.publicKey,
+        token,
+        vault: vestingAccount.vault,
+        vestingSigner,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        coBeneficiary: lockup.programId,
+        withdrawDelegate: lockup.programId,
+        lockup: lockupAddress,
+        mint,
+        feeTreasury: lockup.programId,
+        grantor: lockup.programId,
+        withdrawCallbackProgram: lockup.programId,
+      },
+    });
+
+    vestingAccount = await lockup.account.vesting.fetch(vesting.publicKey);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(0)));
+
+    const vaultAccount = await serumCmn.getTokenAccount(
+      provider,
+      vestingAccount.vault
+    );
+    assert.isTrue(vaultAccount.amount.eq(new anchor.BN(0)));
+
+    const tokenAccount = await serumCmn.getTokenAccount(provider, token);
+    assert.isTrue(tokenAccount.amount.eq(new anchor.BN(100)));
+  });
+
+  it("Closes the fully withdrawn vesting account", async () => {
+    await lockup.rpc.closeVesting({
+      accounts: {
+        vesting: vesting.publicKey,
+        beneficiary: provider.wallet.publicKey,
+        vault: vestingAccount.vault,
+        vestingSigner,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      },
+    });
+
+    assert.isNull(await provider.connection.getAccountInfo(vesting.publicKey));
+    assert.isNull(
+      await provider.connection.getAccountInfo(vestingAccount.vault)
+    );
+  });
+
+  it("Sets a new beneficiary", async () => {
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god);
+    const newBeneficiary = anchor.web3.Keypair.generate();
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.setBeneficiary(anchor.web3.PublicKey.default, {
+          accounts: { vesting, beneficiary: provider.wallet.publicKey },
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6022);
+        return true;
+      }
+    );
+
+    await lockup.rpc.setBeneficiary(newBeneficiary.publicKey, {
+      accounts: { vesting, beneficiary: provider.wallet.publicKey },
+    });
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.beneficiary.equals(newBeneficiary.publicKey));
+  });
+
+  it("Clears the delegates when setting a new beneficiary", async () => {
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god);
+    const delegate = anchor.web3.Keypair.generate();
+    const accounts = { vesting, beneficiary: provider.wallet.publicKey };
+    await lockup.rpc.setWithdrawDelegate(delegate.publicKey, { accounts });
+    await lockup.rpc.setRelayInitiators([delegate.publicKey], { accounts });
+
+    await lockup.rpc.setBeneficiary(anchor.web3.Keypair.generate().publicKey, {
+      accounts,
+    });
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isNull(vestingAccount.withdrawDelegate);
+    assert.isTrue(
+      vestingAccount.relayInitiators.every((key) =>
+        key.equals(anchor.web3.PublicKey.default)
+      )
+    );
+  });
+
+  it("Emits an event when creating a vesting account", async () => {
+    let listener = null;
+    const [event, { vesting }] = await Promise.all([
+      new Promise((resolve) => {
+        listener = lockup.addEventListener("VestingCreated", (event) =>
+          resolve(event)
+        );
+      }),
+      utils.createVesting(lockup, provider, mint, god),
+    ]);
+    await lockup.removeEventListener(listener);
+
+    assert.isTrue(event.vesting.equals(vesting));
+    assert.isTrue(event.beneficiary.equals(provider.wallet.publicKey));
+    assert.isTrue(event.depositAmount.eq(new anchor.BN(100)));
+  });
+
+  it("Tops up an existing vesting account", async () => {
+    const { vesting, vault } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god
+    );
+    const accounts = {
+      vesting,
+      vault,
+      depositor: god,
+      depositorAuthority: provider.wallet.publicKey,
+      mint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      lockup: lockupAddress,
+    };
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.depositToVesting(new anchor.BN(0), { accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6002);
+        return true;
+      }
+    );
+
+    await lockup.rpc.depositToVesting(new anchor.BN(50), { accounts });
+
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(150)));
+    assert.isTrue(vestingAccount.startBalance.eq(new anchor.BN(150)));
+    const vaultAccount = await serumCmn.getTokenAccount(provider, vault);
+    assert.isTrue(vaultAccount.amount.eq(new anchor.BN(150)));
+  });
+
+  it("Returns the amount available for withdrawal", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs: startTs.addn(5),
+    });
+
+    const available = await lockup.methods
+      .availableForWithdrawal()
+      .accounts({
+        vesting,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: lockupAddress,
+      })
+      .view();
+    assert.isTrue(available.eq(new anchor.BN(100)));
+  });
+
+  const relayMock = anchor.workspace.RelayMock;
+  let relay = null;
+
+  it("Whitelists a program to relay vesting funds to", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 + 60 * 60);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(60 * 60) }
+    );
+    const [programVaultAuthority] =
+      await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from("vault-authority")],
+        relayMock.programId
+      );
+    const programVault = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      programVaultAuthority
+    );
+
+    await lockup.state.rpc.whitelistAdd(
+      {
+        programId: relayMock.programId,
+        maxAmount: new anchor.BN(1000),
+        label: utils.whitelistLabel("relay-mock"),
+        discriminator: Array(8).fill(0),
+      },
+      { accounts: { authority: provider.wallet.publicKey } }
+    );
+
+    relay = {
+      lockup: lockupAddress,
+      beneficiary: provider.wallet.publicKey,
+      whitelistedProgram: relayMock.programId,
+      vesting,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      whitelistedProgramVault: programVault,
+      whitelistedProgramVaultAuthority: programVaultAuthority,
+    };
+  });
+
+  it("Relays a whitelist withdrawal", async () => {
+    const amount = new anchor.BN(40);
+    await lockup.rpc.whitelistWithdraw(
+      relayMock.coder.instruction.encode("take", { amount }),
+      amount,
+      { accounts: { transfer: relay } }
+    );
+
+    const vestingAccount = await lockup.account.vesting.fetch(relay.vesting);
+    assert.isTrue(vestingAccount.whitelistOwned.eq(amount));
+    assert.isTrue(
+      vestingAccount.whitelistLoans[0].programId.equals(relayMock.programId)
+    );
+    assert.isTrue(vestingAccount.whitelistLoans[0].amount.eq(amount));
+  });
+
+  it("Reads the amount loaned to a whitelisted program", async () => {
+    const accounts = {
+      vesting: relay.vesting,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      lockup: lockupAddress,
+    };
+    const loaned = await lockup.methods
+      .loanedToProgram(relayMock.programId)
+      .accounts(accounts)
+      .view();
+    assert.isTrue(loaned.eq(new anchor.BN(40)));
+
+    const none = await lockup.methods
+      .loanedToProgram(anchor.web3.Keypair.generate().publicKey)
+      .accounts(accounts)
+      .view();
+    assert.isTrue(none.eq(new anchor.BN(0)));
+  });
+
+  it("Fails to relay a whitelist deposit of a different mint", async () => {
+    const [_otherMint, otherVault] = await serumCmn.createMintAndVault(
+      provider,
+      new anchor.BN(1000),
+      relay.whitelistedProgramVaultAuthority
+    );
+
+    await nativeAssert.rejects(async () => {
+      await lockup.rpc.whitelistDeposit(
+        relayMock.coder.instruction.encode("give_other_mint", {
+          amount: new anchor.BN(40),
+        }),
+        null, // No expected amount.
+        new anchor.BN(0),
+        {
+          accounts: { transfer: relay },
+          remainingAccounts: [
+            { pubkey: otherVault, isWritable: true, isSigner: false },
+          ],
+        }
+      );
+    });
+
+    const vestingAccount = await lockup.account.vesting.fetch(relay.vesting);
+    assert.isTrue(vestingAccount.whitelistOwned.eq(new anchor.BN(40)));
+  });
+
+  it("Relays a whitelist deposit", async () => {
+    await lockup.rpc.whitelistDeposit(
+      relayMock.coder.instruction.encode("give", {
+        amount: new anchor.BN(40),
+      }),
+      null, // No expected amount.
+      new anchor.BN(0),
+      { accounts: { transfer: relay } }
+    );
+
+    const vestingAccount = await lockup.account.vesting.fetch(relay.vesting);
+    assert.isTrue(vestingAccount.whitelistOwned.eq(new anchor.BN(0)));
+    assert.isTrue(
+      vestingAccount.whitelistLoans[0].amount.eq(new anchor.BN(0))
+    );
+    const vaultAccount = await serumCmn.getTokenAccount(provider, relay.vault);
+    assert.isTrue(vaultAccount.amount.eq(new anchor.BN(100)));
+  });
+
+  it("Fails to relay a whitelist deposit that shrinks the vault", async () => {
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.whitelistDeposit(
+          relayMock.coder.instruction.encode("take", {
+            amount: new anchor.BN(10),
+          }),
+          null, // No expected amount.
+          new anchor.BN(0),
+          { accounts: { transfer: relay } }
+        );
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6010);
+        return true;
+      }
+    );
+
+    const vaultAccount = await serumCmn.getTokenAccount(provider, relay.vault);
+    assert.isTrue(vaultAccount.amount.eq(new anchor.BN(100)));
+  });
+
+  it("Lets a relay initiator relay for the beneficiary", async () => {
+    const operator = anchor.web3.Keypair.generate();
+    const stranger = anchor.web3.Keypair.generate();
+    const amount = new anchor.BN(10);
+    const take = relayMock.coder.instruction.encode("take", { amount });
+    const setInitiators = (initiators) =>
+      lockup.rpc.setRelayInitiators(initiators, {
+        accounts: {
+          vesting: relay.vesting,
+          beneficiary: provider.wallet.publicKey,
+        },
+      });
+    await setInitiators([operator.publicKey]);
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.whitelistWithdraw(take, amount, {
+          accounts: {
+            transfer: { ...relay, beneficiary: stranger.publicKey },
+          },
+          signers: [stranger],
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6014);
+        return true;
+      }
+    );
+
+    const transfer = { ...relay, beneficiary: operator.publicKey };
+    await lockup.rpc.whitelistWithdraw(take, amount, {
+      accounts: { transfer },
+      signers: [operator],
+    });
+    let vestingAccount = await lockup.account.vesting.fetch(relay.vesting);
+    assert.isTrue(vestingAccount.whitelistOwned.eq(amount));
+
+    await lockup.rpc.whitelistDeposit(
+      relayMock.coder.instruction.encode("give", { amount }),
+      null, // No expected amount.
+      new anchor.BN(0),
+      { accounts: { transfer }, signers: [operator] }
+    );
+    await setInitiators([]);
+    vestingAccount = await lockup.account.vesting.fetch(relay.vesting);
+    assert.isTrue(vestingAccount.whitelistOwned.eq(new anchor.BN(0)));
+    assert.isTrue(
+      vestingAccount.relayInitiators.every((key) =>
+        key.equals(anchor.web3.PublicKey.default)
+      )
+    );
+  });
+
+  it("Fails to relay with another vesting account's vault", async () => {
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.whitelistWithdraw(
+          relayMock.coder.instruction.encode("take", {
+            amount: new anchor.BN(1),
+          }),
+          new anchor.BN(1),
+          {
+            accounts: {
+              transfer: { ...relay, vault: relay.whitelistedProgramVault },
+            },
+          }
+        );
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6055);
+        assert.strictEqual(
+          err.error.errorMessage,
+          "The vault isn't the vesting account's vault."
+        );
+        return true;
+      }
+    );
+  });
+
+  it("Returns the vesting schedule breakdown", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 + 60 * 60);
+    const endTs = startTs.addn(60 * 60);
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs,
+      periodCount: new anchor.BN(4),
+    });
+
+    const schedule = await lockup.methods
+      .vestingSchedule()
+      .accounts({
+        vesting,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: lockupAddress,
+      })
+      .view();
+    assert.isTrue(schedule.startTs.eq(startTs));
+    assert.isTrue(schedule.endTs.eq(endTs));
+    assert.isTrue(schedule.periodCount.eq(new anchor.BN(4)));
+    assert.isTrue(schedule.periodAmount.eq(new anchor.BN(25)));
+    assert.isTrue(schedule.nextUnlockTs.eq(startTs.addn(15 * 60)));
+  });
+
+  it("Projects the amount vested at any timestamp", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 + 60 * 60);
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs: startTs.addn(60 * 60),
+      periodCount: new anchor.BN(4),
+    });
+    const vestedAt = (ts) =>
+      lockup.methods
+        .vestedAt(ts)
+        .accounts({
+          vesting,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          lockup: lockupAddress,
+        })
+        .view();
+
+    assert.isTrue((await vestedAt(new anchor.BN(0))).eq(new anchor.BN(0)));
+    assert.isTrue(
+      (await vestedAt(startTs.addn(30 * 60))).eq(new anchor.BN(50))
+    );
+    assert.isTrue(
+      (await vestedAt(startTs.addn(2 * 60 * 60))).eq(new anchor.BN(100))
+    );
+  });
+
+  it("Releases a time lock all at once at the end", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const endTs = startTs.addn(60 * 60);
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs,
+      periodCount: new anchor.BN(4),
+      scheduleKind: { timeLock: {} },
+    });
+    const vestedAt = (ts) =>
+      lockup.methods
+        .vestedAt(ts)
+        .accounts({
+          vesting,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          lockup: lockupAddress,
+        })
+        .view();
+
+    // A four period linear schedule would have unlocked half by now.
+    assert.isTrue((await vestedAt(startTs.addn(30 * 60))).eq(new anchor.BN(0)));
+    assert.isTrue((await vestedAt(endTs.subn(1))).eq(new anchor.BN(0)));
+    assert.isTrue((await vestedAt(endTs)).eq(new anchor.BN(100)));
+  });
+
+  it("Front-loads a vesting schedule along its curve", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const endTs = startTs.addn(60 * 60);
+    const options = {
+      startTs,
+      endTs,
+      periodCount: new anchor.BN(4),
+      scheduleKind: { frontLoaded: {} },
+    };
+
+    // An exponent of one would be the linear schedule.
+    await nativeAssert.rejects(
+      async () => {
+        await utils.createVesting(lockup, provider, mint, god, {
+          ...options,
+          curveExponent: 1,
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6087);
+        return true;
+      }
+    );
+
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      ...options,
+      curveExponent: 2,
+    });
+    const vestedAt = (ts) =>
+      lockup.methods
+        .vestedAt(ts)
+        .accounts({
+          vesting,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          lockup: lockupAddress,
+        })
+        .view();
+
+    // 1 - (3/4)^2 and 1 - (1/2)^2 of the deposit, against 25 and 50 for
+    // the linear schedule.
+    assert.isTrue(
+      (await vestedAt(startTs.addn(15 * 60))).eq(new anchor.BN(43))
+    );
+    assert.isTrue(
+      (await vestedAt(startTs.addn(30 * 60))).eq(new anchor.BN(75))
+    );
+    assert.isTrue((await vestedAt(endTs)).eq(new anchor.BN(100)));
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.strictEqual(vestingAccount.curveExponent, 2);
+  });
+
+  it("Withdraws everything available from a vesting account", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const token = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      provider.wallet.publicKey
+    );
+
+    await lockup.rpc.withdrawAll({
+      accounts: {
+        vesting,
+        beneficiary: provider.wallet.publicKey,
+        token,
+        vault,
+        vestingSigner,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        coBeneficiary: lockup.programId,
+        withdrawDelegate: lockup.programId,
+        lockup: lockupAddress,
+        mint,
+        feeTreasury: lockup.programId,
+        grantor: lockup.programId,
+        withdrawCallbackProgram: lockup.programId,
+      },
+    });
+
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(0)));
+    assert.isTrue(vestingAccount.withdrawCount.eqn(1));
+    const tokenAccount = await serumCmn.getTokenAccount(provider, token);
+    assert.isTrue(tokenAccount.amount.eq(new anchor.BN(100)));
+  });
+
+  it("Freezes and thaws a vesting account", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const authAccounts = {
+      lockup: lockupAddress,
+      authority: provider.wallet.publicKey,
+      vesting,
+    };
+    const withdrawAccounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      token: god,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      coBeneficiary: lockup.programId,
+      withdrawDelegate: lockup.programId,
+      lockup: lockupAddress,
+      mint,
+      feeTreasury: lockup.programId,
+      grantor: lockup.programId,
+      withdrawCallbackProgram: lockup.programId,
+    };
+
+    await lockup.rpc.freezeVesting({ accounts: authAccounts });
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdraw(new anchor.BN(100), {
+          accounts: withdrawAccounts,
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6028);
+        assert.strictEqual(
+          err.error.errorMessage,
+          "The vesting account is frozen."
+        );
+        return true;
+      }
+    );
+
+    await lockup.rpc.thawVesting({ accounts: authAccounts });
+    await lockup.rpc.withdraw(new anchor.BN(100), {
+      accounts: withdrawAccounts,
+    });
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isFalse(vestingAccount.frozen);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(0)));
+  });
+
+  it("Returns the withdrawable amount formatted for display", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs: startTs.addn(5),
+    });
+
+    const available = await lockup.methods
+      .availableForWithdrawalUi()
+      .accounts({
+        vesting,
+        mint,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: lockupAddress,
+      })
+      .view();
+    const mintAccount = await serumCmn.getMintInfo(provider, mint);
+    assert.isTrue(available.amount.eq(new anchor.BN(100)));
+    assert.strictEqual(available.decimals, mintAccount.decimals);
+    assert.strictEqual(
+      available.uiAmount,
+      (100 / 10 ** mintAccount.decimals).toString()
+    );
+  });
+
+  it("Fails to re-enter the lockup program during a relay", async () => {
+    const amount = new anchor.BN(10);
+    const nested = lockup.coder.instruction.encode("whitelist_withdraw", {
+      instructionData: relayMock.coder.instruction.encode("take", { amount }),
+      amount,
+    });
+    const meta = (pubkey, isWritable, isSigner = false) => ({
+      pubkey,
+      isWritable,
+      isSigner,
+    });
+    const nestedAccounts = [
+      meta(relay.lockup, false),
+      meta(relay.beneficiary, false, true),
+      meta(relay.whitelistedProgram, false),
+      meta(relay.vesting, true),
+      meta(relay.vault, true),
+      meta(relay.vestingSigner, false),
+      meta(relay.tokenProgram, false),
+      meta(relay.whitelistedProgramVault, true),
+      meta(relay.whitelistedProgramVaultAuthority, false),
+    ];
+
+    // The runtime doesn't allow the relayed program to call back into the
+    // lockup program, so the `in_relay` guard is a second line of defense,
+    // covered by the program's unit tests.
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.whitelistWithdraw(
+          relayMock.coder.instruction.encode("reenter", { data: nested }),
+          amount,
+          {
+            accounts: { transfer: relay },
+            remainingAccounts: [
+              meta(lockup.programId, false),
+              ...nestedAccounts,
+            ],
+          }
+        );
+      },
+      (err) => {
+        assert.match(err.toString(), /reentrancy not allowed/);
+        return true;
+      }
+    );
+
+    const vestingAccount = await lockup.account.vesting.fetch(relay.vesting);
+    assert.isFalse(vestingAccount.inRelay);
+    assert.isTrue(vestingAccount.whitelistOwned.eq(new anchor.BN(0)));
+  });
+
+  it("Fails to withdraw from within a relay", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const transfer = { ...relay, vesting, vault, vestingSigner };
+    const token = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      provider.wallet.publicKey
+    );
+
+    // Everything has vested, so only reentrancy protection stops the relayed
+    // program from withdrawing mid-relay. The runtime rejects the callback
+    // before it reaches the lockup program's own `in_relay` guard, which the
+    // program's unit tests cover.
+    const nested = lockup.coder.instruction.encode("withdraw", {
+      amount: new anchor.BN(100),
+    });
+    const meta = (pubkey, isWritable, isSigner = false) => ({
+      pubkey,
+      isWritable,
+      isSigner,
+    });
+    const nestedAccounts = [
+      meta(vesting, true),
+      meta(provider.wallet.publicKey, false, true),
+      meta(vault, true),
+      meta(vestingSigner, false),
+      meta(token, true),
+      meta(mint, false),
+      meta(TOKEN_PROGRAM_ID, false),
+      meta(anchor.web3.SYSVAR_CLOCK_PUBKEY, false),
+      meta(lockup.programId, false),
+      meta(lockup.programId, false),
+      meta(lockupAddress, true),
+      meta(lockup.programId, false),
+      meta(lockup.programId, false),
+      meta(lockup.programId, false),
+    ];
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.whitelistWithdraw(
+          relayMock.coder.instruction.encode("reenter", { data: nested }),
+          new anchor.BN(0),
+          {
+            accounts: { transfer },
+            remainingAccounts: [
+              meta(lockup.programId, false),
+              ...nestedAccounts,
+            ],
+          }
+        );
+      },
+      (err) => {
+        assert.match(err.toString(), /reentrancy not allowed/);
+        return true;
+      }
+    );
+
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isFalse(vestingAccount.inRelay);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(100)));
+    assert.isTrue(vestingAccount.whitelistOwned.isZero());
+  });
+
+  it("Migrates the vault to a new token account", async () => {
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god
+    );
+    const accounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      vault,
+      vestingSigner,
+      mint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+
+    const badVault = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      provider.wallet.publicKey
+    );
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.migrateVault({
+          accounts: { ...accounts, newVault: badVault },
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6005);
+        return true;
+      }
+    );
+
+    const newVault = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      vestingSigner
+    );
+    await lockup.rpc.migrateVault({ accounts: { ...accounts, newVault } });
+
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.vault.equals(newVault));
+    const oldVaultAccount = await serumCmn.getTokenAccount(provider, vault);
+    assert.isTrue(oldVaultAccount.amount.eq(new anchor.BN(0)));
+    const newVaultAccount = await serumCmn.getTokenAccount(provider, newVault);
+    assert.isTrue(newVaultAccount.amount.eq(new anchor.BN(100)));
+  });
+
+  it("Rotates the vesting signer to a new nonce", async () => {
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god
+    );
+    const { nonce } = await lockup.account.vesting.fetch(vesting);
+    // Find the next valid, non-canonical bump below the canonical one.
+    let newNonce = nonce - 1;
+    let newVestingSigner = null;
+    while (newVestingSigner === null) {
+      try {
+        newVestingSigner = await anchor.web3.PublicKey.createProgramAddress(
+          [vesting.toBuffer(), Buffer.from([newNonce])],
+          lockup.programId
+        );
+      } catch (_err) {
+        newNonce -= 1;
+      }
+    }
+    const accounts = {
+      lockup: lockupAddress,
+      authority: provider.wallet.publicKey,
+      vesting,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+
+    // The new signer must derive from the given nonce.
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.rotateSigner(newNonce, {
+          accounts: { ...accounts, newVestingSigner: vestingSigner },
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6084);
+        return true;
+      }
+    );
+
+    await lockup.rpc.rotateSigner(newNonce, {
+      accounts: { ...accounts, newVestingSigner },
+    });
+
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.strictEqual(vestingAccount.nonce, newNonce);
+    const vaultAccount = await serumCmn.getTokenAccount(provider, vault);
+    assert.isTrue(vaultAccount.owner.equals(newVestingSigner));
+
+    // And back to the canonical bump, signing with the rotated signer.
+    await lockup.rpc.rotateSigner(nonce, {
+      accounts: {
+        ...accounts,
+        vestingSigner: newVestingSigner,
+        newVestingSigner: vestingSigner,
+      },
+    });
+    const restored = await serumCmn.getTokenAccount(provider, vault);
+    assert.isTrue(restored.owner.equals(vestingSigner));
+  });
+
+  it("Throttles withdrawals to the minimum interval", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      {
+        startTs,
+        endTs: startTs.addn(5),
+        minWithdrawInterval: new anchor.BN(60 * 60),
+      }
+    );
+    const token = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      provider.wallet.publicKey
+    );
+    const accounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      token,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      coBeneficiary: lockup.programId,
+      withdrawDelegate: lockup.programId,
+      lockup: lockupAddress,
+      mint,
+      feeTreasury: lockup.programId,
+      grantor: lockup.programId,
+      withdrawCallbackProgram: lockup.programId,
+    };
+
+    await lockup.rpc.withdraw(new anchor.BN(10), { accounts });
+    let vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.lastWithdrawTs.gtn(0));
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdraw(new anchor.BN(10), { accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6032);
+        return true;
+      }
+    );
+    vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(90)));
+  });
+
+  it("Requires the co-beneficiary to sign withdrawals", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const coBeneficiary = anchor.web3.Keypair.generate();
+    await lockup.rpc.setCoBeneficiary(coBeneficiary.publicKey, {
+      accounts: {
+        vesting,
+        beneficiary: provider.wallet.publicKey,
+        coBeneficiary: lockup.programId,
+      },
+    });
+
+    const accounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      token: god,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      withdrawDelegate: lockup.programId,
+      lockup: lockupAddress,
+      mint,
+      feeTreasury: lockup.programId,
+      grantor: lockup.programId,
+      withdrawCallbackProgram: lockup.programId,
+    };
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdraw(new anchor.BN(100), {
+          accounts: { ...accounts, coBeneficiary: lockup.programId },
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6033);
+        return true;
+      }
+    );
+
+    await lockup.rpc.withdraw(new anchor.BN(100), {
+      accounts: { ...accounts, coBeneficiary: coBeneficiary.publicKey },
+      signers: [coBeneficiary],
+    });
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.coBeneficiary.equals(coBeneficiary.publicKey));
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(0)));
+  });
+
+  it("Fails to create a vesting account with a pre-funded vault", async () => {
+    const vault = anchor.web3.Keypair.generate();
+    await nativeAssert.rejects(
+      async () => {
+        await utils.createVesting(lockup, provider, mint, god, {
+          vault,
+          extraInstructions: [
+            Token.createTransferInstruction(
+              TOKEN_PROGRAM_ID,
+              god,
+              vault.publicKey,
+              provider.wallet.publicKey,
+              [],
+              1
+            ),
+          ],
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6006);
+        return true;
+      }
+    );
+  });
+
+  it("Accelerates a vesting account", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 + 60 * 60);
+    const accounts = {
+      grantor: provider.wallet.publicKey,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      lockup: lockupAddress,
+    };
+
+    const { vesting: fixed } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(60 * 60) }
+    );
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.accelerate({
+          accounts: { ...accounts, vesting: fixed },
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6034);
+        return true;
+      }
+    );
+
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs: startTs.addn(60 * 60),
+      accelerable: true,
+    });
+    let listener = null;
+    const [event] = await Promise.all([
+      new Promise((resolve) => {
+        listener = lockup.addEventListener("VestingAccelerated", (event) =>
+          resolve(event)
+        );
+      }),
+      lockup.rpc.accelerate({ accounts: { ...accounts, vesting } }),
+    ]);
+    await lockup.removeEventListener(listener);
+
+    assert.isTrue(event.vesting.equals(vesting));
+    const available = await lockup.methods
+      .availableForWithdrawal()
+      .accounts({
+        vesting,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: lockupAddress,
+      })
+      .view();
+    assert.isTrue(available.eq(new anchor.BN(100)));
+  });
+
+  it("Fails to act as grantor without the grantor's signature", async () => {
+    const stranger = anchor.web3.Keypair.generate();
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { revocable: true, accelerable: true }
+    );
+    const unauthorized = (err) => {
+      assert.strictEqual(err.error.errorCode.number, 6014);
+      return true;
+    };
+
+    await nativeAssert.rejects(async () => {
+      await lockup.rpc.accelerate({
+        accounts: {
+          vesting,
+          grantor: stranger.publicKey,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          lockup: lockupAddress,
+        },
+        signers: [stranger],
+      });
+    }, unauthorized);
+    await nativeAssert.rejects(async () => {
+      await lockup.rpc.revoke({
+        accounts: {
+          vesting,
+          grantor: stranger.publicKey,
+          vault,
+          vestingSigner,
+          token: god,
+          mint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          lockup: lockupAddress,
+          grantorTally: lockup.programId,
+        },
+        signers: [stranger],
+      });
+    }, unauthorized);
+  });
+
+  it("Validates the schedule at its boundaries", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const endTs = startTs.addn(10);
+
+    // One period per second is the finest schedule allowed.
+    await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs,
+      periodCount: new anchor.BN(10),
+    });
+    await nativeAssert.rejects(
+      async () => {
+        await utils.createVesting(lockup, provider, mint, god, {
+          startTs,
+          endTs,
+          periodCount: new anchor.BN(11),
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6018);
+        return true;
+      }
+    );
+    await nativeAssert.rejects(
+      async () => {
+        await utils.createVesting(lockup, provider, mint, god, {
+          startTs: new anchor.BN(-1),
+          endTs,
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6000);
+        return true;
+      }
+    );
+  });
+
+  it("Simulates a withdrawal without transferring funds", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs: startTs.addn(5),
+    });
+    const accounts = {
+      vesting,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      lockup: lockupAddress,
+    };
+
+    const simulated = await lockup.methods
+      .simulateWithdraw(new anchor.BN(40))
+      .accounts(accounts)
+      .view();
+    assert.isTrue(simulated.newOutstanding.eq(new anchor.BN(60)));
+    assert.isTrue(simulated.remainingAvailable.eq(new anchor.BN(60)));
+
+    await nativeAssert.rejects(async () => {
+      await lockup.methods
+        .simulateWithdraw(new anchor.BN(101))
+        .accounts(accounts)
+        .view();
+    });
+
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(100)));
+  });
+
+  it("Checks whether an amount can be withdrawn", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs: startTs.addn(5),
+    });
+    const accounts = {
+      vesting,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      lockup: lockupAddress,
+    };
+
+    const ok = await lockup.methods
+      .canWithdraw(new anchor.BN(100))
+      .accounts(accounts)
+      .view();
+    assert.isTrue(ok.allowed);
+    assert.isTrue(ok.available.eq(new anchor.BN(100)));
+
+    const tooMuch = await lockup.methods
+      .canWithdraw(new anchor.BN(101))
+      .accounts(accounts)
+      .view();
+    assert.isFalse(tooMuch.allowed);
+    assert.isTrue(tooMuch.available.eq(new anchor.BN(100)));
+  });
+
+  it("Lets a withdraw delegate withdraw to the beneficiary", async () => {
+    const beneficiary = anchor.web3.Keypair.generate();
+    const delegate = anchor.web3.Keypair.generate();
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { beneficiary: beneficiary.publicKey, startTs, endTs: startTs.addn(5) }
+    );
+    await lockup.rpc.setWithdrawDelegate(delegate.publicKey, {
+      accounts: { vesting, beneficiary: beneficiary.publicKey },
+      signers: [beneficiary],
+    });
+
+    const token = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      beneficiary.publicKey
+    );
+    const accounts = {
+      vesting,
+      beneficiary: beneficiary.publicKey,
+      token,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      coBeneficiary: lockup.programId,
+      withdrawDelegate: delegate.publicKey,
+      lockup: lockupAddress,
+      mint,
+      feeTreasury: lockup.programId,
+      grantor: lockup.programId,
+      withdrawCallbackProgram: lockup.programId,
+    };
+
+    // The delegate can't redirect funds.
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdraw(new anchor.BN(100), {
+          accounts: { ...accounts, token: god },
+          signers: [delegate],
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6035);
+        return true;
+      }
+    );
+
+    await lockup.rpc.withdraw(new anchor.BN(100), {
+      accounts,
+      signers: [delegate],
+    });
+    const tokenAccount = await serumCmn.getTokenAccount(provider, token);
+    assert.isTrue(tokenAccount.amount.eq(new anchor.BN(100)));
+  });
+
+  it("Grows the whitelist", async () => {
+    const accounts = {
+      lockup: lockupAddress,
+      authority: provider.wallet.publicKey,
+      payer: provider.wallet.publicKey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+    const entry = () => ({
+      programId: anchor.web3.Keypair.generate().publicKey,
+      maxAmount: new anchor.BN(1000),
+      label: utils.whitelistLabel("grown"),
+      discriminator: Array(8).fill(0),
+    });
+    let lockupAccount = await lockup.state.fetch();
+    const capacity = lockupAccount.whitelistCapacity;
+    assert.strictEqual(lockupAccount.whitelist.length, capacity);
+
+    await lockup.rpc.growWhitelist(2, { accounts });
+    await lockup.state.rpc.whitelistAddMany([entry(), entry()], {
+      accounts: { authority: provider.wallet.publicKey },
+    });
+    lockupAccount = await lockup.state.fetch();
+    assert.strictEqual(lockupAccount.whitelistCapacity, capacity + 2);
+    assert.strictEqual(lockupAccount.whitelist.length, capacity + 2);
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.growWhitelist(64, { accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6036);
+        return true;
+      }
+    );
+  });
+
+  it("Returns the vesting signer address", async () => {
+    const { vesting, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god
+    );
+    const [, bump] = await anchor.web3.PublicKey.findProgramAddress(
+      [vesting.toBuffer()],
+      lockup.programId
+    );
+
+    const signer = await lockup.methods
+      .vestingSignerAddress()
+      .accounts({ vesting })
+      .view();
+    assert.isTrue(signer.address.equals(vestingSigner));
+    assert.strictEqual(signer.bump, bump);
+  });
+
+  it("Reconciles outstanding against the vault balance", async () => {
+    const { vesting, vault } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god
+    );
+    // Send funds to the vault directly, bypassing the bookkeeping.
+    await provider.sendAndConfirm(
+      new anchor.web3.Transaction().add(
+        Token.createTransferInstruction(
+          TOKEN_PROGRAM_ID,
+          god,
+          vault,
+          provider.wallet.publicKey,
+          [],
+          5
+        )
+      )
+    );
+    const accounts = {
+      lockup: lockupAddress,
+      authority: provider.wallet.publicKey,
+      vesting,
+      vault,
+    };
+
+    let listener = null;
+    const [event] = await Promise.all([
+      new Promise((resolve) => {
+        listener = lockup.addEventListener("ReconcileEvent", (event) =>
+          resolve(event)
+        );
+      }),
+      lockup.rpc.reconcile(false, { accounts }),
+    ]);
+    await lockup.removeEventListener(listener);
+    assert.isTrue(event.delta.eq(new anchor.BN(5)));
+    let vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(100)));
+
+    await lockup.rpc.reconcile(true, { accounts });
+    vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(105)));
+    assert.isTrue(vestingAccount.startBalance.eq(new anchor.BN(105)));
+  });
+
+  it("Enforces the lockup's minimum cliff", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
+    await lockup.state.rpc.setMinCliff(new anchor.BN(60), { accounts });
+    const lockupAccount = await lockup.state.fetch();
+    assert.isTrue(lockupAccount.minCliffSeconds.eq(new anchor.BN(60)));
+
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const endTs = startTs.addn(120);
+    await nativeAssert.rejects(
+      async () => {
+        await utils.createVesting(lockup, provider, mint, god, {
+          startTs,
+          endTs,
+          cliffTs: startTs.addn(59),
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6037);
+        return true;
+      }
+    );
+    await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs,
+      cliffTs: startTs.addn(60),
+    });
+
+    await lockup.state.rpc.setMinCliff(new anchor.BN(0), { accounts });
+  });
+
+  it("Enforces the lockup's minimum deposit", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
+    await lockup.state.rpc.setMinDeposit(new anchor.BN(50), { accounts });
+    try {
+      const lockupAccount = await lockup.state.fetch();
+      assert.isTrue(lockupAccount.minDepositAmount.eq(new anchor.BN(50)));
+
+      await nativeAssert.rejects(
+        async () => {
+          await utils.createVesting(lockup, provider, mint, god, {
+            depositAmount: new anchor.BN(49),
+          });
+        },
+        (err) => {
+          assert.strictEqual(err.error.errorCode.number, 6085);
+          return true;
+        }
+      );
+      await utils.createVesting(lockup, provider, mint, god, {
+        depositAmount: new anchor.BN(50),
+      });
+    } finally {
+      await lockup.state.rpc.setMinDeposit(new anchor.BN(0), { accounts });
+    }
+  });
+
+  it("Enforces the lockup's maximum duration", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
+    const year = 365 * 24 * 60 * 60;
+    await lockup.state.rpc.setMaxDuration(new anchor.BN(10 * year), {
+      accounts,
+    });
+    try {
+      const startTs = new anchor.BN(Date.now() / 1000);
+      // An end timestamp in milliseconds.
+      const endTs = new anchor.BN(Date.now() + 60 * 60 * 1000);
+      await nativeAssert.rejects(
+        async () => {
+          await utils.createVesting(lockup, provider, mint, god, {
+            startTs,
+            endTs,
+          });
+        },
+        (err) => {
+          assert.strictEqual(err.error.errorCode.number, 6088);
+          return true;
+        }
+      );
+      await utils.createVesting(lockup, provider, mint, god, {
+        startTs,
+        endTs: startTs.addn(10 * year),
+      });
+    } finally {
+      await lockup.state.rpc.setMaxDuration(new anchor.BN(0), { accounts });
+    }
+  });
+
+  it("Emits events when changing the whitelist and authority", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
+    const lockupAccount = await lockup.state.fetch();
+    const entry = lockupAccount.whitelist[lockupAccount.whitelist.length - 1];
+    const nextEvent = (name, rpc) => {
+      let listener = null;
+      return Promise.all([
+        new Promise((resolve) => {
+          listener = lockup.addEventListener(name, (event) => resolve(event));
+        }),
+        rpc(),
+      ]).then(async ([event]) => {
+        await lockup.removeEventListener(listener);
+        return event;
+      });
+    };
+
+    const deleted = await nextEvent("WhitelistDeleted", () =>
+      lockup.state.rpc.whitelistDelete(entry, { accounts })
+    );
+    assert.isTrue(deleted.authority.equals(provider.wallet.publicKey));
+    assert.isTrue(deleted.entry.programId.equals(entry.programId));
+
+    const added = await nextEvent("WhitelistAdded", () =>
+      lockup.state.rpc.whitelistAdd(entry, { accounts })
+    );
+    assert.isTrue(added.entry.programId.equals(entry.programId));
+
+    const changed = await nextEvent("AuthorityChanged", () =>
+      lockup.state.rpc.setAuthority(provider.wallet.publicKey, { accounts })
+    );
+    assert.isTrue(changed.authority.equals(provider.wallet.publicKey));
+    assert.isTrue(changed.newAuthority.equals(provider.wallet.publicKey));
+  });
+
+  it("Withdraws from several vesting accounts at once", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const token = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      provider.wallet.publicKey
+    );
+    const remainingAccounts = [];
+    const vestings = [];
+    for (let i = 0; i < 2; i += 1) {
+      const { vesting, vault, vestingSigner } = await utils.createVesting(
+        lockup,
+        provider,
+        mint,
+        god,
+        { startTs, endTs: startTs.addn(5) }
+      );
+      vestings.push(vesting);
+      remainingAccounts.push(
+        { pubkey: vesting, isWritable: true, isSigner: false },
+        { pubkey: vault, isWritable: true, isSigner: false },
+        { pubkey: vestingSigner, isWritable: false, isSigner: false },
+        { pubkey: token, isWritable: true, isSigner: false }
+      );
+    }
+    const accounts = {
+      beneficiary: provider.wallet.publicKey,
+      mint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      lockup: lockupAddress,
+    };
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdrawMany({
+          accounts,
+          remainingAccounts: remainingAccounts.slice(0, 3),
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6038);
+        return true;
+      }
+    );
+
+    await lockup.rpc.withdrawMany({ accounts, remainingAccounts });
+
+    for (const vesting of vestings) {
+      const vestingAccount = await lockup.account.vesting.fetch(vesting);
+      assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(0)));
+    }
+    const tokenAccount = await serumCmn.getTokenAccount(provider, token);
+    assert.isTrue(tokenAccount.amount.eq(new anchor.BN(200)));
+  });
+
+  it("Fails to withdraw to a token account of a different mint", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const [_otherMint, otherToken] = await serumCmn.createMintAndVault(
+      provider,
+      new anchor.BN(0),
+      provider.wallet.publicKey
+    );
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdraw(new anchor.BN(100), {
+          accounts: {
+            vesting,
+            beneficiary: provider.wallet.publicKey,
+            token: otherToken,
+            vault,
+            vestingSigner,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+            coBeneficiary: lockup.programId,
+            withdrawDelegate: lockup.programId,
+            lockup: lockupAddress,
+            mint,
+            feeTreasury: lockup.programId,
+            grantor: lockup.programId,
+            withdrawCallbackProgram: lockup.programId,
+          },
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6027);
+        return true;
+      }
+    );
+
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(100)));
+  });
+
+  it("Sweeps residual vault dust back to the grantor", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const sweepAccounts = {
+      vesting,
+      grantor: provider.wallet.publicKey,
+      vault,
+      vestingSigner,
+      token: god,
+      mint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      lockup: lockupAddress,
+      grantorTally: lockup.programId,
+    };
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.sweepDust({ accounts: sweepAccounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6021);
+        return true;
+      }
+    );
+
+    const token = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      provider.wallet.publicKey
+    );
+    await lockup.rpc.withdrawAll({
+      accounts: {
+        vesting,
+        beneficiary: provider.wallet.publicKey,
+        token,
+        vault,
+        vestingSigner,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        coBeneficiary: lockup.programId,
+        withdrawDelegate: lockup.programId,
+        lockup: lockupAddress,
+        mint,
+        feeTreasury: lockup.programId,
+        grantor: lockup.programId,
+        withdrawCallbackProgram: lockup.programId,
+      },
+    });
+    await provider.sendAndConfirm(
+      new anchor.web3.Transaction().add(
+        Token.createTransferInstruction(
+          TOKEN_PROGRAM_ID,
+          god,
+          vault,
+          provider.wallet.publicKey,
+          [],
+          3
+        )
+      )
+    );
+    const godBefore = await serumCmn.getTokenAccount(provider, god);
+
+    await lockup.rpc.sweepDust({ accounts: sweepAccounts });
+
+    const vaultAccount = await serumCmn.getTokenAccount(provider, vault);
+    assert.isTrue(vaultAccount.amount.eq(new anchor.BN(0)));
+    const godAfter = await serumCmn.getTokenAccount(provider, god);
+    assert.isTrue(godAfter.amount.sub(godBefore.amount).eq(new anchor.BN(3)));
+  });
+
+  it("Vests a step schedule at its milestones", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const endTs = startTs.addn(60 * 60);
+
+    await nativeAssert.rejects(
+      async () => {
+        await utils.createVesting(lockup, provider, mint, god, {
+          startTs,
+          endTs,
+          milestones: [
+            { unlockTs: startTs, cumulativeAmount: new anchor.BN(30) },
+            { unlockTs: endTs, cumulativeAmount: new anchor.BN(99) },
+          ],
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6040);
+        return true;
+      }
+    );
+
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs,
+      milestones: [
+        { unlockTs: startTs, cumulativeAmount: new anchor.BN(30) },
+        { unlockTs: endTs, cumulativeAmount: new anchor.BN(100) },
+      ],
+    });
+
+    const available = await lockup.methods
+      .availableForWithdrawal()
+      .accounts({
+        vesting,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: lockupAddress,
+      })
+      .view();
+    assert.isTrue(available.eq(new anchor.BN(30)));
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.strictEqual(vestingAccount.milestoneCount, 2);
+  });
+
+  it("Checks whether a program is whitelisted", async () => {
+    const isWhitelisted = (programId) =>
+      lockup.methods
+        .isProgramWhitelisted(programId)
+        .accounts({ lockup: lockupAddress })
+        .view();
+
+    const lockupAccount = await lockup.state.fetch();
+    const entry = lockupAccount.whitelist.find(
+      (e) => !e.programId.equals(anchor.web3.PublicKey.default)
+    );
+    assert.isTrue(await isWhitelisted(entry.programId));
+    assert.isFalse(
+      await isWhitelisted(anchor.web3.Keypair.generate().publicKey)
+    );
+  });
+
+  it("Reads the whitelist a page at a time", async () => {
+    const page = (offset, limit) =>
+      lockup.methods
+        .getWhitelistPage(offset, limit)
+        .accounts({ lockup: lockupAddress })
+        .view();
+
+    const lockupAccount = await lockup.state.fetch();
+    const whitelist = lockupAccount.whitelist;
+    const first = await page(0, 2);
+    assert.deepEqual(
+      first.map((e) => e.programId.toString()),
+      whitelist.slice(0, 2).map((e) => e.programId.toString())
+    );
+    // The last page is cut short at the end of the whitelist.
+    const last = await page(whitelist.length - 1, 12);
+    assert.strictEqual(last.length, 1);
+    assert.deepEqual(await page(whitelist.length, 1), []);
+
+    await nativeAssert.rejects(async () => {
+      await page(whitelist.length + 1, 1);
+    });
+    await nativeAssert.rejects(async () => {
+      await page(0, 13);
+    });
+  });
+
+  it("Ignores the test clock outside of test-clock builds", async () => {
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.state.rpc.setTestClock(new anchor.BN(1), {
+          accounts: { authority: provider.wallet.publicKey },
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6072);
+        return true;
+      }
+    );
+    const lockupAccount = await lockup.state.fetch();
+    assert.isTrue(lockupAccount.testClock.eq(new anchor.BN(0)));
+  });
+
+  it("Restricts relays to a vesting account's allowlist", async () => {
+    const accounts = {
+      vesting: relay.vesting,
+      authority: provider.wallet.publicKey,
+    };
+    const otherProgram = anchor.web3.Keypair.generate().publicKey;
+    await lockup.rpc.setProgramAllowlist([otherProgram], { accounts });
+    let vestingAccount = await lockup.account.vesting.fetch(relay.vesting);
+    assert.isTrue(vestingAccount.programAllowlist[0].equals(otherProgram));
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.whitelistWithdraw(
+          relayMock.coder.instruction.encode("take", {
+            amount: new anchor.BN(1),
+          }),
+          new anchor.BN(1),
+          { accounts: { transfer: relay } }
+        );
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6042);
+        return true;
+      }
+    );
+
+    await lockup.rpc.setProgramAllowlist([relayMock.programId], { accounts });
+    await lockup.rpc.setProgramAllowlist([], { accounts });
+    vestingAccount = await lockup.account.vesting.fetch(relay.vesting);
+    assert.isTrue(
+      vestingAccount.programAllowlist.every((p) =>
+        p.equals(anchor.web3.PublicKey.default)
+      )
+    );
+  });
+
+  it("Only lets the beneficiary narrow the grantor's allowlist", async () => {
+    const beneficiary = anchor.web3.Keypair.generate();
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      beneficiary: beneficiary.publicKey,
+    });
+    const otherProgram = anchor.web3.Keypair.generate().publicKey;
+    await lockup.rpc.setProgramAllowlist([relayMock.programId, otherProgram], {
+      accounts: { vesting, authority: provider.wallet.publicKey },
+    });
+
+    const setByBeneficiary = (programs) =>
+      lockup.rpc.setProgramAllowlist(programs, {
+        accounts: { vesting, authority: beneficiary.publicKey },
+        signers: [beneficiary],
+      });
+    const unauthorized = (err) => {
+      assert.strictEqual(err.error.errorCode.number, 6014);
+      return true;
+    };
+
+    await setByBeneficiary([relayMock.programId]);
+    let vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(
+      vestingAccount.programAllowlist[0].equals(relayMock.programId)
+    );
+    assert.isTrue(
+      vestingAccount.programAllowlist[1].equals(anchor.web3.PublicKey.default)
+    );
+
+    // Neither clearing nor re-adding a dropped program narrows the list.
+    await nativeAssert.rejects(async () => {
+      await setByBeneficiary([]);
+    }, unauthorized);
+    await nativeAssert.rejects(async () => {
+      await setByBeneficiary([relayMock.programId, otherProgram]);
+    }, unauthorized);
+
+    await lockup.rpc.setProgramAllowlist([], {
+      accounts: { vesting, authority: provider.wallet.publicKey },
+    });
+    vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(
+      vestingAccount.programAllowlist.every((p) =>
+        p.equals(anchor.web3.PublicKey.default)
+      )
+    );
+  });
+
+  it("Fails to withdraw funds owed back by whitelisted programs", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const amount = new anchor.BN(40);
+    await lockup.rpc.whitelistWithdraw(
+      relayMock.coder.instruction.encode("take", { amount }),
+      amount,
+      { accounts: { transfer: { ...relay, vesting, vault, vestingSigner } } }
+    );
+    const withdrawAccounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      token: god,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      coBeneficiary: lockup.programId,
+      withdrawDelegate: lockup.programId,
+      lockup: lockupAddress,
+      mint,
+      feeTreasury: lockup.programId,
+      grantor: lockup.programId,
+      withdrawCallbackProgram: lockup.programId,
+    };
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdraw(new anchor.BN(60), {
+          accounts: withdrawAccounts,
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6043);
+        return true;
+      }
+    );
+
+    await lockup.rpc.withdraw(new anchor.BN(20), {
+      accounts: withdrawAccounts,
+    });
+    const vaultAccount = await serumCmn.getTokenAccount(provider, vault);
+    assert.isTrue(vaultAccount.amount.eq(amount));
+  });
+
+  it("Restricts relays to the whitelist entry's instruction", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
+    const relayEntry = (discriminator) => ({
+      programId: relayMock.programId,
+      maxAmount: new anchor.BN(1000),
+      label: utils.whitelistLabel("relay-mock"),
+      discriminator,
+    });
+    const give = relayMock.coder.instruction.encode("give", {
+      amount: new anchor.BN(1),
+    });
+    await lockup.state.rpc.whitelistDelete(relayEntry(Array(8).fill(0)), {
+      accounts,
+    });
+    await lockup.state.rpc.whitelistAdd(relayEntry([...give.slice(0, 8)]), {
+      accounts,
+    });
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.whitelistWithdraw(
+          relayMock.coder.instruction.encode("take", {
+            amount: new anchor.BN(1),
+          }),
+          new anchor.BN(1),
+          { accounts: { transfer: relay } }
+        );
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6044);
+        return true;
+      }
+    );
+
+    await lockup.state.rpc.whitelistDelete(relayEntry(Array(8).fill(0)), {
+      accounts,
+    });
+    await lockup.state.rpc.whitelistAdd(relayEntry(Array(8).fill(0)), {
+      accounts,
+    });
+  });
+
+  it("Fails to relay to a whitelisted non-program account", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
+    const notAProgram = anchor.web3.Keypair.generate().publicKey;
+    const notAProgramEntry = {
+      programId: notAProgram,
+      maxAmount: new anchor.BN(1000),
+      label: utils.whitelistLabel("not-a-program"),
+      discriminator: Array(8).fill(0),
+    };
+    await lockup.state.rpc.whitelistAdd(notAProgramEntry, { accounts });
+    const transfer = { ...relay, whitelistedProgram: notAProgram };
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.whitelistWithdraw(
+          relayMock.coder.instruction.encode("take", {
+            amount: new anchor.BN(1),
+          }),
+          new anchor.BN(1),
+          { accounts: { transfer } }
+        );
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6077);
+        return true;
+      }
+    );
+
+    await lockup.state.rpc.whitelistDelete(notAProgramEntry, { accounts });
+  });
+
+  it("Fails to migrate a vesting account with the current layout", async () => {
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god);
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.strictEqual(vestingAccount.version, 17);
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.migrateVesting(null, {
+          accounts: {
+            lockup: lockupAddress,
+            authority: provider.wallet.publicKey,
+            vesting,
+            payer: provider.wallet.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          },
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6046);
+        return true;
+      }
+    );
+  });
+
+  it("Computes the period count for a vesting frequency", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const endTs = startTs.addn(4 * 365 * 24 * 60 * 60);
+    const periods = await lockup.methods
+      .periodsFor(startTs, endTs, { monthly: {} })
+      .accounts({})
+      .view();
+    assert.isTrue(periods.eq(new anchor.BN(48)));
+  });
+
+  it("Emits the loaned balance on every whitelist relay", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const transfer = { ...relay, vesting, vault, vestingSigner };
+    const relayed = async (rpc) => {
+      let listener = null;
+      const [event] = await Promise.all([
+        new Promise((resolve) => {
+          listener = lockup.addEventListener(
+            "WhitelistBalanceChanged",
+            (event) => resolve(event)
+          );
+        }),
+        rpc(),
+      ]);
+      await lockup.removeEventListener(listener);
+      return event;
+    };
+
+    const amount = new anchor.BN(40);
+    let event = await relayed(() =>
+      lockup.rpc.whitelistWithdraw(
+        relayMock.coder.instruction.encode("take", { amount }),
+        amount,
+        { accounts: { transfer } }
+      )
+    );
+    assert.isTrue(event.vesting.equals(vesting));
+    assert.isTrue(event.program.equals(relayMock.programId));
+    assert.isTrue(event.whitelistOwned.eq(amount));
+
+    event = await relayed(() =>
+      lockup.rpc.whitelistDeposit(
+        relayMock.coder.instruction.encode("give", {
+          amount: new anchor.BN(15),
+        }),
+        null, // No expected amount.
+        new anchor.BN(0),
+        { accounts: { transfer } }
+      )
+    );
+    assert.isTrue(event.whitelistOwned.eq(new anchor.BN(25)));
+  });
+
+  it("Emits the vault delta of every whitelist relay", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const transfer = { ...relay, vesting, vault, vestingSigner };
+    const relayed = async (rpc) => {
+      let listener = null;
+      const [event] = await Promise.all([
+        new Promise((resolve) => {
+          listener = lockup.addEventListener("RelayExecuted", (event) =>
+            resolve(event)
+          );
+        }),
+        rpc(),
+      ]);
+      await lockup.removeEventListener(listener);
+      return event;
+    };
+
+    const amount = new anchor.BN(40);
+    const take = relayMock.coder.instruction.encode("take", { amount });
+    let event = await relayed(() =>
+      lockup.rpc.whitelistWithdraw(take, amount, { accounts: { transfer } })
+    );
+    assert.isTrue(event.vesting.equals(vesting));
+    assert.isTrue(event.program.equals(relayMock.programId));
+    assert.deepEqual(event.discriminator, [...take.slice(0, 8)]);
+    assert.isTrue(event.vaultBefore.eq(new anchor.BN(100)));
+    assert.isTrue(event.vaultAfter.eq(new anchor.BN(60)));
+    assert.isTrue(event.delta.eq(new anchor.BN(-40)));
+
+    event = await relayed(() =>
+      lockup.rpc.whitelistDeposit(
+        relayMock.coder.instruction.encode("give", { amount }),
+        null, // No expected amount.
+        new anchor.BN(0),
+        { accounts: { transfer } }
+      )
+    );
+    assert.isTrue(event.vaultAfter.eq(new anchor.BN(100)));
+    assert.isTrue(event.delta.eq(amount));
+  });
+
+  it("Requires the withdraw callback program on withdrawal", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const callbackAccounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      lockup: lockupAddress,
+    };
+    const withdrawAccounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      token: god,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      coBeneficiary: lockup.programId,
+      withdrawDelegate: lockup.programId,
+      lockup: lockupAddress,
+      mint,
+      feeTreasury: lockup.programId,
+      grantor: lockup.programId,
+      withdrawCallbackProgram: lockup.programId,
+    };
+
+    // Only whitelisted programs can be called back.
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.setWithdrawCallback(
+          anchor.web3.Keypair.generate().publicKey,
+          { accounts: callbackAccounts }
+        );
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6067);
+        return true;
+      }
+    );
+
+    await lockup.rpc.setWithdrawCallback(relayMock.programId, {
+      accounts: callbackAccounts,
+    });
+    let vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(
+      vestingAccount.withdrawCallbackProgram.equals(relayMock.programId)
+    );
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdraw(new anchor.BN(100), {
+          accounts: withdrawAccounts,
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6067);
+        return true;
+      }
+    );
+
+    await lockup.rpc.setWithdrawCallback(null, { accounts: callbackAccounts });
+    await lockup.rpc.withdraw(new anchor.BN(100), {
+      accounts: withdrawAccounts,
+    });
+    vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(0)));
+  });
+
+  it("Checks a whitelist deposit against the expected amount", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(60 * 60) }
+    );
+    const transfer = { ...relay, vesting, vault, vestingSigner };
+    const amount = new anchor.BN(40);
+    await lockup.rpc.whitelistWithdraw(
+      relayMock.coder.instruction.encode("take", { amount }),
+      amount,
+      { accounts: { transfer } }
+    );
+    const give = relayMock.coder.instruction.encode("give", { amount });
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.whitelistDeposit(
+          give,
+          new anchor.BN(30),
+          new anchor.BN(5),
+          { accounts: { transfer } }
+        );
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6068);
+        return true;
+      }
+    );
+
+    // Within tolerance.
+    await lockup.rpc.whitelistDeposit(
+      give,
+      new anchor.BN(38),
+      new anchor.BN(2),
+      { accounts: { transfer } }
+    );
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.whitelistOwned.eq(new anchor.BN(0)));
+  });
+
+  it("Marks a vesting account with overdue loans delinquent", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(60 * 60) }
+    );
+    const transfer = { ...relay, vesting, vault, vestingSigner };
+    const authAccounts = {
+      lockup: lockupAddress,
+      authority: provider.wallet.publicKey,
+      vesting,
+    };
+    const amount = new anchor.BN(40);
+    const take = relayMock.coder.instruction.encode("take", { amount });
+    const forceReconcile = () =>
+      lockup.rpc.forceReconcile({ accounts: authAccounts });
+
+    // Loans are due back immediately.
+    await lockup.rpc.setRelayDeadline(new anchor.BN(0), {
+      accounts: authAccounts,
+    });
+    await nativeAssert.rejects(forceReconcile, (err) => {
+      assert.strictEqual(err.error.errorCode.number, 6073);
+      return true;
+    });
+
+    await lockup.rpc.whitelistWithdraw(take, amount, {
+      accounts: { transfer },
+    });
+    await serumCmn.sleep(2 * 1000);
+    await forceReconcile();
+    let vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.delinquent);
+
+    // No further loans until everything's returned.
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.whitelistWithdraw(take, amount, {
+          accounts: { transfer },
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6074);
+        return true;
+      }
+    );
+    await lockup.rpc.whitelistDeposit(
+      relayMock.coder.instruction.encode("give", { amount }),
+      null, // No expected amount.
+      new anchor.BN(0),
+      { accounts: { transfer } }
+    );
+    vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isFalse(vestingAccount.delinquent);
+    assert.isTrue(vestingAccount.loanDueTs.eq(new anchor.BN(0)));
+  });
+
+  it("Splits a vesting account in two", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const endTs = startTs.addn(60 * 60);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs, revocable: true }
+    );
+    const newBeneficiary = anchor.web3.Keypair.generate().publicKey;
+    const split = async (amount) => {
+      const newVesting = anchor.web3.Keypair.generate();
+      const newVault = anchor.web3.Keypair.generate();
+      const [newVestingSigner] =
+        await anchor.web3.PublicKey.findProgramAddress(
+          [newVesting.publicKey.toBuffer()],
+          lockup.programId
+        );
+      await lockup.rpc.splitVesting(amount, newBeneficiary, {
+        accounts: {
+          vesting,
+          beneficiary: provider.wallet.publicKey,
+          vault,
+          vestingSigner,
+          newVesting: newVesting.publicKey,
+          newVault: newVault.publicKey,
+          mint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          lockup: lockupAddress,
+          coBeneficiary: lockup.programId,
+        },
+        signers: [newVesting, newVault],
+        instructions: [
+          await lockup.account.vesting.createInstruction(newVesting),
+          ...(await serumCmn.createTokenAccountInstrs(
+            provider,
+            newVault.publicKey,
+            mint,
+            newVestingSigner
+          )),
+        ],
+      });
+      return { newVesting: newVesting.publicKey, newVault: newVault.publicKey };
+    };
+
+    const { newVesting, newVault } = await split(new anchor.BN(40));
+
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.startBalance.eq(new anchor.BN(60)));
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(60)));
+    const newVestingAccount = await lockup.account.vesting.fetch(newVesting);
+    assert.isTrue(newVestingAccount.beneficiary.equals(newBeneficiary));
+    assert.isTrue(newVestingAccount.startBalance.eq(new anchor.BN(40)));
+    assert.isTrue(newVestingAccount.outstanding.eq(new anchor.BN(40)));
+    assert.isTrue(newVestingAccount.startTs.eq(startTs));
+    assert.isTrue(newVestingAccount.endTs.eq(endTs));
+    assert.isTrue(newVestingAccount.periodCount.eq(new anchor.BN(2)));
+    assert.isTrue(newVestingAccount.revocable);
+    const newVaultAccount = await serumCmn.getTokenAccount(provider, newVault);
+    assert.isTrue(newVaultAccount.amount.eq(new anchor.BN(40)));
+    const vaultAccount = await serumCmn.getTokenAccount(provider, vault);
+    assert.isTrue(vaultAccount.amount.eq(new anchor.BN(60)));
+
+    // Can't split more than what's left.
+    await nativeAssert.rejects(
+      async () => {
+        await split(new anchor.BN(61));
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6069);
+        return true;
+      }
+    );
+  });
+
+  it("Splits a partially revoked vesting account", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(60 * 60), revocable: true }
+    );
+    await lockup.rpc.revokePartial(new anchor.BN(25), {
+      accounts: {
+        vesting,
+        grantor: provider.wallet.publicKey,
+        vault,
+        vestingSigner,
+        token: god,
+        mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: lockupAddress,
+        grantorTally: lockup.programId,
+      },
+    });
+
+    const newVesting = anchor.web3.Keypair.generate();
+    const newVault = anchor.web3.Keypair.generate();
+    const [newVestingSigner] = await anchor.web3.PublicKey.findProgramAddress(
+      [newVesting.publicKey.toBuffer()],
+      lockup.programId
+    );
+    await lockup.rpc.splitVesting(
+      new anchor.BN(30),
+      anchor.web3.Keypair.generate().publicKey,
+      {
+        accounts: {
+          vesting,
+          beneficiary: provider.wallet.publicKey,
+          vault,
+          vestingSigner,
+          newVesting: newVesting.publicKey,
+          newVault: newVault.publicKey,
+          mint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          lockup: lockupAddress,
+          coBeneficiary: lockup.programId,
+        },
+        signers: [newVesting, newVault],
+        instructions: [
+          await lockup.account.vesting.createInstruction(newVesting),
+          ...(await serumCmn.createTokenAccountInstrs(
+            provider,
+            newVault.publicKey,
+            mint,
+            newVestingSigner
+          )),
+        ],
+      }
+    );
+
+    // 30 of the remaining 75 is 40%, taking 40% of the 25 revoked along.
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.startBalance.eq(new anchor.BN(45)));
+    assert.isTrue(vestingAccount.partiallyRevoked.eq(new anchor.BN(15)));
+    const newVestingAccount = await lockup.account.vesting.fetch(
+      newVesting.publicKey
+    );
+    assert.isTrue(newVestingAccount.startBalance.eq(new anchor.BN(30)));
+    assert.isTrue(newVestingAccount.partiallyRevoked.eq(new anchor.BN(10)));
+    assert.isTrue(
+      newVestingAccount.lastWithdrawTs.eq(vestingAccount.lastWithdrawTs)
+    );
+  });
+
+  it("Recovers a vesting account to the grantor", async () => {
+    const recoveryAuthority = anchor.web3.Keypair.generate();
+    // Initialized when tallying grants and revocations.
+    const [grantorTally] = await anchor.web3.PublicKey.findProgramAddress(
+      [Buffer.from("grantor-tally"), provider.wallet.publicKey.toBuffer()],
+      lockup.programId
+    );
+    const committed = () =>
+      lockup.methods.grantorCommitted().accounts({ grantorTally }).view();
+    const committedBefore = await committed();
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      {
+        startTs,
+        endTs: startTs.addn(60 * 60),
+        recoveryAuthority: recoveryAuthority.publicKey,
+        grantorTally,
+      }
+    );
+    const accounts = {
+      vesting,
+      recoveryAuthority: recoveryAuthority.publicKey,
+      vault,
+      vestingSigner,
+      destination: god,
+      mint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      lockup: lockupAddress,
+      grantorTally,
+    };
+
+    // Only the recovery authority can recover.
+    const impostor = anchor.web3.Keypair.generate();
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.recover({
+          accounts: { ...accounts, recoveryAuthority: impostor.publicKey },
+          signers: [impostor],
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6014);
+        return true;
+      }
+    );
+
+    // Dust sent to the vault doesn't get in the way.
+    await provider.sendAndConfirm(
+      new anchor.web3.Transaction().add(
+        Token.createTransferInstruction(
+          TOKEN_PROGRAM_ID,
+          god,
+          vault,
+          provider.wallet.publicKey,
+          [],
+          3
+        )
+      )
+    );
+    const godBefore = await serumCmn.getTokenAccount(provider, god);
+    await lockup.rpc.recover({ accounts, signers: [recoveryAuthority] });
+    const godAfter = await serumCmn.getTokenAccount(provider, god);
+    assert.isTrue(godAfter.amount.sub(godBefore.amount).eq(new anchor.BN(100)));
+
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(0)));
+    const vaultAccount = await serumCmn.getTokenAccount(provider, vault);
+    assert.isTrue(vaultAccount.amount.eq(new anchor.BN(3)));
+    // The recovered grant no longer counts as committed.
+    assert.isTrue((await committed()).eq(committedBefore));
+  });
+
+  it("Vests a second vault on the same schedule", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const [otherMint, otherGod] = await serumCmn.createMintAndVault(
+      provider,
+      new anchor.BN(500),
+      provider.wallet.publicKey
+    );
+    const otherVault = await serumCmn.createTokenAccount(
+      provider,
+      otherMint,
+      vestingSigner
+    );
+    await lockup.rpc.addVault(new anchor.BN(500), {
+      accounts: {
+        vesting,
+        grantor: provider.wallet.publicKey,
+        vault: otherVault,
+        depositor: otherGod,
+        mint: otherMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        lockup: lockupAddress,
+      },
+    });
+
+    const token = await serumCmn.createTokenAccount(
+      provider,
+      otherMint,
+      provider.wallet.publicKey
+    );
+    const accounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      vault: otherVault,
+      vestingSigner,
+      token,
+      mint: otherMint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      coBeneficiary: lockup.programId,
+      grantor: lockup.programId,
+      lockup: lockupAddress,
+    };
+
+    // Only the vault's own deposit is available.
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdrawFromVault(0, new anchor.BN(501), {
+          accounts,
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6007);
+        return true;
+      }
+    );
+    await lockup.rpc.withdrawFromVault(0, new anchor.BN(500), { accounts });
+
+    const tokenAccount = await serumCmn.getTokenAccount(provider, token);
+    assert.isTrue(tokenAccount.amount.eq(new anchor.BN(500)));
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.strictEqual(vestingAccount.extraVaultCount, 1);
+    assert.isTrue(vestingAccount.extraVaults[0].outstanding.isZero());
+    // The primary vault is untouched.
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(100)));
+
+    // Once both are empty, both vaults are closed with the account.
+    await lockup.rpc.withdrawAll({
+      accounts: {
+        vesting,
+        beneficiary: provider.wallet.publicKey,
+        token: god,
+        vault,
+        vestingSigner,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        coBeneficiary: lockup.programId,
+        withdrawDelegate: lockup.programId,
+        lockup: lockupAddress,
+        mint,
+        feeTreasury: lockup.programId,
+        grantor: lockup.programId,
+        withdrawCallbackProgram: lockup.programId,
+      },
+    });
+    const closeAccounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.closeVesting({ accounts: closeAccounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6075);
+        return true;
+      }
+    );
+    await lockup.rpc.closeVesting({
+      accounts: closeAccounts,
+      remainingAccounts: [
+        { pubkey: otherVault, isWritable: true, isSigner: false },
+      ],
+    });
+    assert.isNull(await provider.connection.getAccountInfo(vesting));
+    assert.isNull(await provider.connection.getAccountInfo(otherVault));
+  });
+
+  it("Withdraws only to allowed destinations", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const allowed = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      provider.wallet.publicKey
+    );
+    const other = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      provider.wallet.publicKey
+    );
+    const allowlistAccounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+    };
+    // Nothing is restricted yet, so this applies right away.
+    await lockup.rpc.setDestinationAllowlist([allowed], {
+      accounts: allowlistAccounts,
+    });
+
+    const accounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      token: other,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      coBeneficiary: lockup.programId,
+      withdrawDelegate: lockup.programId,
+      lockup: lockupAddress,
+      mint,
+      feeTreasury: lockup.programId,
+      grantor: lockup.programId,
+      withdrawCallbackProgram: lockup.programId,
+    };
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdraw(new anchor.BN(10), { accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6078);
+        return true;
+      }
+    );
+    await lockup.rpc.withdraw(new anchor.BN(10), {
+      accounts: { ...accounts, token: allowed },
+    });
+
+    // Lifting the restriction takes the full delay.
+    await lockup.rpc.setDestinationAllowlist([], {
+      accounts: allowlistAccounts,
+    });
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.applyDestinationAllowlist({
+          accounts: allowlistAccounts,
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6080);
+        return true;
+      }
+    );
+    await lockup.rpc.cancelDestinationAllowlist({
+      accounts: allowlistAccounts,
+    });
+
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.destinationAllowlist[0].equals(allowed));
+    assert.isTrue(vestingAccount.destinationAllowlistEffectiveTs.isZero());
+  });
+
+  it("Limits the number of accounts forwarded by a relay", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
+    let lockupAccount = await lockup.state.fetch();
+    assert.strictEqual(lockupAccount.maxRelayAccounts, 32);
+
+    await lockup.state.rpc.setMaxRelayAccounts(0, { accounts });
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.whitelistWithdraw(
+          relayMock.coder.instruction.encode("take", {
+            amount: new anchor.BN(1),
+          }),
+          new anchor.BN(1),
+          {
+            accounts: { transfer: relay },
+            remainingAccounts: [
+              { pubkey: mint, isWritable: false, isSigner: false },
+            ],
+          }
+        );
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6047);
+        return true;
+      }
+    );
+
+    await lockup.state.rpc.setMaxRelayAccounts(32, { accounts });
+    lockupAccount = await lockup.state.fetch();
+    assert.strictEqual(lockupAccount.maxRelayAccounts, 32);
+  });
+
+  it("Withdraws only to the associated token account", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const associatedToken = await Token.getAssociatedTokenAddress(
+      ASSOCIATED_TOKEN_PROGRAM_ID,
+      TOKEN_PROGRAM_ID,
+      mint,
+      provider.wallet.publicKey
+    );
+    const accounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      token: god,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      coBeneficiary: lockup.programId,
+      withdrawDelegate: lockup.programId,
+      lockup: lockupAddress,
+      mint,
+      feeTreasury: lockup.programId,
+      grantor: lockup.programId,
+      withdrawCallbackProgram: lockup.programId,
+    };
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdrawToAssociated(new anchor.BN(10), { accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6048);
+        return true;
+      }
+    );
+
+    await lockup.rpc.withdrawToAssociated(new anchor.BN(10), {
+      accounts: { ...accounts, token: associatedToken },
+      instructions: [
+        Token.createAssociatedTokenAccountInstruction(
+          ASSOCIATED_TOKEN_PROGRAM_ID,
+          TOKEN_PROGRAM_ID,
+          mint,
+          associatedToken,
+          provider.wallet.publicKey,
+          provider.wallet.publicKey
+        ),
+      ],
+    });
+    const tokenAccount = await serumCmn.getTokenAccount(
+      provider,
+      associatedToken
+    );
+    assert.isTrue(tokenAccount.amount.eq(new anchor.BN(10)));
+  });
+
+  it("Withdraws wrapped SOL as native SOL", async () => {
+    const depositor = await Token.createWrappedNativeAccount(
+      provider.connection,
+      TOKEN_PROGRAM_ID,
+      provider.wallet.publicKey,
+      provider.wallet.payer,
+      1000
+    );
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      NATIVE_MINT,
+      depositor,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const token = await serumCmn.createTokenAccount(
+      provider,
+      NATIVE_MINT,
+      provider.wallet.publicKey
+    );
+    const accounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      token,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      coBeneficiary: lockup.programId,
+      withdrawDelegate: lockup.programId,
+      lockup: lockupAddress,
+      mint: NATIVE_MINT,
+      feeTreasury: lockup.programId,
+      grantor: lockup.programId,
+      withdrawCallbackProgram: lockup.programId,
+    };
+    await lockup.rpc.withdrawUnwrap(new anchor.BN(100), { accounts });
+
+    // The temporary account is gone, its lamports sent to the beneficiary.
+    assert.isNull(await provider.connection.getAccountInfo(token));
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.outstanding.isZero());
+
+    // Other mints can't be unwrapped.
+    const other = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs: startTs.addn(5),
+    });
+    const otherToken = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      provider.wallet.publicKey
+    );
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdrawUnwrap(new anchor.BN(100), {
+          accounts: { ...accounts, ...other, token: otherToken, mint },
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6081);
+        return true;
+      }
+    );
+  });
+
+  it("Tracks the total outstanding across vesting accounts", async () => {
+    const totalOutstanding = () =>
+      lockup.methods
+        .totalOutstanding()
+        .accounts({ lockup: lockupAddress })
+        .view();
+    const before = await totalOutstanding();
+
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    assert.isTrue((await totalOutstanding()).eq(before.addn(100)));
+
+    await lockup.rpc.withdraw(new anchor.BN(30), {
+      accounts: {
+        vesting,
+        beneficiary: provider.wallet.publicKey,
+        token: god,
+        vault,
+        vestingSigner,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        coBeneficiary: lockup.programId,
+        withdrawDelegate: lockup.programId,
+        lockup: lockupAddress,
+        mint,
+        feeTreasury: lockup.programId,
+        grantor: lockup.programId,
+        withdrawCallbackProgram: lockup.programId,
+      },
+    });
+    assert.isTrue((await totalOutstanding()).eq(before.addn(70)));
+  });
+
+  it("Holds back the final unlock until the end of the schedule", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const endTs = startTs.addn(60 * 60);
+
+    await nativeAssert.rejects(
+      async () => {
+        await utils.createVesting(lockup, provider, mint, god, {
+          startTs,
+          endTs,
+          finalBps: 10001,
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6049);
+        return true;
+      }
+    );
+
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god, {
+      startTs,
+      endTs,
+      periodCount: new anchor.BN(1),
+      finalBps: 10000,
+    });
+    const available = await lockup.methods
+      .availableForWithdrawal()
+      .accounts({
+        vesting,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: lockupAddress,
+      })
+      .view();
+    assert.isTrue(available.eq(new anchor.BN(0)));
+  });
+
+  it("Hands over the authority in two steps", async () => {
+    const newAuthority = anchor.web3.Keypair.generate();
+    const accounts = { authority: provider.wallet.publicKey };
+    const newAccounts = { authority: newAuthority.publicKey };
+
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.state.rpc.acceptAuthority({
+          accounts: newAccounts,
+          signers: [newAuthority],
+        });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6050);
+        return true;
+      }
+    );
+
+    await lockup.state.rpc.proposeAuthority(newAuthority.publicKey, {
+      accounts,
+    });
+    let lockupAccount = await lockup.state.fetch();
+    assert.isTrue(lockupAccount.authority.equals(provider.wallet.publicKey));
+    assert.isTrue(
+      lockupAccount.pendingAuthority.equals(newAuthority.publicKey)
+    );
+
+    // Only the proposed key can accept.
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.state.rpc.acceptAuthority({ accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6051);
+        return true;
+      }
+    );
+
+    await lockup.state.rpc.acceptAuthority({
+      accounts: newAccounts,
+      signers: [newAuthority],
+    });
+    lockupAccount = await lockup.state.fetch();
     assert.isTrue(lockupAccount.authority.equals(newAuthority.publicKey));
+    assert.isTrue(
+      lockupAccount.pendingAuthority.equals(anchor.web3.PublicKey.default)
+    );
 
-    await lockup.state.rpc.setAuthority(provider.wallet.publicKey, {
-      accounts: {
-        authority: newAuthority.publicKey,
-      },
+    // Hand it back, cancelling a proposal along the way.
+    await lockup.state.rpc.proposeAuthority(provider.wallet.publicKey, {
+      accounts: newAccounts,
       signers: [newAuthority],
     });
-
+    await lockup.state.rpc.cancelAuthorityProposal({
+      accounts: newAccounts,
+      signers: [newAuthority],
+    });
+    await lockup.state.rpc.proposeAuthority(provider.wallet.publicKey, {
+      accounts: newAccounts,
+      signers: [newAuthority],
+    });
+    await lockup.state.rpc.acceptAuthority({ accounts });
     lockupAccount = await lockup.state.fetch();
     assert.isTrue(lockupAccount.authority.equals(provider.wallet.publicKey));
   });
 
-  const entries = [];
-
-  it("Adds to the whitelist", async () => {
-    const generateEntry = async () => {
-      let programId = anchor.web3.Keypair.generate().publicKey;
-      return {
-        programId,
-      };
-    };
+  it("Charges the withdrawal fee to the treasury", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5) }
+    );
+    const token = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      provider.wallet.publicKey
+    );
+    const treasury = await serumCmn.createTokenAccount(
+      provider,
+      mint,
+      provider.wallet.publicKey
+    );
+    const authority = { authority: provider.wallet.publicKey };
 
-    for (let k = 0; k < WHITELIST_SIZE; k += 1) {
-      entries.push(await generateEntry());
-    }
+    // 1%.
+    await lockup.state.rpc.setWithdrawFee(100, treasury, {
+      accounts: authority,
+    });
 
     const accounts = {
-      authority: provider.wallet.publicKey,
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      token,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      coBeneficiary: lockup.programId,
+      withdrawDelegate: lockup.programId,
+      lockup: lockupAddress,
+      mint,
+      feeTreasury: lockup.programId,
+      grantor: lockup.programId,
+      withdrawCallbackProgram: lockup.programId,
     };
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdraw(new anchor.BN(100), { accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6058);
+        return true;
+      }
+    );
 
-    await lockup.state.rpc.whitelistAdd(entries[0], { accounts });
-
-    let lockupAccount = await lockup.state.fetch();
-
-    assert.lengthOf(lockupAccount.whitelist, 1);
-    assert.deepEqual(lockupAccount.whitelist, [entries[0]]);
-
-    for (let k = 1; k < WHITELIST_SIZE; k += 1) {
-      await lockup.state.rpc.whitelistAdd(entries[k], { accounts });
-    }
-
-    lockupAccount = await lockup.state.fetch();
+    await lockup.rpc.withdraw(new anchor.BN(100), {
+      accounts: { ...accounts, feeTreasury: treasury },
+    });
+    const tokenAccount = await serumCmn.getTokenAccount(provider, token);
+    assert.isTrue(tokenAccount.amount.eq(new anchor.BN(99)));
+    const treasuryAccount = await serumCmn.getTokenAccount(provider, treasury);
+    assert.isTrue(treasuryAccount.amount.eq(new anchor.BN(1)));
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(0)));
 
-    assert.deepEqual(lockupAccount.whitelist, entries);
+    await lockup.state.rpc.setWithdrawFee(0, anchor.web3.PublicKey.default, {
+      accounts: authority,
+    });
+  });
 
+  it("Fails to set a withdrawal fee above the maximum", async () => {
     await nativeAssert.rejects(
       async () => {
-        const e = await generateEntry();
-        await lockup.state.rpc.whitelistAdd(e, { accounts });
+        await lockup.state.rpc.setWithdrawFee(
+          501,
+          anchor.web3.Keypair.generate().publicKey,
+          { accounts: { authority: provider.wallet.publicKey } }
+        );
       },
       (err) => {
-        assert.strictEqual(err.error.errorCode.number, 6008);
-        assert.strictEqual(err.error.errorMessage, "Whitelist is full");
+        assert.strictEqual(err.error.errorCode.number, 6057);
         return true;
       }
     );
   });
 
-  it("Removes from the whitelist", async () => {
-    await lockup.state.rpc.whitelistDelete(entries[0], {
-      accounts: {
-        authority: provider.wallet.publicKey,
-      },
-    });
+  it("Snapshots the lockup's rounding on new vesting accounts", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
     let lockupAccount = await lockup.state.fetch();
-    assert.deepStrictEqual(lockupAccount.whitelist, entries.slice(1));
-  });
+    assert.deepEqual(lockupAccount.rounding, { down: {} });
 
-  const vesting = anchor.web3.Keypair.generate();
-  let vestingAccount = null;
-  let vestingSigner = null;
+    await lockup.state.rpc.setRounding({ up: {} }, { accounts });
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god);
+    await lockup.state.rpc.setRounding({ down: {} }, { accounts });
 
-  it("Creates a vesting account", async () => {
-    const startTs = new anchor.BN(Date.now() / 1000);
-    const endTs = new anchor.BN(startTs.toNumber() + 5);
-    const periodCount = new anchor.BN(2);
-    const beneficiary = provider.wallet.publicKey;
-    const depositAmount = new anchor.BN(100);
+    // Changing the policy doesn't touch existing schedules.
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.deepEqual(vestingAccount.rounding, { up: {} });
+    lockupAccount = await lockup.state.fetch();
+    assert.deepEqual(lockupAccount.rounding, { down: {} });
+  });
 
-    const vault = anchor.web3.Keypair.generate();
-    let [_vestingSigner, nonce] =
-      await anchor.web3.PublicKey.findProgramAddress(
-        [vesting.publicKey.toBuffer()],
-        lockup.programId
-      );
-    vestingSigner = _vestingSigner;
+  it("Pauses and unpauses vesting account creation", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
+    const { vesting } = await utils.createVesting(lockup, provider, mint, god);
 
-    await lockup.rpc.createVesting(
-      beneficiary,
-      depositAmount,
-      nonce,
-      startTs,
-      endTs,
-      periodCount,
-      null, // Lock realizor is None.
-      {
-        accounts: {
-          vesting: vesting.publicKey,
-          vault: vault.publicKey,
-          depositor: god,
-          depositorAuthority: provider.wallet.publicKey,
-          tokenProgram: TOKEN_PROGRAM_ID,
-          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
-          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+    await lockup.state.rpc.pauseCreation({ accounts });
+    try {
+      await nativeAssert.rejects(
+        async () => {
+          await utils.createVesting(lockup, provider, mint, god);
         },
-        signers: [vesting, vault],
-        instructions: [
-          await lockup.account.vesting.createInstruction(vesting),
-          ...(await serumCmn.createTokenAccountInstrs(
-            provider,
-            vault.publicKey,
-            mint,
-            vestingSigner
-          )),
-        ],
+        (err) => {
+          assert.strictEqual(err.error.errorCode.number, 6083);
+          return true;
+        }
+      );
+      // Existing accounts are untouched.
+      const vestingAccount = await lockup.account.vesting.fetch(vesting);
+      assert.isTrue(vestingAccount.outstanding.gtn(0));
+    } finally {
+      await lockup.state.rpc.unpauseCreation({ accounts });
+    }
+
+    await utils.createVesting(lockup, provider, mint, god);
+    const lockupAccount = await lockup.state.fetch();
+    assert.isFalse(lockupAccount.creationPaused);
+  });
+
+  it("Requires the grantor to approve withdrawals", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000 - 10);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
+      provider,
+      mint,
+      god,
+      { startTs, endTs: startTs.addn(5), requiresGrantorApproval: true }
+    );
+    const accounts = {
+      vesting,
+      beneficiary: provider.wallet.publicKey,
+      token: god,
+      vault,
+      vestingSigner,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+      coBeneficiary: lockup.programId,
+      withdrawDelegate: lockup.programId,
+      lockup: lockupAddress,
+      mint,
+      feeTreasury: lockup.programId,
+      grantor: lockup.programId,
+      withdrawCallbackProgram: lockup.programId,
+    };
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdraw(new anchor.BN(100), { accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6059);
+        assert.strictEqual(
+          err.error.errorMessage,
+          "The grantor must approve withdrawals from this vesting account."
+        );
+        return true;
+      }
+    );
+    await nativeAssert.rejects(
+      async () => {
+        await lockup.rpc.withdrawAll({ accounts });
+      },
+      (err) => {
+        assert.strictEqual(err.error.errorCode.number, 6059);
+        return true;
       }
     );
 
-    vestingAccount = await lockup.account.vesting.fetch(vesting.publicKey);
-
-    assert.isTrue(vestingAccount.beneficiary.equals(provider.wallet.publicKey));
-    assert.isTrue(vestingAccount.mint.equals(mint));
-    assert.isTrue(vestingAccount.grantor.equals(provider.wallet.publicKey));
-    assert.isTrue(vestingAccount.outstanding.eq(depositAmount));
-    assert.isTrue(vestingAccount.startBalance.eq(depositAmount));
-    assert.isTrue(vestingAccount.whitelistOwned.eq(new anchor.BN(0)));
-    assert.strictEqual(vestingAccount.nonce, nonce);
-    assert.isTrue(vestingAccount.createdTs.gt(new anchor.BN(0)));
-    assert.isTrue(vestingAccount.startTs.eq(startTs));
-    assert.isTrue(vestingAccount.endTs.eq(endTs));
-    assert.isNull(vestingAccount.realizor);
+    // The grantor is the provider wallet, which signs.
+    await lockup.rpc.withdraw(new anchor.BN(100), {
+      accounts: { ...accounts, grantor: provider.wallet.publicKey },
+    });
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.requiresGrantorApproval);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(0)));
   });
 
-  it("Fails to withdraw from a vesting account before vesting", async () => {
+  it("Fails to close the lockup while the whitelist has entries", async () => {
     await nativeAssert.rejects(
       async () => {
-        await lockup.rpc.withdraw(new anchor.BN(100), {
+        await lockup.rpc.closeLockup({
           accounts: {
-            vesting: vesting.publicKey,
-            beneficiary: provider.wallet.publicKey,
-            token: god,
-            vault: vestingAccount.vault,
-            vestingSigner: vestingSigner,
-            tokenProgram: TOKEN_PROGRAM_ID,
-            clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+            lockup: lockupAddress,
+            authority: provider.wallet.publicKey,
           },
         });
       },
       (err) => {
-        assert.strictEqual(err.error.errorCode.number, 6007);
+        assert.strictEqual(err.error.errorCode.number, 6061);
         assert.strictEqual(
           err.error.errorMessage,
-          "Insufficient withdrawal balance."
+          "The whitelist must be emptied before closing the lockup."
         );
         return true;
       }
     );
+    assert.isNotNull(await provider.connection.getAccountInfo(lockupAddress));
   });
 
-  it("Waits for a vesting period to pass", async () => {
-    await serumCmn.sleep(10 * 1000);
-  });
-
-  it("Withdraws from the vesting account", async () => {
-    const token = await serumCmn.createTokenAccount(
+  it("Fails to change the terms of an immutable vesting account", async () => {
+    const startTs = new anchor.BN(Date.now() / 1000);
+    const { vesting, vault, vestingSigner } = await utils.createVesting(
+      lockup,
       provider,
       mint,
-      provider.wallet.publicKey
+      god,
+      {
+        startTs,
+        endTs: startTs.addn(60 * 60),
+        revocable: true,
+        accelerable: true,
+        immutable: true,
+      }
     );
+    const isImmutable = (err) => {
+      assert.strictEqual(err.error.errorCode.number, 6063);
+      assert.strictEqual(
+        err.error.errorMessage,
+        "This vesting account's terms can't be changed."
+      );
+      return true;
+    };
 
-    await lockup.rpc.withdraw(new anchor.BN(100), {
-      accounts: {
-        vesting: vesting.publicKey,
-        beneficiary: provider.wallet.publicKey,
-        token,
-        vault: vestingAccount.vault,
-        vestingSigner,
-        tokenProgram: TOKEN_PROGRAM_ID,
-        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
-      },
-    });
-
-    vestingAccount = await lockup.account.vesting.fetch(vesting.publicKey);
-    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(0)));
-
-    const vaultAccount = await serumCmn.getTokenAccount(
-      provider,
-      vestingAccount.vault
-    );
-    assert.isTrue(vaultAccount.amount.eq(new anchor.BN(0)));
+    await nativeAssert.rejects(async () => {
+      await lockup.rpc.revoke({
+        accounts: {
+          vesting,
+          grantor: provider.wallet.publicKey,
+          vault,
+          vestingSigner,
+          token: god,
+          mint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          lockup: lockupAddress,
+          grantorTally: lockup.programId,
+        },
+      });
+    }, isImmutable);
+    await nativeAssert.rejects(async () => {
+      await lockup.rpc.accelerate({
+        accounts: {
+          vesting,
+          grantor: provider.wallet.publicKey,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          lockup: lockupAddress,
+        },
+      });
+    }, isImmutable);
+    const newBeneficiary = anchor.web3.Keypair.generate().publicKey;
+    await nativeAssert.rejects(async () => {
+      await lockup.rpc.setBeneficiary(newBeneficiary, {
+        accounts: { vesting, beneficiary: provider.wallet.publicKey },
+      });
+    }, isImmutable);
 
-    const tokenAccount = await serumCmn.getTokenAccount(provider, token);
-    assert.isTrue(tokenAccount.amount.eq(new anchor.BN(100)));
+    const vestingAccount = await lockup.account.vesting.fetch(vesting);
+    assert.isTrue(vestingAccount.immutable);
+    assert.isTrue(vestingAccount.outstanding.eq(new anchor.BN(100)));
   });
 
   const registrar = anchor.web3.Keypair.generate();
@@ -427,6 +4326,52 @@ describe("Lockup and Registry", () => {
     assert.isTrue(memberVault.amount.eq(depositAmount));
   });
 
+  // Renouncing is irreversible, so this runs after everything else that
+  // needs the whitelist authority.
+  it("Renounces the whitelist authority permanently", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
+    const entry = {
+      programId: anchor.web3.Keypair.generate().publicKey,
+      maxAmount: new anchor.BN(1000),
+      label: utils.whitelistLabel("too-late"),
+      discriminator: Array(8).fill(0),
+    };
+
+    await lockup.state.rpc.renounceAuthority({ accounts });
+    const lockupAccount = await lockup.state.fetch();
+    assert.isTrue(lockupAccount.locked);
+    assert.isTrue(
+      lockupAccount.authority.equals(anchor.web3.PublicKey.default)
+    );
+
+    const isLocked = (err) => {
+      assert.strictEqual(err.error.errorCode.number, 6054);
+      assert.strictEqual(
+        err.error.errorMessage,
+        "The whitelist authority has been renounced."
+      );
+      return true;
+    };
+    await nativeAssert.rejects(async () => {
+      await lockup.state.rpc.whitelistAdd(entry, { accounts });
+    }, isLocked);
+    await nativeAssert.rejects(async () => {
+      await lockup.state.rpc.setAuthority(provider.wallet.publicKey, {
+        accounts,
+      });
+    }, isLocked);
+    await nativeAssert.rejects(async () => {
+      await lockup.rpc.growWhitelist(1, {
+        accounts: {
+          lockup: lockupAddress,
+          authority: provider.wallet.publicKey,
+          payer: provider.wallet.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        },
+      });
+    }, isLocked);
+  });
+
   /*
   it("Stakes to a member (unlocked)", async () => {
     const stakeAmount = new anchor.BN(10);
@@ -673,24 +4618,25 @@ describe("Lockup and Registry", () => {
   let vendoredVestingVault = null;
   let vendoredVestingSigner = null;
 
-  it("Claims a locked reward", async () => {
-    vendoredVesting = anchor.web3.Keypair.generate();
-    vendoredVestingVault = anchor.web3.Keypair.generate();
-    let [_vendoredVestingSigner, nonce] =
-      await anchor.web3.PublicKey.findProgramAddress(
-        [vendoredVesting.publicKey.toBuffer()],
-        lockup.programId
-      );
-    vendoredVestingSigner = _vendoredVestingSigner;
+  // Claims the locked reward into a new vesting account.
+  const claimLockedReward = async () => {
+    const vesting = anchor.web3.Keypair.generate();
+    const vault = anchor.web3.Keypair.generate();
+    const [vestingSigner] = await anchor.web3.PublicKey.findProgramAddress(
+      [vesting.publicKey.toBuffer()],
+      lockup.programId
+    );
     const remainingAccounts = lockup.instruction.createVesting
       .accounts({
-        vesting: vendoredVesting.publicKey,
-        vault: vendoredVestingVault.publicKey,
+        vesting: vesting.publicKey,
+        vault: vault.publicKey,
         depositor: lockedVendorVault.publicKey,
         depositorAuthority: lockedVendorSigner,
         tokenProgram: TOKEN_PROGRAM_ID,
         rent: anchor.web3.SYSVAR_RENT_PUBKEY,
         clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: lockupAddress,
+        mint,
       })
       // Change the signer status on the vendor signer since it's signed by the program, not the
       // client.
@@ -698,7 +4644,7 @@ describe("Lockup and Registry", () => {
         meta.pubkey === lockedVendorSigner ? { ...meta, isSigner: false } : meta
       );
 
-    await registry.rpc.claimRewardLocked(nonce, {
+    await registry.rpc.claimRewardLocked({
       accounts: {
         registry: await registry.state.address(),
         lockupProgram: lockup.programId,
@@ -719,17 +4665,86 @@ describe("Lockup and Registry", () => {
         },
       },
       remainingAccounts,
-      signers: [vendoredVesting, vendoredVestingVault],
+      signers: [vesting, vault],
       instructions: [
-        await lockup.account.vesting.createInstruction(vendoredVesting),
+        await lockup.account.vesting.createInstruction(vesting),
         ...(await serumCmn.createTokenAccountInstrs(
           provider,
-          vendoredVestingVault.publicKey,
+          vault.publicKey,
           mint,
-          vendoredVestingSigner
+          vestingSigner
         )),
       ],
     });
+    return { vesting, vault, vestingSigner };
+  };
+
+  it("Fails to claim a locked reward under a minimum cliff", async () => {
+    // Locked rewards vest from the start, without a cliff.
+    const accounts = { authority: provider.wallet.publicKey };
+    await lockup.state.rpc.setMinCliff(new anchor.BN(1), { accounts });
+    try {
+      await nativeAssert.rejects(
+        async () => {
+          await claimLockedReward();
+        },
+        (err) => {
+          assert.strictEqual(err.error.errorCode.number, 6037);
+          return true;
+        }
+      );
+    } finally {
+      await lockup.state.rpc.setMinCliff(new anchor.BN(0), { accounts });
+    }
+  });
+
+  it("Fails to claim a locked reward under a minimum deposit", async () => {
+    const accounts = { authority: provider.wallet.publicKey };
+    await lockup.state.rpc.setMinDeposit(lockedRewardAmount.addn(1), {
+      accounts,
+    });
+    try {
+      await nativeAssert.rejects(
+        async () => {
+          await claimLockedReward();
+        },
+        (err) => {
+          assert.strictEqual(err.error.errorCode.number, 6085);
+          return true;
+        }
+      );
+    } finally {
+      await lockup.state.rpc.setMinDeposit(new anchor.BN(0), { accounts });
+    }
+  });
+
+  it("Fails to claim a locked reward over a maximum duration", async () => {
+    const { startTs, endTs } = lockedRewardKind.locked;
+    const accounts = { authority: provider.wallet.publicKey };
+    await lockup.state.rpc.setMaxDuration(endTs.sub(startTs).subn(1), {
+      accounts,
+    });
+    try {
+      await nativeAssert.rejects(
+        async () => {
+          await claimLockedReward();
+        },
+        (err) => {
+          assert.strictEqual(err.error.errorCode.number, 6088);
+          return true;
+        }
+      );
+    } finally {
+      await lockup.state.rpc.setMaxDuration(new anchor.BN(0), { accounts });
+    }
+  });
+
+  it("Claims a locked reward", async () => {
+    ({
+      vesting: vendoredVesting,
+      vault: vendoredVestingVault,
+      vestingSigner: vendoredVestingSigner,
+    } = await claimLockedReward());
 
     const lockupAccount = await lockup.account.vesting.fetch(
       vendoredVesting.publicKey
@@ -771,6 +4786,13 @@ describe("Lockup and Registry", () => {
             vestingSigner: vendoredVestingSigner,
             tokenProgram: TOKEN_PROGRAM_ID,
             clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+            coBeneficiary: lockup.programId,
+            withdrawDelegate: lockup.programId,
+            lockup: lockupAddress,
+            mint,
+            feeTreasury: lockup.programId,
+            grantor: lockup.programId,
+            withdrawCallbackProgram: lockup.programId,
           },
           // TODO: trait methods generated on the client. Until then, we need to manually
           //       specify the account metas here.
@@ -1,5 +1,6 @@
 const anchor = require("@coral-xyz/anchor");
 const serumCmn = require("@project-serum/common");
+const { TOKEN_PROGRAM_ID } = require("@solana/spl-token");
 
 async function createBalanceSandbox(provider, r, registrySigner) {
   const spt = anchor.web3.Keypair.generate();
@@ -61,6 +62,118 @@ async function createBalanceSandbox(provider, r, registrySigner) {
   ];
 }
 
+// Creates a vesting account funded by the given depositor, defaulting to a
+// short linear schedule with the provider's wallet as beneficiary.
+async function createVesting(
+  program,
+  provider,
+  mint,
+  depositor,
+  {
+    beneficiary = provider.wallet.publicKey,
+    depositAmount = new anchor.BN(100),
+    startTs = new anchor.BN(Date.now() / 1000),
+    endTs = new anchor.BN(startTs.toNumber() + 5),
+    periodCount = new anchor.BN(2),
+    cliffTs = startTs,
+    revocable = false,
+    accelerable = false,
+    periodRounding = { firstPeriod: {} },
+    minWithdrawInterval = new anchor.BN(0),
+    memo = vestingMemo(""),
+    milestones = [],
+    finalBps = 0,
+    revokeNoticeSeconds = new anchor.BN(0),
+    requiresGrantorApproval = false,
+    immutable = false,
+    scheduleKind = { linear: {} },
+    curveExponent = 0,
+    recoveryAuthority = null,
+    realizor = null,
+    grantorTally = null,
+    vault = anchor.web3.Keypair.generate(),
+    extraInstructions = [],
+  } = {}
+) {
+  const vesting = anchor.web3.Keypair.generate();
+  const [vestingSigner] = await anchor.web3.PublicKey.findProgramAddress(
+    [vesting.publicKey.toBuffer()],
+    program.programId
+  );
+
+  await program.rpc.createVesting(
+    {
+      beneficiary,
+      depositAmount,
+      startTs,
+      endTs,
+      periodCount,
+      cliffTs,
+      revocable,
+      accelerable,
+      periodRounding,
+      minWithdrawInterval,
+      memo,
+      milestones,
+      finalBps,
+      revokeNoticeSeconds,
+      requiresGrantorApproval,
+      immutable,
+      scheduleKind,
+      curveExponent,
+      recoveryAuthority,
+      realizor,
+    },
+    {
+      accounts: {
+        vesting: vesting.publicKey,
+        vault: vault.publicKey,
+        depositor,
+        depositorAuthority: provider.wallet.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        lockup: await program.state.address(),
+        mint,
+        grantorTally: grantorTally || program.programId,
+      },
+      signers: [vesting, vault],
+      instructions: [
+        await program.account.vesting.createInstruction(vesting),
+        ...(await serumCmn.createTokenAccountInstrs(
+          provider,
+          vault.publicKey,
+          mint,
+          vestingSigner
+        )),
+        ...extraInstructions,
+      ],
+    }
+  );
+
+  return {
+    vesting: vesting.publicKey,
+    vault: vault.publicKey,
+    vestingSigner,
+  };
+}
+
+// Encodes a whitelist entry label as a fixed size, zero padded byte array.
+function whitelistLabel(name) {
+  const label = Buffer.alloc(32);
+  label.write(name);
+  return [...label];
+}
+
+// Encodes a vesting memo as a fixed size, zero padded byte array.
+function vestingMemo(memo) {
+  const bytes = Buffer.alloc(64);
+  bytes.write(memo);
+  return [...bytes];
+}
+
 module.exports = {
   createBalanceSandbox,
+  createVesting,
+  whitelistLabel,
+  vestingMemo,
 };